@@ -17,6 +17,7 @@ use tokio::sync::{Notify, RwLock};
 
 #[rocket::get("/rooms/<room_id>/subscribe")]
 async fn subscribe(
+    _auth: Authorized,
     db: &State<Db>,
     mut shutdown: rocket::Shutdown,
     last_seen_msg: LastEventId,
@@ -39,7 +40,7 @@ async fn subscribe(
 }
 
 #[rocket::post("/rooms/<room_id>/issue_unique_idx")]
-async fn issue_idx(db: &State<Db>, room_id: &str) -> Json<IssuedUniqueIdx> {
+async fn issue_idx(_auth: Authorized, db: &State<Db>, room_id: &str) -> Json<IssuedUniqueIdx> {
     let room = db.get_room_or_create_empty(room_id).await;
     let idx = room.issue_unique_idx();
     println!("issued id {idx}");
@@ -47,7 +48,7 @@ async fn issue_idx(db: &State<Db>, room_id: &str) -> Json<IssuedUniqueIdx> {
 }
 
 #[rocket::post("/rooms/<room_id>/broadcast", data = "<message>")]
-async fn broadcast(db: &State<Db>, room_id: &str, message: String) -> Status {
+async fn broadcast(_auth: Authorized, db: &State<Db>, room_id: &str, message: String) -> Status {
     println!("broadcasted {message} into {room_id}");
     let room = db.get_room_or_create_empty(room_id).await;
     room.publish(message).await;
@@ -182,6 +183,42 @@ impl<'r> FromRequest<'r> for LastEventId {
     }
 }
 
+/// Clients must send this as `Authorization: Bearer <token>` when set.
+const TOKEN_ENV: &str = "BOOMERSIG_RELAY_TOKEN";
+
+struct RelayToken(Option<String>);
+
+/// Admits a request carrying the relay's token, or any request when the relay
+/// has none.
+struct Authorized;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorized {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match request.rocket().state::<RelayToken>() {
+            Some(RelayToken(Some(token))) => token,
+            _ => return Outcome::Success(Authorized),
+        };
+        let presented = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+        match presented {
+            Some(token)
+                if bool::from(subtle::ConstantTimeEq::ct_eq(
+                    token.as_bytes(),
+                    expected.as_bytes(),
+                )) =>
+            {
+                Outcome::Success(Authorized)
+            }
+            _ => Outcome::Error((Status::Unauthorized, "missing or wrong relay token")),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct IssuedUniqueIdx {
     unique_idx: u16,
@@ -196,6 +233,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     rocket::custom(figment)
         .mount("/", rocket::routes![subscribe, issue_idx, broadcast])
         .manage(Db::empty())
+        .manage(RelayToken(
+            std::env::var(TOKEN_ENV)
+                .ok()
+                .filter(|token| !token.is_empty()),
+        ))
         .launch()
         .await?;
     Ok(())
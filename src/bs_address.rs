@@ -0,0 +1,374 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_CHECKSIG};
+use bitcoin::blockdata::script::Builder;
+use bitcoin::secp256k1::{Parity, XOnlyPublicKey};
+use bitcoin::{Address, Network, PublicKey, ScriptBuf};
+
+pub trait AddressDeriver {
+    fn derive(&self, pubkey: &PublicKey, network: Network) -> Result<Address>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressType {
+    #[default]
+    P2pkh,
+    P2wpkh,
+    P2shP2wpkh,
+    P2wsh,
+}
+
+impl AddressType {
+    pub const ALL: [AddressType; 4] = [
+        AddressType::P2pkh,
+        AddressType::P2wpkh,
+        AddressType::P2shP2wpkh,
+        AddressType::P2wsh,
+    ];
+
+    pub fn deriver(&self) -> &'static dyn AddressDeriver {
+        match self {
+            AddressType::P2pkh => &P2pkh,
+            AddressType::P2wpkh => &P2wpkh,
+            AddressType::P2shP2wpkh => &P2shP2wpkh,
+            AddressType::P2wsh => &P2wsh,
+        }
+    }
+}
+
+impl FromStr for AddressType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "p2pkh" => Ok(AddressType::P2pkh),
+            "p2wpkh" => Ok(AddressType::P2wpkh),
+            "p2sh-p2wpkh" => Ok(AddressType::P2shP2wpkh),
+            "p2wsh" => Ok(AddressType::P2wsh),
+            _ => Err(anyhow!(
+                "unknown address type {:?}, expected p2pkh, p2wpkh, p2sh-p2wpkh or p2wsh",
+                s
+            )),
+        }
+    }
+}
+
+/// The name `from_str` takes.
+impl fmt::Display for AddressType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AddressType::P2pkh => "p2pkh",
+            AddressType::P2wpkh => "p2wpkh",
+            AddressType::P2shP2wpkh => "p2sh-p2wpkh",
+            AddressType::P2wsh => "p2wsh",
+        })
+    }
+}
+
+/// Parses a hex public key, compressed (33 bytes), uncompressed (65 bytes) or
+/// x-only (32 bytes). X-only keys don't carry their parity and are taken to be
+/// the even one, as BIP340 does.
+pub fn parse_pubkey(hex_key: &str) -> Result<PublicKey> {
+    let bytes = hex::decode(hex_key.trim()).context("public key is not valid hex")?;
+    match bytes.len() {
+        32 => {
+            let x_only = XOnlyPublicKey::from_slice(&bytes).context("invalid x-only public key")?;
+            Ok(PublicKey::new(x_only.public_key(Parity::Even)))
+        }
+        33 | 65 => PublicKey::from_slice(&bytes).context("invalid public key"),
+        len => bail!("public key must be 32, 33 or 65 bytes, got {} bytes", len),
+    }
+}
+
+pub const LEGACY_ONLY_WARNING: &str =
+    "This multisig uses an uncompressed-key legacy address; segwit is not available.";
+
+/// Segwit derivers compress the key themselves, so only a p2pkh address of an
+/// uncompressed key is stuck with the larger legacy encoding.
+pub fn is_legacy_only(pubkey: &PublicKey, address_type: AddressType) -> bool {
+    !pubkey.compressed && address_type == AddressType::P2pkh
+}
+
+pub struct P2pkh;
+pub struct P2wpkh;
+pub struct P2shP2wpkh;
+pub struct P2wsh;
+
+// Segwit only relays compressed keys, so the segwit derivers always use the
+// compressed encoding of the group key.
+fn compressed(pubkey: &PublicKey) -> PublicKey {
+    PublicKey::new(pubkey.inner)
+}
+
+/// The `<pubkey> OP_CHECKSIG` witness script locking a p2wsh output to a single key.
+pub fn single_key_script(pubkey: &PublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_key(&compressed(pubkey))
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Most keys `OP_CHECKMULTISIG` takes.
+const MAX_MULTISIG_KEYS: usize = 20;
+
+/// A classic `OP_m <pubkeys> OP_n OP_CHECKMULTISIG` p2wsh multisig, for users
+/// who want the threshold visible on chain rather than behind one group key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptMultisig {
+    pub required: usize,
+    /// In script order, which is the order the signatures go in.
+    pub keys: Vec<PublicKey>,
+}
+
+impl ScriptMultisig {
+    pub fn new(required: usize, keys: Vec<PublicKey>) -> Result<Self> {
+        if keys.len() > MAX_MULTISIG_KEYS {
+            bail!(
+                "a multisig script takes at most {} keys, got {}",
+                MAX_MULTISIG_KEYS,
+                keys.len()
+            );
+        }
+        if required < 1 || required > keys.len() {
+            bail!(
+                "a multisig script needs between 1 and {} signatures, got {}",
+                keys.len(),
+                required
+            );
+        }
+        Ok(Self {
+            required,
+            keys: keys.iter().map(compressed).collect(),
+        })
+    }
+
+    pub fn witness_script(&self) -> ScriptBuf {
+        let mut builder = Builder::new().push_int(self.required as i64);
+        for key in &self.keys {
+            builder = builder.push_key(key);
+        }
+        builder
+            .push_int(self.keys.len() as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    /// The p2wsh output script, the same on every network.
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_v0_p2wsh(&self.witness_script().wscript_hash())
+    }
+
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.witness_script(), network)
+    }
+
+    /// `wsh(multi(...))` with its BIP380 checksum.
+    pub fn descriptor(&self) -> String {
+        let keys: Vec<String> = self.keys.iter().map(PublicKey::to_string).collect();
+        let body = format!("wsh(multi({},{}))", self.required, keys.join(","));
+        let checksum = descriptor_checksum(&body);
+        format!("{}#{}", body, checksum)
+    }
+}
+
+/// The output descriptor of the key's `address_type` output with its BIP380
+/// checksum, for importing the multisig into a watch-only wallet.
+pub fn descriptor(pubkey: &PublicKey, address_type: AddressType) -> String {
+    let body = match address_type {
+        AddressType::P2pkh => format!("pkh({})", pubkey),
+        AddressType::P2wpkh => format!("wpkh({})", compressed(pubkey)),
+        AddressType::P2shP2wpkh => format!("sh(wpkh({}))", compressed(pubkey)),
+        AddressType::P2wsh => format!("wsh(pk({}))", compressed(pubkey)),
+    };
+    let checksum = descriptor_checksum(&body);
+    format!("{}#{}", body, checksum)
+}
+
+const DESCRIPTOR_INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const DESCRIPTOR_CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn descriptor_polymod(c: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let top = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// BIP380's checksum, as computed by Bitcoin Core's `DescriptorChecksum`. Our
+/// descriptors only ever contain characters of its input charset.
+fn descriptor_checksum(descriptor: &str) -> String {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = DESCRIPTOR_INPUT_CHARSET.find(ch).unwrap_or_default() as u64;
+        c = descriptor_polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = descriptor_polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = descriptor_polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = descriptor_polymod(c, 0);
+    }
+    c ^= 1;
+    (0..8)
+        .map(|j| DESCRIPTOR_CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect()
+}
+
+impl AddressDeriver for P2pkh {
+    fn derive(&self, pubkey: &PublicKey, network: Network) -> Result<Address> {
+        Ok(Address::p2pkh(pubkey, network))
+    }
+}
+
+impl AddressDeriver for P2wpkh {
+    fn derive(&self, pubkey: &PublicKey, network: Network) -> Result<Address> {
+        Ok(Address::p2wpkh(&compressed(pubkey), network)?)
+    }
+}
+
+impl AddressDeriver for P2shP2wpkh {
+    fn derive(&self, pubkey: &PublicKey, network: Network) -> Result<Address> {
+        Ok(Address::p2shwpkh(&compressed(pubkey), network)?)
+    }
+}
+
+impl AddressDeriver for P2wsh {
+    fn derive(&self, pubkey: &PublicKey, network: Network) -> Result<Address> {
+        Ok(Address::p2wsh(&single_key_script(pubkey), network))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // The public key of secret key 1, i.e. the generator point.
+    const GENERATOR: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn derive(address_type: AddressType) -> String {
+        let pubkey = PublicKey::from_str(GENERATOR).unwrap();
+        address_type
+            .deriver()
+            .derive(&pubkey, Network::Bitcoin)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn descriptors_carry_the_bip380_checksum() {
+        // Test vectors from BIP380 and Bitcoin Core's descriptors.md.
+        assert_eq!(descriptor_checksum("raw(deadbeef)"), "89f8spxm");
+        assert_eq!(
+            descriptor_checksum(
+                "pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)"
+            ),
+            "8fhd9pwu"
+        );
+
+        let pubkey = PublicKey::from_str(GENERATOR).unwrap();
+        assert_eq!(
+            descriptor(&pubkey, AddressType::P2wpkh),
+            format!("wpkh({})#ucxz0gak", GENERATOR)
+        );
+        assert_eq!(
+            descriptor(&pubkey, AddressType::P2pkh),
+            format!("pkh({})#e48zzw02", GENERATOR)
+        );
+    }
+
+    #[test]
+    fn multisig_script_lists_keys_in_order() {
+        let two = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let generator = PublicKey::from_str(GENERATOR).unwrap();
+        let keys = vec![
+            PublicKey::new_uncompressed(generator.inner),
+            PublicKey::from_str(two).unwrap(),
+        ];
+        let multisig = ScriptMultisig::new(2, keys.clone()).unwrap();
+
+        assert_eq!(
+            hex::encode(multisig.witness_script().as_bytes()),
+            format!("5221{}21{}52ae", GENERATOR, two)
+        );
+        assert!(multisig
+            .descriptor()
+            .starts_with(&format!("wsh(multi(2,{},{}))#", GENERATOR, two)));
+        assert!(multisig.script_pubkey().is_v0_p2wsh());
+        assert!(ScriptMultisig::new(3, keys.clone()).is_err());
+        assert!(ScriptMultisig::new(0, keys).is_err());
+    }
+
+    #[test]
+    fn derives_every_address_type() {
+        assert_eq!(
+            derive(AddressType::P2pkh),
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"
+        );
+        assert_eq!(
+            derive(AddressType::P2wpkh),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(
+            derive(AddressType::P2shP2wpkh),
+            "3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN"
+        );
+        assert_eq!(
+            derive(AddressType::P2wsh),
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3"
+        );
+    }
+
+    #[test]
+    fn segwit_derivers_compress_the_key() {
+        let compressed = PublicKey::from_str(GENERATOR).unwrap();
+        let uncompressed = PublicKey::new_uncompressed(compressed.inner);
+
+        assert_eq!(
+            P2wpkh.derive(&uncompressed, Network::Bitcoin).unwrap(),
+            P2wpkh.derive(&compressed, Network::Bitcoin).unwrap()
+        );
+        assert_ne!(
+            P2pkh.derive(&uncompressed, Network::Bitcoin).unwrap(),
+            P2pkh.derive(&compressed, Network::Bitcoin).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_pubkey_accepts_every_encoding() {
+        let compressed = PublicKey::from_str(GENERATOR).unwrap();
+        let uncompressed = PublicKey::new_uncompressed(compressed.inner);
+
+        assert_eq!(parse_pubkey(GENERATOR).unwrap(), compressed);
+        assert_eq!(
+            parse_pubkey(&hex::encode(uncompressed.to_bytes())).unwrap(),
+            uncompressed
+        );
+        assert_eq!(parse_pubkey(&GENERATOR[2..]).unwrap(), compressed);
+        assert!(parse_pubkey("02deadbeef").is_err());
+        assert!(parse_pubkey("not hex").is_err());
+    }
+}
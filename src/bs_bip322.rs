@@ -0,0 +1,140 @@
+//! BIP322 proofs of an address: the signer spends a virtual output of the
+//! address committing to the message, with a transaction that can never be
+//! mined, and hands out that spend as the signature.
+
+use std::convert::TryFrom;
+
+use anyhow::{bail, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::blockdata::opcodes::all::{OP_PUSHBYTES_0, OP_RETURN};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::{OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+use sha2::{Digest, Sha256};
+
+use crate::bs_address::AddressType;
+
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// The BIP340 tagged hash of the message, what `to_spend` commits to.
+pub fn message_hash(message: &str) -> [u8; 32] {
+    let tag = Sha256::digest(MESSAGE_TAG);
+    Sha256::new()
+        .chain(tag)
+        .chain(tag)
+        .chain(message.as_bytes())
+        .finalize()
+        .into()
+}
+
+/// The virtual transaction creating the output of `script_pubkey` that the
+/// proof spends.
+pub fn to_spend(script_pubkey: &Script, message: &str) -> Result<Transaction> {
+    let hash = PushBytesBuf::try_from(message_hash(message).to_vec())?;
+    Ok(Transaction {
+        version: 0,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new()
+                .push_opcode(OP_PUSHBYTES_0)
+                .push_slice(hash)
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.to_owned(),
+        }],
+    })
+}
+
+/// The unsigned virtual transaction spending `to_spend`, signed like any PSBT
+/// input of the address.
+pub fn to_sign(script_pubkey: &Script, message: &str) -> Result<PartiallySignedTransaction> {
+    let to_spend = to_spend(script_pubkey, message)?;
+    let tx = Transaction {
+        version: 0,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    };
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)?;
+    if script_pubkey.is_witness_program() {
+        psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+    }
+    psbt.inputs[0].non_witness_utxo = Some(to_spend);
+    Ok(psbt)
+}
+
+/// The base64 signature of a finalized `to_sign`: only its witness for
+/// p2wpkh (the simple format), the whole transaction when the input needs a
+/// scriptSig too (the full format).
+pub fn encode_signature(
+    psbt: &PartiallySignedTransaction,
+    address_type: AddressType,
+) -> Result<String> {
+    let tx = psbt.clone().extract_tx();
+    if tx.input[0].script_sig.is_empty() && tx.input[0].witness.is_empty() {
+        bail!("to_sign is not signed yet");
+    }
+    let encoded = match address_type {
+        AddressType::P2wpkh => bitcoin::consensus::serialize(&tx.input[0].witness),
+        AddressType::P2pkh | AddressType::P2shP2wpkh => bitcoin::consensus::serialize(&tx),
+        AddressType::P2wsh => bail!("BIP322 proofs of a p2wsh address aren't supported"),
+    };
+    Ok(bitcoin::base64::encode(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{Address, Network};
+    use std::str::FromStr;
+
+    // The test vectors of BIP322.
+    #[test]
+    fn virtual_transactions_match_the_bip() {
+        assert_eq!(
+            hex::encode(message_hash("")),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            hex::encode(message_hash("Hello World")),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+            .unwrap()
+            .require_network(Network::Bitcoin)
+            .unwrap();
+        let script_pubkey = address.script_pubkey();
+        for (message, to_spend_txid, to_sign_txid) in [
+            (
+                "",
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                "Hello World",
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ] {
+            let spend = to_spend(&script_pubkey, message).unwrap();
+            assert_eq!(spend.txid().to_string(), to_spend_txid);
+            let sign = to_sign(&script_pubkey, message).unwrap();
+            assert_eq!(sign.unsigned_tx.txid().to_string(), to_sign_txid);
+            assert_eq!(sign.inputs[0].witness_utxo, Some(spend.output[0].clone()));
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::Txid;
+use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
+
+use crate::bs_error::BoomerError;
+
+/// The JSON-RPC interface of a bitcoind of our own, to broadcast without
+/// telling a public explorer about the transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BitcoindRpc {
+    /// e.g. `http://127.0.0.1:38332` for signet.
+    pub url: String,
+    /// The `.cookie` bitcoind writes to its data directory. Read again for
+    /// every broadcast, it changes when bitcoind restarts. Wins over `user`
+    /// and `password`.
+    pub cookie_file: Option<PathBuf>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+impl BitcoindRpc {
+    fn credentials(&self) -> Result<(String, String)> {
+        match (&self.cookie_file, &self.user, &self.password) {
+            (Some(path), _, _) => {
+                let cookie = std::fs::read_to_string(path)
+                    .context(format!("cannot read bitcoind cookie {}", path.display()))?;
+                parse_cookie(&cookie).context(format!("invalid bitcoind cookie {}", path.display()))
+            }
+            (None, Some(user), Some(password)) => Ok((user.clone(), password.clone())),
+            _ => bail!("bitcoind_rpc needs a cookie_file, or a user and a password"),
+        }
+    }
+}
+
+/// `user:password`, as in a bitcoind cookie.
+fn parse_cookie(cookie: &str) -> Result<(String, String)> {
+    match cookie.trim().split_once(':') {
+        Some((user, password)) => Ok((user.to_string(), password.to_string())),
+        None => bail!("expected user:password"),
+    }
+}
+
+/// Where signed transactions are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Broadcaster {
+    /// An esplora API, by its base URL.
+    Esplora(String),
+    Bitcoind(BitcoindRpc),
+}
+
+#[derive(Debug)]
+pub struct BroadcastConfig {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            retries: 3,
+        }
+    }
+}
+
+/// Sends the raw transaction hex to `broadcaster`, returning the accepted
+/// txid. Broadcasting the same transaction twice is harmless, so requests that
+/// failed or timed out are simply sent again; a rejection is final.
+pub async fn broadcast_raw_transaction(
+    tx: &str,
+    broadcaster: &Broadcaster,
+    config: &BroadcastConfig,
+    mut on_retry: impl FnMut(u32),
+) -> Result<Txid> {
+    let mut attempt = 0;
+    loop {
+        let sent = match broadcaster {
+            Broadcaster::Esplora(esplora) => {
+                timeout(config.timeout, post_transaction(esplora, tx)).await
+            }
+            Broadcaster::Bitcoind(rpc) => {
+                timeout(config.timeout, send_raw_transaction(rpc, tx)).await
+            }
+        };
+        match sent {
+            Ok(Ok(result)) => return result,
+            Err(_) if attempt >= config.retries => {
+                return Err(BoomerError::BroadcastTimeout {
+                    attempts: attempt + 1,
+                    timeout: config.timeout,
+                }
+                .into())
+            }
+            Ok(Err(e)) if attempt >= config.retries => return Err(e),
+            _ => {
+                attempt += 1;
+                on_retry(attempt);
+            }
+        }
+    }
+}
+
+/// One broadcast request. The inner error is esplora rejecting the
+/// transaction, with the reason it gave.
+async fn post_transaction(esplora: &str, tx: &str) -> Result<Result<Txid>> {
+    let mut response = surf::post(format!("{}/tx", esplora))
+        .body(tx)
+        .await
+        .map_err(|e| e.into_inner())?;
+    let body = response.body_string().await.map_err(|e| e.into_inner())?;
+    if !response.status().is_success() {
+        return Ok(Err(anyhow!(
+            "esplora rejected the transaction ({}): {}",
+            response.status(),
+            body.trim()
+        )));
+    }
+    Ok(Txid::from_str(body.trim())
+        .context(format!("unexpected broadcast response {:?}", body.trim())))
+}
+
+/// `post_transaction` for bitcoind's `sendrawtransaction`. Credentials that
+/// can't be read or are refused are as final as bitcoind rejecting the
+/// transaction.
+async fn send_raw_transaction(rpc: &BitcoindRpc, tx: &str) -> Result<Result<Txid>> {
+    let (user, password) = match rpc.credentials() {
+        Ok(credentials) => credentials,
+        Err(e) => return Ok(Err(e)),
+    };
+    let auth = surf::http::auth::BasicAuth::new(user, password);
+    let request = serde_json::json!({
+        "jsonrpc": "1.0",
+        "id": "boomersig",
+        "method": "sendrawtransaction",
+        "params": [tx],
+    });
+    let mut response = surf::post(&rpc.url)
+        .header(auth.name(), auth.value())
+        .body(request.to_string())
+        .await
+        .map_err(|e| e.into_inner())?;
+    let body = response.body_string().await.map_err(|e| e.into_inner())?;
+    if response.status() == surf::StatusCode::Unauthorized {
+        return Ok(Err(anyhow!("bitcoind rejected the RPC credentials")));
+    }
+    Ok(rpc_txid(u16::from(response.status()), &body))
+}
+
+/// The txid of a JSON-RPC `sendrawtransaction` reply, or bitcoind's error
+/// word for word, e.g. `min relay fee not met`.
+fn rpc_txid(status: u16, body: &str) -> Result<Txid> {
+    #[derive(Deserialize)]
+    struct Reply {
+        result: Option<String>,
+        error: Option<RpcError>,
+    }
+    #[derive(Deserialize)]
+    struct RpcError {
+        code: i64,
+        message: String,
+    }
+
+    let unexpected = || {
+        format!(
+            "unexpected bitcoind response (HTTP {}): {:?}",
+            status,
+            body.trim()
+        )
+    };
+    let reply: Reply = serde_json::from_str(body).with_context(unexpected)?;
+    match (reply.error, reply.result) {
+        (Some(error), _) => Err(anyhow!(
+            "bitcoind rejected the transaction ({}): {}",
+            error.code,
+            error.message
+        )),
+        (None, Some(txid)) => Txid::from_str(&txid).with_context(unexpected),
+        (None, None) => Err(anyhow!(unexpected())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitcoind_replies_give_the_txid_or_the_rejection_verbatim() {
+        let txid = "4d3c2b1a".repeat(8);
+        let accepted = format!(r#"{{"result":"{}","error":null,"id":"boomersig"}}"#, txid);
+        assert_eq!(rpc_txid(200, &accepted).unwrap().to_string(), txid);
+
+        let rejected = r#"{"result":null,"error":{"code":-26,"message":"min relay fee not met, 100 < 141"},"id":"boomersig"}"#;
+        assert_eq!(
+            rpc_txid(500, rejected).unwrap_err().to_string(),
+            "bitcoind rejected the transaction (-26): min relay fee not met, 100 < 141"
+        );
+
+        assert!(rpc_txid(403, "").is_err());
+        assert_eq!(
+            parse_cookie("__cookie__:5e2f\n").unwrap(),
+            ("__cookie__".to_string(), "5e2f".to_string())
+        );
+        assert!(parse_cookie("no separator").is_err());
+    }
+}
@@ -0,0 +1,542 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use bitcoin::sighash::EcdsaSighashType;
+use bitcoin::Network;
+use serde_json::json;
+use structopt::StructOpt;
+
+use crate::bs_address::{descriptor, parse_pubkey, AddressType};
+use crate::bs_broadcast::broadcast_raw_transaction;
+use crate::bs_config::{config_path, load_config, Config};
+use crate::bs_encryption::env_passphrase;
+use crate::bs_error::{error_category, BoomerError, ErrorCategory};
+use crate::bs_fee::parse_fee_rate;
+use crate::bs_keygen::{do_keygen, preflight, KeygenConfig};
+use crate::bs_progress::ProgressHandle;
+use crate::bs_rbf::bump_fee;
+use crate::bs_signing::{
+    do_sign, parse_psbt, parse_sighash_type, signing_room, HashScheme, InputSelection,
+    SigningConfig,
+};
+use crate::bs_verify::verify_tx_input;
+
+/// Exit code for failures that are neither a `BoomerError` nor a `SigningError`.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Prints the error to stderr, keeping stdout for JSON results, and exits with
+/// the code of the error's category.
+pub fn exit_with_error(error: anyhow::Error) -> ! {
+    eprintln!("error: {:#}", error);
+    let code = error_category(&error)
+        .map(|category| category.exit_code())
+        .unwrap_or(EXIT_FAILURE);
+    std::process::exit(code)
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "boomersig", about = "Threshold ECDSA bitcoin wallet")]
+pub struct Cli {
+    /// Runs a headless command instead of the interactive UI.
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+    /// Skips the validation of an https relay's certificate, for self-signed
+    /// development relays only.
+    #[structopt(long, global = true)]
+    pub insecure: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Derives the address of a group public key, no share needed.
+    AddressFromPubkey {
+        /// Hex public key, compressed, uncompressed or x-only.
+        pubkey: String,
+        #[structopt(long, default_value = "signet")]
+        network: Network,
+        /// One of p2pkh, p2wpkh, p2sh-p2wpkh or p2wsh.
+        #[structopt(long = "type", default_value = "p2pkh")]
+        address_type: AddressType,
+    },
+    /// Runs a keygen ceremony and writes our share, with the relay options and
+    /// network from the saved config.
+    Keygen {
+        /// Our participant index, from 1 to `--parties`.
+        #[structopt(long)]
+        index: u16,
+        #[structopt(long)]
+        threshold: u16,
+        /// Number of parties taking part.
+        #[structopt(long)]
+        parties: u16,
+        /// Must be the same for every party.
+        #[structopt(long, default_value = "default-keygen")]
+        room: String,
+        /// Relay to use instead of the saved one.
+        #[structopt(long)]
+        server: Option<surf::Url>,
+        /// Where to write the share, `local-share<index>.json` in the data
+        /// directory by default.
+        #[structopt(long, parse(from_os_str))]
+        out: Option<PathBuf>,
+        /// Signs a test message once the shares are written, every party must
+        /// pass it.
+        #[structopt(long)]
+        test_signature: bool,
+        /// Only runs the local readiness checks and prints them, without
+        /// contacting the relay. Exits with 2 if any fails.
+        #[structopt(long)]
+        check: bool,
+    },
+    /// Signs a PSBT with the relay from the saved config, in the same room the
+    /// Sign screen uses.
+    Sign {
+        /// PSBT, in base64 or hex.
+        #[structopt(required_unless = "psbt-file")]
+        psbt: Option<String>,
+        /// Reads the PSBT from this file instead, `-` for stdin.
+        #[structopt(long, parse(from_os_str), conflicts_with = "psbt")]
+        psbt_file: Option<PathBuf>,
+        /// Our participant index.
+        #[structopt(long)]
+        index: u16,
+        /// Our share, `local-share<index>.json` in the data directory by
+        /// default.
+        #[structopt(long, parse(from_os_str))]
+        share: Option<PathBuf>,
+        /// Participant indexes of every signer, ours included, e.g. `1,3`.
+        #[structopt(long, use_delimiter = true)]
+        parties: Vec<u16>,
+        /// Must be the same for every signer, bump it to retry in a fresh room.
+        #[structopt(long, default_value = "0")]
+        attempt: u8,
+        /// Signs only this input instead of every input spending the group key.
+        #[structopt(long)]
+        input: Option<usize>,
+        /// Saves the completed offline stage here, for `--resume-offline`.
+        #[structopt(long, parse(from_os_str))]
+        save_offline: Option<PathBuf>,
+        /// Skips the offline stage, reusing one saved for this same PSBT and
+        /// parties by `--save-offline`.
+        #[structopt(long, parse(from_os_str), conflicts_with = "save-offline")]
+        resume_offline: Option<PathBuf>,
+        /// Address type reported in the result: p2pkh, p2wpkh or p2sh-p2wpkh.
+        /// Each input is signed as the type of the output it spends.
+        #[structopt(long = "type", default_value = "p2pkh")]
+        address_type: AddressType,
+        /// Sighash type of our signatures, e.g. `none` or
+        /// `single|anyonecanpay`. Must be the one the PSBT asks for, if any.
+        #[structopt(long, default_value = "all", parse(try_from_str = parse_sighash_type))]
+        sighash: EcdsaSighashType,
+        /// Broadcasts the transaction once it is fully signed.
+        #[structopt(long)]
+        broadcast: bool,
+        /// Prints only the signed transaction hex instead of JSON, failing if
+        /// the transaction still needs other signers.
+        #[structopt(long)]
+        hex: bool,
+        /// Makes every input signal replace-by-fee before signing, as `rbf`
+        /// in the config does. Every signer must pass it.
+        #[structopt(long)]
+        rbf: bool,
+    },
+    /// Proves control of the group address by signing a message as BIP322,
+    /// in a room of its own for the message and signers.
+    SignMessage {
+        message: String,
+        /// Our participant index.
+        #[structopt(long)]
+        index: u16,
+        /// Our share, `local-share<index>.json` in the data directory by
+        /// default.
+        #[structopt(long, parse(from_os_str))]
+        share: Option<PathBuf>,
+        /// Participant indexes of every signer, ours included, e.g. `1,3`.
+        #[structopt(long, use_delimiter = true)]
+        parties: Vec<u16>,
+        /// Must be the same for every signer, bump it to retry in a fresh room.
+        #[structopt(long, default_value = "0")]
+        attempt: u8,
+        /// Type of the address to prove: p2pkh, p2wpkh or p2sh-p2wpkh.
+        #[structopt(long = "type", default_value = "p2wpkh")]
+        address_type: AddressType,
+    },
+    /// Builds a replacement of a stuck replace-by-fee transaction paying a
+    /// higher fee, to be signed again like any PSBT.
+    Bump {
+        /// The PSBT of the original transaction, signed or not, in base64 or
+        /// hex.
+        #[structopt(required_unless = "psbt-file")]
+        psbt: Option<String>,
+        /// Reads the PSBT from this file instead, `-` for stdin.
+        #[structopt(long, parse(from_os_str), conflicts_with = "psbt")]
+        psbt_file: Option<PathBuf>,
+        /// Fee rate of the replacement, in sat/vB, at most 10000.
+        #[structopt(long, parse(try_from_str = parse_fee_rate))]
+        fee_rate: u64,
+    },
+    /// Checks the signature of one input of a signed transaction against a
+    /// public key, no share or ceremony needed.
+    Verify {
+        /// Raw transaction hex.
+        #[structopt(long)]
+        tx: String,
+        /// Hex public key the input must be signed by, e.g. the group key.
+        #[structopt(long)]
+        pubkey: String,
+        #[structopt(long, default_value = "0")]
+        input: usize,
+        /// Sats spent by the input, needed for segwit inputs whose sighash
+        /// commits to them.
+        #[structopt(long)]
+        amount: Option<u64>,
+    },
+}
+
+/// Runs the keygen preflight, which never touches the network, as JSON with
+/// each check's PASS or FAIL, and whether all of them passed.
+fn check_report(keygen: &KeygenConfig) -> (serde_json::Value, bool) {
+    let checks = preflight(keygen);
+    let passed = checks.iter().all(|check| check.result.is_ok());
+    let checks: Vec<serde_json::Value> = checks
+        .iter()
+        .map(|check| match &check.result {
+            Ok(()) => json!({ "check": check.name, "result": "PASS" }),
+            Err(e) => json!({ "check": check.name, "result": "FAIL", "error": e }),
+        })
+        .collect();
+    (json!({ "checks": checks, "passed": passed }), passed)
+}
+
+/// The PSBT given on the command line, in `file`, or on stdin for `-`.
+fn read_psbt(psbt: Option<String>, file: Option<PathBuf>) -> Result<String> {
+    let psbt = match (psbt, file) {
+        (Some(psbt), _) => psbt,
+        (None, Some(file)) if file.as_os_str() == "-" => {
+            let mut psbt = String::new();
+            std::io::stdin()
+                .read_to_string(&mut psbt)
+                .context("cannot read psbt from stdin")?;
+            psbt
+        }
+        (None, Some(file)) => std::fs::read_to_string(&file)
+            .context(format!("cannot read psbt file {}", file.display()))?,
+        (None, None) => bail!("no psbt given"),
+    };
+    Ok(psbt.trim().to_string())
+}
+
+/// The saved config, with the relay options of this run.
+fn load_run_config(insecure: bool) -> Result<Config> {
+    let mut config = load_config(&config_path())?;
+    config.relay_insecure = insecure;
+    Ok(config)
+}
+
+pub fn run(command: Command, insecure: bool) -> Result<()> {
+    match command {
+        Command::AddressFromPubkey {
+            pubkey,
+            network,
+            address_type,
+        } => {
+            let pubkey = parse_pubkey(&pubkey)?;
+            let address = address_type.deriver().derive(&pubkey, network)?;
+            println!(
+                "{}",
+                json!({
+                    "pubkey": hex::encode(pubkey.to_bytes()),
+                    "network": network.to_string(),
+                    "address": address.to_string(),
+                    "descriptor": descriptor(&pubkey, address_type),
+                })
+            );
+        }
+        Command::Keygen {
+            index,
+            threshold,
+            parties,
+            room,
+            server,
+            out,
+            test_signature,
+            check,
+        } => {
+            let config = load_run_config(insecure)?;
+            let keygen = KeygenConfig {
+                address: match server {
+                    Some(server) => server,
+                    None => config.relay_url()?,
+                },
+                relay_options: config.relay_options()?,
+                room,
+                output: out.unwrap_or_else(|| config.share_path(index)),
+                index,
+                threshold,
+                number_of_parties: parties,
+                test_signature,
+                progress: ProgressHandle::default(),
+                network: config.network,
+                passphrase: env_passphrase(),
+                script_multisig: config.script_multisig,
+                timeouts: config.phase_timeouts(),
+                events: None,
+            };
+            if check {
+                let (report, passed) = check_report(&keygen);
+                println!("{}", report);
+                if !passed {
+                    std::process::exit(ErrorCategory::Config.exit_code());
+                }
+                return Ok(());
+            }
+            let failed: Vec<String> = preflight(&keygen)
+                .into_iter()
+                .filter_map(|check| check.result.err().map(|e| format!("{}: {}", check.name, e)))
+                .collect();
+            if !failed.is_empty() {
+                bail!("keygen preflight failed: {}", failed.join("; "));
+            }
+
+            let result = tokio::runtime::Runtime::new()?.block_on(do_keygen(keygen))?;
+            println!(
+                "{}",
+                json!({
+                    "pubkey": result.pubkey,
+                    "address": result.address,
+                    "descriptor": result.descriptor(AddressType::default())?,
+                    "share": result.out_dir,
+                    "tested": result.tested,
+                    "threshold": result.threshold,
+                    "parties": result.parties,
+                    "party_pubkeys": result.party_pubkeys,
+                    "vss_commitments": result.vss_commitments,
+                    "multisig_address": result.multisig_address,
+                    "multisig_descriptor": result.multisig_descriptor,
+                })
+            );
+        }
+        Command::Sign {
+            psbt,
+            psbt_file,
+            index,
+            share,
+            parties,
+            attempt,
+            input,
+            save_offline,
+            resume_offline,
+            address_type,
+            sighash,
+            broadcast,
+            hex,
+            rbf,
+        } => {
+            let psbt = read_psbt(psbt, psbt_file)?;
+            let config = load_run_config(insecure)?;
+            let args = SigningConfig {
+                address: config.relay_url()?,
+                relay_options: config.relay_options()?,
+                room: signing_room(&psbt, &parties, attempt, rbf || config.rbf),
+                local_share: share.unwrap_or_else(|| config.share_path(index)),
+                parties,
+                data_to_sign: psbt,
+                transaction: true,
+                idx: index,
+                address_type,
+                network: config.network,
+                hash_scheme: HashScheme::default(),
+                sighash_type: sighash,
+                progress: ProgressHandle::default(),
+                save_offline_stage: save_offline,
+                resume_offline_stage: resume_offline,
+                inputs: input.map_or(InputSelection::All, InputSelection::Single),
+                passphrase: env_passphrase(),
+                script_multisig: config.script_multisig,
+                timeouts: config.phase_timeouts(),
+                // Nothing is broadcast without `--broadcast`.
+                dry_run: !broadcast,
+                rbf: rbf || config.rbf,
+                bip322: false,
+            };
+            let runtime = tokio::runtime::Runtime::new()?;
+            let mut result = runtime.block_on(do_sign(args))?;
+            if (broadcast || hex) && result.signined_tx.is_none() {
+                bail!(
+                    "transaction still needs other signers, pass the psbt on: {}",
+                    result.partially_signed_psbt.unwrap_or_default()
+                );
+            }
+            if let (Some(tx), true) = (&result.signined_tx, broadcast) {
+                // The signing is done either way, keep the transaction in
+                // the error so it can be broadcast by other means.
+                let txid = runtime
+                    .block_on(broadcast_raw_transaction(
+                        tx,
+                        &config.broadcaster()?,
+                        &config.broadcast_config(),
+                        |attempt| eprintln!("broadcast failed, retry {}", attempt),
+                    ))
+                    .context(format!("signed transaction {} not broadcast", tx))?;
+                result.broadcast_txid = Some(txid.to_string());
+            }
+
+            if hex {
+                println!("{}", result.signined_tx.unwrap_or_default());
+            } else {
+                println!(
+                    "{}",
+                    json!({
+                        "pubkey": result.pubkey,
+                        "address": result.address,
+                        "signers": result.parties,
+                        "signed_inputs": result.signed_inputs,
+                        "signed_tx": result.signined_tx,
+                        "psbt": result.partially_signed_psbt,
+                        "txid": result.broadcast_txid,
+                    })
+                );
+            }
+        }
+        Command::SignMessage {
+            message,
+            index,
+            share,
+            parties,
+            attempt,
+            address_type,
+        } => {
+            let config = load_run_config(insecure)?;
+            let args = SigningConfig {
+                address: config.relay_url()?,
+                relay_options: config.relay_options()?,
+                room: signing_room(&message, &parties, attempt, false),
+                local_share: share.unwrap_or_else(|| config.share_path(index)),
+                parties,
+                data_to_sign: message,
+                transaction: false,
+                idx: index,
+                address_type,
+                network: config.network,
+                hash_scheme: HashScheme::default(),
+                sighash_type: EcdsaSighashType::All,
+                progress: ProgressHandle::default(),
+                save_offline_stage: None,
+                resume_offline_stage: None,
+                inputs: InputSelection::All,
+                passphrase: env_passphrase(),
+                script_multisig: false,
+                timeouts: config.phase_timeouts(),
+                dry_run: true,
+                rbf: false,
+                bip322: true,
+            };
+            let result = tokio::runtime::Runtime::new()?.block_on(do_sign(args))?;
+            println!(
+                "{}",
+                json!({
+                    "pubkey": result.pubkey,
+                    "address": result.address,
+                    "signers": result.parties,
+                    "signature": result.bip322_signature,
+                })
+            );
+        }
+        Command::Bump {
+            psbt,
+            psbt_file,
+            fee_rate,
+        } => {
+            let original = parse_psbt(&read_psbt(psbt, psbt_file)?)?;
+            let bump = bump_fee(&original, fee_rate)?;
+            println!(
+                "{}",
+                json!({
+                    "psbt": bump.psbt.to_string(),
+                    "txid": bump.psbt.unsigned_tx.txid().to_string(),
+                    "replaces": original.unsigned_tx.txid().to_string(),
+                    "original_fee": bump.original_fee,
+                    "fee": bump.fee,
+                })
+            );
+        }
+        Command::Verify {
+            tx,
+            pubkey,
+            input,
+            amount,
+        } => {
+            let bytes = hex::decode(tx.trim()).context("transaction is not valid hex")?;
+            let tx: bitcoin::Transaction =
+                bitcoin::consensus::encode::deserialize(&bytes).context("parse transaction")?;
+            let pubkey = parse_pubkey(&pubkey)?;
+            let checked = verify_tx_input(&tx, input, &pubkey, amount)?;
+            println!(
+                "{}",
+                json!({
+                    "txid": tx.txid().to_string(),
+                    "input": input,
+                    "type": checked.address_type.to_string(),
+                    "sighash": checked.signature.hash_ty.to_string(),
+                    "valid": checked.valid,
+                    "signing_key": hex::encode(checked.signing_key.to_bytes()),
+                    "recovered_pubkey": hex::encode(checked.recovered_key.to_bytes()),
+                })
+            );
+            if !checked.valid {
+                return Err(BoomerError::InvalidSignature {
+                    reason: format!("input {} is not signed by {}", input, pubkey),
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_client::RelayOptions;
+    use crate::bs_signing::PhaseTimeouts;
+    use std::net::TcpListener;
+
+    #[test]
+    fn keygen_check_never_contacts_the_relay() {
+        let relay = TcpListener::bind("127.0.0.1:0").unwrap();
+        relay.set_nonblocking(true).unwrap();
+        let keygen = KeygenConfig {
+            address: format!("http://{}/", relay.local_addr().unwrap())
+                .parse()
+                .unwrap(),
+            relay_options: RelayOptions::default(),
+            room: "default-keygen".into(),
+            output: std::env::temp_dir()
+                .join(format!("boomersig-check-{}.json", std::process::id())),
+            index: 1,
+            threshold: 1,
+            number_of_parties: 3,
+            test_signature: false,
+            progress: ProgressHandle::default(),
+            network: Network::Signet,
+            passphrase: None,
+            script_multisig: false,
+            timeouts: PhaseTimeouts::default(),
+            events: None,
+        };
+
+        let (report, passed) = check_report(&keygen);
+        assert!(passed, "{}", report);
+        assert_eq!(report["checks"][0]["result"], "PASS");
+        let (report, passed) = check_report(&KeygenConfig {
+            threshold: 3,
+            ..keygen
+        });
+        assert!(!passed);
+        assert_eq!(report["checks"][0]["result"], "FAIL");
+
+        let accepted = relay.accept().map_err(|e| e.kind());
+        assert_eq!(accepted.unwrap_err(), std::io::ErrorKind::WouldBlock);
+    }
+}
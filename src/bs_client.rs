@@ -1,15 +1,26 @@
 use std::convert::TryInto;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
-use futures::{Sink, Stream, StreamExt, TryStreamExt};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::stream::BoxStream;
+use futures::{Sink, SinkExt, Stream, StreamExt, TryStreamExt};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Digest;
 use structopt::StructOpt;
+use tracing::Instrument;
 
 use round_based::Msg;
 
+use crate::bs_error::BoomerError;
+use crate::bs_progress::{Activity, Progress, ProgressHandle};
+
+#[tracing::instrument(skip(address, options), fields(party = tracing::field::Empty), err)]
 pub async fn join_computation<M>(
     address: surf::Url,
     room_id: &str,
+    options: &RelayOptions,
 ) -> Result<(
     u16,
     impl Stream<Item = Result<Msg<M>>>,
@@ -18,30 +29,40 @@ pub async fn join_computation<M>(
 where
     M: Serialize + DeserializeOwned,
 {
-    let client = SmClient::new(address, room_id).context("construct SmClient")?;
+    let client = SmClient::new(address, room_id, options).context("construct SmClient")?;
 
     // Construct channel of incoming messages
-    let incoming = client
-        .subscribe()
-        .await
-        .context("subscribe")?
-        .and_then(|msg| async move {
-            serde_json::from_str::<Msg<M>>(&msg).context("deserialize message")
-        });
+    let events = client.subscribe_from(None).await.context("subscribe")?;
+    let incoming = match options.reconnect {
+        Some(max_backoff) => Subscription::new(client.clone(), max_backoff, events).into_stream(),
+        None => events.map_ok(|(_, msg)| msg).boxed(),
+    };
+    let incoming = incoming.and_then(|msg| async move {
+        serde_json::from_str::<Msg<M>>(&msg).context("deserialize message")
+    });
 
     // Obtain party index
     let index = client.issue_index().await.context("issue an index")?;
+    tracing::Span::current().record("party", index);
+    tracing::info!("joined the room");
 
     // Ignore incoming messages addressed to someone else
     let incoming = incoming.try_filter(move |msg| {
-        futures::future::ready(
-            msg.sender != index && (msg.receiver.is_none() || msg.receiver == Some(index)),
-        )
+        let ours = msg.sender != index && (msg.receiver.is_none() || msg.receiver == Some(index));
+        if ours {
+            tracing::debug!(sender = msg.sender, receiver = ?msg.receiver, "received message");
+        }
+        futures::future::ready(ours)
     });
 
     // Construct channel of outgoing messages
     let outgoing = futures::sink::unfold(client, |client, message: Msg<M>| async move {
         let serialized = serde_json::to_string(&message).context("serialize message")?;
+        tracing::debug!(
+            sender = message.sender,
+            receiver = ?message.receiver,
+            "sending message"
+        );
         client
             .broadcast(&serialized)
             .await
@@ -52,59 +73,404 @@ where
     Ok((index, incoming, outgoing))
 }
 
+/// Announces a party in a ceremony's lobby.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    pub party: u16,
+}
+
+// How often the lobby checks whether it was asked to start without everyone.
+const LOBBY_POLL: Duration = Duration::from_millis(200);
+
+/// Waits in the lobby of `room` until every `expected` party announced itself
+/// there, or until `progress` is asked to start now, reporting who is present
+/// as they arrive. The relay replays earlier announcements, so parties can
+/// arrive in any order.
+pub async fn wait_in_lobby(
+    address: surf::Url,
+    room: &str,
+    options: &RelayOptions,
+    party: u16,
+    expected: &[u16],
+    parties: u16,
+    progress: &ProgressHandle,
+) -> Result<()> {
+    let (i, incoming, outgoing) =
+        join_computation::<Presence>(address, &format!("{}-lobby", room), options)
+            .await
+            .context("join lobby")?;
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+    outgoing
+        .send(Msg {
+            sender: i,
+            receiver: None,
+            body: Presence { party },
+        })
+        .await?;
+
+    // Only a request made while in this lobby counts.
+    progress.take_start_now();
+    let mut present = vec![party];
+    loop {
+        progress.set(Progress {
+            stage: "lobby",
+            round: 0,
+            total_rounds: None,
+            activity: Activity::Lobby {
+                present: present.clone(),
+                expected: expected.to_vec(),
+                parties,
+            },
+        });
+        if expected.iter().all(|party| present.contains(party)) || progress.take_start_now() {
+            return Ok(());
+        }
+        tokio::select! {
+            msg = incoming.next() => match msg {
+                Some(msg) => {
+                    let party = msg?.body.party;
+                    if !present.contains(&party) {
+                        present.push(party);
+                        present.sort_unstable();
+                    }
+                }
+                None => bail!("relay closed the lobby"),
+            },
+            _ = tokio::time::sleep(LOBBY_POLL) => {}
+        }
+    }
+}
+
+/// Appends the attempt nonce to a room name so that every fresh attempt of a
+/// ceremony starts in a clean room, free of messages left by aborted attempts.
+pub fn attempt_room(room: &str, attempt: u8) -> String {
+    format!("{}-attempt{}", room, attempt)
+}
+
+/// How to connect to the SM-manager, on top of its address.
+#[derive(Debug, Clone, Default)]
+pub struct RelayOptions {
+    /// SHA-256 of the relay's leaf certificate. When set, only that exact
+    /// certificate is accepted, which also allows self-signed relays.
+    pub cert_fingerprint: Option<[u8; 32]>,
+    /// Accepts any certificate, for self-signed development relays. Anyone
+    /// on the path can then read and alter the ceremony's messages.
+    pub insecure: bool,
+    /// Sent as `Authorization: Bearer` with every request, for relays shared
+    /// by several groups.
+    pub auth_token: Option<String>,
+    /// Subscribes again when the relay connection drops, waiting at most this
+    /// long between tries. Off when unset, a drop then fails the ceremony.
+    pub reconnect: Option<Duration>,
+}
+
+/// Failed reconnects in a row before the subscription gives up.
+const RECONNECT_ATTEMPTS: u32 = 8;
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Doubles from `FIRST_BACKOFF` with every failure in a row, up to `max`.
+fn backoff(failures: u32, max: Duration) -> Duration {
+    FIRST_BACKOFF
+        .checked_mul(1 << failures.saturating_sub(1).min(16))
+        .map_or(max, |delay| delay.min(max))
+}
+
+/// Relay messages with their event ids.
+type Events = BoxStream<'static, Result<(Option<u16>, String)>>;
+
+/// A room subscription that survives dropped connections. It subscribes again
+/// with the id of the last message received, which the relay answers by
+/// replaying every message after it.
+struct Subscription {
+    client: SmClient,
+    max_backoff: Duration,
+    last_event: Option<u16>,
+    events: Option<Events>,
+    done: bool,
+}
+
+impl Subscription {
+    fn new(client: SmClient, max_backoff: Duration, events: Events) -> Self {
+        Self {
+            client,
+            max_backoff,
+            last_event: None,
+            events: Some(events),
+            done: false,
+        }
+    }
+
+    fn into_stream(self) -> BoxStream<'static, Result<String>> {
+        futures::stream::unfold(self, |mut subscription| async move {
+            let message = subscription.next_message().await?;
+            Some((message, subscription))
+        })
+        .boxed()
+    }
+
+    async fn next_message(&mut self) -> Option<Result<String>> {
+        if self.done {
+            return None;
+        }
+        let mut failures = 0;
+        loop {
+            let dropped = match &mut self.events {
+                Some(events) => match events.next().await {
+                    Some(Ok((id, message))) => {
+                        let checked = self.check_event_id(id).map(|()| message);
+                        self.done = checked.is_err();
+                        return Some(checked);
+                    }
+                    Some(Err(e)) => e,
+                    None => anyhow!("relay closed the subscription"),
+                },
+                None => match self.client.subscribe_from(self.last_event).await {
+                    Ok(events) => {
+                        self.events = Some(events);
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+            };
+            self.events = None;
+            failures += 1;
+            tracing::warn!(
+                room = %self.client.room,
+                failures,
+                error = %format!("{:#}", dropped),
+                "relay connection dropped"
+            );
+            // Retrying won't make the relay accept our token.
+            let rejected = matches!(
+                dropped.downcast_ref::<BoomerError>(),
+                Some(BoomerError::RelayRejectedCredentials { .. })
+            );
+            if rejected {
+                self.done = true;
+                return Some(Err(dropped));
+            }
+            if failures > RECONNECT_ATTEMPTS {
+                self.done = true;
+                return Some(Err(dropped.context(format!(
+                    "relay connection lost, gave up after {} reconnects",
+                    RECONNECT_ATTEMPTS
+                ))));
+            }
+            tokio::time::sleep(backoff(failures, self.max_backoff)).await;
+        }
+    }
+
+    /// Messages must arrive in order and without gaps, a missed one would
+    /// only surface much later as a stalled or failed round.
+    fn check_event_id(&mut self, id: Option<u16>) -> Result<()> {
+        let expected = self.last_event.map_or(0, |last| last + 1);
+        match id {
+            Some(id) if id == expected => {
+                self.last_event = Some(id);
+                Ok(())
+            }
+            Some(id) if id < expected => bail!(
+                "relay replayed message {} after {}, it lost the room's messages",
+                id,
+                expected - 1
+            ),
+            Some(id) => bail!("relay skipped messages {} to {}", expected, id - 1),
+            None => bail!("relay sent a message without an id, can't resume after a reconnect"),
+        }
+    }
+}
+
+/// Parses a SHA-256 fingerprint as hex, with or without the colons printed by
+/// `openssl x509 -fingerprint -sha256`.
+pub fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32]> {
+    let hex_digits: String = fingerprint.trim().chars().filter(|&c| c != ':').collect();
+    let bytes = hex::decode(&hex_digits).context("certificate fingerprint is not valid hex")?;
+    if bytes.len() != 32 {
+        bail!(
+            "certificate fingerprint must be a 32 byte SHA-256, got {} bytes",
+            bytes.len()
+        );
+    }
+    let mut pin = [0u8; 32];
+    pin.copy_from_slice(&bytes);
+    Ok(pin)
+}
+
+/// Accepts the relay only if its leaf certificate hashes to the pinned
+/// fingerprint, in place of the usual CA validation.
+struct PinnedCertVerifier {
+    pin: [u8; 32],
+}
+
+impl PinnedCertVerifier {
+    fn matches(&self, certificate: &[u8]) -> bool {
+        let digest = sha2::Sha256::digest(certificate);
+        subtle::ConstantTimeEq::ct_eq(digest.as_slice(), &self.pin[..]).into()
+    }
+}
+
+impl rustls::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> std::result::Result<rustls::ServerCertVerified, rustls::TLSError> {
+        match presented_certs.first() {
+            Some(leaf) if self.matches(&leaf.0) => Ok(rustls::ServerCertVerified::assertion()),
+            _ => Err(rustls::TLSError::General(
+                "relay certificate does not match the pinned fingerprint".into(),
+            )),
+        }
+    }
+}
+
+/// Accepts whatever certificate the relay presents.
+struct NoCertVerification;
+
+impl rustls::ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> std::result::Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+fn http_client(address: &surf::Url, options: &RelayOptions) -> Result<http_client::h1::H1Client> {
+    let config = http_client::Config::new().set_timeout(None);
+    let verifier: Arc<dyn rustls::ServerCertVerifier> =
+        match (address.scheme(), options.cert_fingerprint, options.insecure) {
+            ("http", None, false) | ("https", None, false) => {
+                return config.try_into().context("build http client")
+            }
+            ("https", Some(pin), false) => Arc::new(PinnedCertVerifier { pin }),
+            ("https", None, true) => Arc::new(NoCertVerification),
+            ("https", Some(_), true) => {
+                bail!("--insecure would ignore the pinned relay certificate, use only one")
+            }
+            ("http", Some(_), _) => bail!("certificate pinning requires an https relay"),
+            ("http", None, true) => bail!("--insecure only applies to https relays"),
+            (scheme, _, _) => bail!("unsupported relay scheme {:?}, use http or https", scheme),
+        };
+    let mut tls_config = rustls::ClientConfig::new();
+    tls_config.dangerous().set_certificate_verifier(verifier);
+    config
+        .set_tls_config(Some(Arc::new(tls_config)))
+        .try_into()
+        .context("build http client")
+}
+
+/// Checks that a client for the relay can be built, without connecting to it.
+pub fn check_relay(address: &surf::Url, options: &RelayOptions) -> Result<()> {
+    http_client(address, options).map(drop)
+}
+
+#[derive(Clone)]
 pub struct SmClient {
     http_client: surf::Client,
+    room: String,
 }
 
 impl SmClient {
-    pub fn new(address: surf::Url, room_id: &str) -> Result<Self> {
-        let config = surf::Config::new()
+    pub fn new(address: surf::Url, room_id: &str, options: &RelayOptions) -> Result<Self> {
+        let mut config = surf::Config::new()
+            .set_http_client(http_client(&address, options)?)
             .set_base_url(address.join(&format!("rooms/{}/", room_id))?)
             .set_timeout(None);
+        if let Some(token) = &options.auth_token {
+            config = config
+                .add_header("Authorization", format!("Bearer {}", token))
+                .map_err(|e| e.into_inner())
+                .context("invalid relay auth token")?;
+        }
         Ok(Self {
             http_client: config.try_into()?,
+            room: room_id.to_string(),
         })
     }
 
+    #[tracing::instrument(skip(self), fields(room = %self.room), err)]
     pub async fn issue_index(&self) -> Result<u16> {
-        let response = self
+        let mut response = self
             .http_client
             .post("issue_unique_idx")
-            .recv_json::<IssuedUniqueIdx>()
             .await
             .map_err(|e| e.into_inner())?;
-        Ok(response.unique_idx)
+        check_status(&response)?;
+        let issued: IssuedUniqueIdx = response.body_json().await.map_err(|e| e.into_inner())?;
+        tracing::debug!(index = issued.unique_idx, "issued index");
+        Ok(issued.unique_idx)
     }
 
+    #[tracing::instrument(skip(self, message), fields(room = %self.room, bytes = message.len()), err)]
     pub async fn broadcast(&self, message: &str) -> Result<()> {
-        self.http_client
+        let response = self
+            .http_client
             .post("broadcast")
             .body(message)
             .await
             .map_err(|e| e.into_inner())?;
-        Ok(())
+        check_status(&response)
     }
 
-    pub async fn subscribe(&self) -> Result<impl Stream<Item = Result<String>>> {
-        let response = self
-            .http_client
-            .get("subscribe")
-            .await
-            .map_err(|e| e.into_inner())?;
-        let events = async_sse::decode(response);
-        Ok(events.filter_map(|msg| async {
-            match msg {
-                Ok(async_sse::Event::Message(msg)) => Some(
-                    String::from_utf8(msg.into_bytes())
-                        .context("SSE message is not valid UTF-8 string"),
-                ),
-                Ok(_) => {
-                    // ignore other types of events
-                    None
-                }
-                Err(e) => Some(Err(e.into_inner())),
+    /// The room's messages after `last_event`, all of them when unset, each
+    /// with its event id.
+    pub fn subscribe_from(
+        &self,
+        last_event: Option<u16>,
+    ) -> impl Future<Output = Result<Events>> + 'static {
+        let mut request = self.http_client.get("subscribe");
+        if let Some(id) = last_event {
+            request = request.header("Last-Event-ID", id.to_string());
+        }
+        let span = tracing::info_span!("subscribe", room = %self.room, last_event = ?last_event);
+        async move {
+            let response = request.await.map_err(|e| e.into_inner())?;
+            check_status(&response)?;
+            tracing::debug!("subscribed");
+            let events = async_sse::decode(response);
+            Ok(events
+                .filter_map(|msg| async {
+                    match msg {
+                        Ok(async_sse::Event::Message(msg)) => {
+                            let id = msg.id().clone().and_then(|id| id.parse().ok());
+                            Some(
+                                String::from_utf8(msg.into_bytes())
+                                    .map(|msg| (id, msg))
+                                    .context("SSE message is not valid UTF-8 string"),
+                            )
+                        }
+                        Ok(_) => {
+                            // ignore other types of events
+                            None
+                        }
+                        Err(e) => Some(Err(e.into_inner())),
+                    }
+                })
+                .boxed())
+        }
+        .instrument(span)
+    }
+}
+
+/// Tells a relay refusing our token apart from one that is merely failing.
+fn check_status(response: &surf::Response) -> Result<()> {
+    let status = response.status();
+    match status {
+        surf::StatusCode::Unauthorized | surf::StatusCode::Forbidden => {
+            Err(BoomerError::RelayRejectedCredentials {
+                status: status.into(),
             }
-        }))
+            .into())
+        }
+        _ if status.is_success() => Ok(()),
+        _ => bail!("relay answered {}", status),
     }
 }
 
@@ -137,7 +503,8 @@ enum Cmd {
 #[allow(dead_code)]
 async fn main() -> Result<()> {
     let args: Cli = Cli::from_args();
-    let client = SmClient::new(args.address, &args.room).context("create SmClient")?;
+    let client = SmClient::new(args.address, &args.room, &RelayOptions::default())
+        .context("create SmClient")?;
     match args.cmd {
         Cmd::Broadcast { message } => client
             .broadcast(&message)
@@ -148,8 +515,7 @@ async fn main() -> Result<()> {
             println!("Index: {}", index);
         }
         Cmd::Subscribe => {
-            let messages = client.subscribe().await.context("subsribe")?;
-            tokio::pin!(messages);
+            let mut messages = client.subscribe_from(None).await.context("subsribe")?;
             while let Some(message) = messages.next().await {
                 println!("{:?}", message);
             }
@@ -157,3 +523,85 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fingerprint_accepts_openssl_output() {
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(parse_fingerprint(&colons).unwrap(), [0xab; 32]);
+        assert_eq!(parse_fingerprint(&"ab".repeat(32)).unwrap(), [0xab; 32]);
+        assert!(parse_fingerprint("abcd").is_err());
+    }
+
+    #[test]
+    fn pinned_verifier_only_matches_the_pinned_certificate() {
+        let certificate = b"not really a certificate";
+        let mut pin = [0u8; 32];
+        pin.copy_from_slice(&sha2::Sha256::digest(certificate));
+
+        let verifier = PinnedCertVerifier { pin };
+        assert!(verifier.matches(certificate));
+        assert!(!verifier.matches(b"another certificate"));
+    }
+
+    #[test]
+    fn pinning_requires_https() {
+        let options = RelayOptions {
+            cert_fingerprint: Some([0; 32]),
+            ..Default::default()
+        };
+        assert!(http_client(&"http://127.0.0.1:8000".parse().unwrap(), &options).is_err());
+        assert!(http_client(&"https://relay.example".parse().unwrap(), &options).is_ok());
+        assert!(http_client(&"ftp://relay.example".parse().unwrap(), &Default::default()).is_err());
+    }
+
+    fn subscription(events: Vec<Result<(Option<u16>, String)>>) -> Subscription {
+        let client = SmClient::new(
+            "http://127.0.0.1:1".parse().unwrap(),
+            "room",
+            &RelayOptions::default(),
+        )
+        .unwrap();
+        let events = futures::stream::iter(events).boxed();
+        Subscription::new(client, Duration::from_millis(1), events)
+    }
+
+    #[tokio::test]
+    async fn subscription_refuses_gaps_in_the_messages() {
+        let message = |id: u16| Ok((Some(id), format!("message {}", id)));
+        let mut messages = subscription(vec![message(0), message(1), message(3)]);
+        assert_eq!(messages.next_message().await.unwrap().unwrap(), "message 0");
+        assert_eq!(messages.next_message().await.unwrap().unwrap(), "message 1");
+        let err = messages.next_message().await.unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "relay skipped messages 2 to 2");
+        assert!(messages.next_message().await.is_none());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let cap = Duration::from_secs(3);
+        assert_eq!(backoff(1, cap), Duration::from_millis(500));
+        assert_eq!(backoff(3, cap), Duration::from_secs(2));
+        assert_eq!(backoff(4, cap), cap);
+        assert_eq!(backoff(40, cap), cap);
+    }
+
+    #[test]
+    fn insecure_is_only_for_unpinned_https() {
+        let insecure = RelayOptions {
+            insecure: true,
+            ..Default::default()
+        };
+        assert!(http_client(&"https://relay.example".parse().unwrap(), &insecure).is_ok());
+        assert!(http_client(&"http://127.0.0.1:8000".parse().unwrap(), &insecure).is_err());
+
+        let pinned = RelayOptions {
+            cert_fingerprint: Some([0; 32]),
+            ..insecure
+        };
+        assert!(http_client(&"https://relay.example".parse().unwrap(), &pinned).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use arboard::Clipboard;
+
+use crate::bs_share::ensure_data_dir;
+
+// Kept open while the app runs: on X11 and Wayland the copied text is served
+// by this process and goes away with the clipboard handle.
+static CLIPBOARD: Mutex<Option<Clipboard>> = Mutex::new(None);
+
+/// Where `copy` put the text.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Copied {
+    Clipboard,
+    /// There was no clipboard, e.g. over SSH or without a display server.
+    File(PathBuf),
+}
+
+/// Puts `text` on the system clipboard, or when there is none writes it to a
+/// file in `dir` named after `label`.
+pub fn copy(label: &str, text: &str, dir: &Path) -> Result<Copied> {
+    if copy_to_clipboard(text).is_ok() {
+        return Ok(Copied::Clipboard);
+    }
+    ensure_data_dir(dir)?;
+    let path = fallback_path(dir, label);
+    std::fs::write(&path, text).context(format!("cannot write {}", path.display()))?;
+    Ok(Copied::File(path))
+}
+
+fn copy_to_clipboard(text: &str) -> Result<(), arboard::Error> {
+    let mut kept = CLIPBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut clipboard = match kept.take() {
+        Some(clipboard) => clipboard,
+        None => Clipboard::new()?,
+    };
+    let copied = clipboard.set_text(text);
+    *kept = Some(clipboard);
+    copied
+}
+
+fn fallback_path(dir: &Path, label: &str) -> PathBuf {
+    let name = label
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-");
+    dir.join(format!("copied-{}.txt", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_file_is_named_after_the_label() {
+        assert_eq!(
+            fallback_path(Path::new("data"), "Signed transaction"),
+            Path::new("data").join("copied-signed-transaction.txt")
+        );
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bitcoin::Network;
+use serde::{Deserialize, Serialize};
+
+use crate::bs_broadcast::{BitcoindRpc, BroadcastConfig, Broadcaster};
+use crate::bs_client::{parse_fingerprint, RelayOptions};
+use crate::bs_network::{esplora_url, DEFAULT_NETWORK};
+use crate::bs_share::{data_dir, share_path_in};
+use crate::bs_signing::PhaseTimeouts;
+
+pub const DEFAULT_RELAY: &str = "http://127.0.0.1:8000";
+
+/// How many recently used relays are remembered.
+const MAX_RECENT_RELAYS: usize = 5;
+
+const DEFAULT_CEREMONY_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_BACKOFF_SECS: u64 = 30;
+
+/// What signed transactions are broadcast through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BroadcastBackend {
+    /// The esplora API of the network, see `Config::esplora_url`.
+    #[default]
+    Esplora,
+    /// Our own node, see `Config::bitcoind_rpc`.
+    Bitcoind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The SM-manager every ceremony connects to.
+    pub relay: String,
+    /// Most recently used first, never containing duplicates.
+    pub recent_relays: Vec<String>,
+    /// SHA-256 fingerprint of an https relay's certificate to pin.
+    pub relay_cert_fingerprint: Option<String>,
+    /// Run after every fully signed transaction, see `bs_hook`. Unset by
+    /// default, it can only be enabled by editing the config file.
+    pub post_sign_command: Option<String>,
+    /// What addresses are derived for, PSBTs checked against and broadcasts
+    /// sent to. Shares record the network they were made for and are refused
+    /// on any other.
+    pub network: Network,
+    /// Esplora API of each network used instead of its mempool.space one, e.g.
+    /// a local instance for regtest.
+    pub esplora_urls: BTreeMap<Network, String>,
+    /// Older configs' single esplora API, for whatever network is configured.
+    /// `esplora_urls` wins over it.
+    pub esplora_url: Option<String>,
+    /// How long parties get to complete a keygen. When signing, how long they
+    /// get to gather on top of the stage timeouts below, per input when
+    /// signing several.
+    pub ceremony_timeout_secs: u64,
+    pub offline_timeout_secs: u64,
+    pub online_timeout_secs: u64,
+    /// Each broadcast attempt.
+    pub broadcast_timeout_secs: u64,
+    pub broadcast_backend: BroadcastBackend,
+    /// Needed by the `bitcoind` broadcast backend.
+    pub bitcoind_rpc: Option<BitcoindRpc>,
+    /// How many times a timed out signing moves to the next room on its own
+    /// before asking whether to retry. Every signer must use the same value.
+    pub ceremony_retries: u8,
+    /// Preselected on every screen at startup, the index used last.
+    pub participant_index: u8,
+    /// Where `local-share<N>.json` files are read and written, the data
+    /// directory when unset. Only the path is stored here, never a share.
+    pub share_dir: Option<PathBuf>,
+    /// Bearer token for a relay that requires one.
+    pub relay_auth_token: Option<String>,
+    /// Subscribes to the relay again when the connection drops mid-ceremony,
+    /// instead of failing it.
+    pub relay_reconnect: bool,
+    /// The longest wait between two reconnects, the wait doubles from half a
+    /// second up to it.
+    pub relay_max_backoff_secs: u64,
+    /// Keygen also reports a classic p2wsh multisig of every party's share
+    /// key, and signing spends that script instead of the group key. Off by
+    /// default, every party must enable it to sign together.
+    pub script_multisig: bool,
+    /// Makes every input of a PSBT signal replace-by-fee before signing it, so
+    /// the transaction can be bumped if it gets stuck. Every signer must use
+    /// the same value, it changes what is signed.
+    pub rbf: bool,
+    /// Set by `--insecure` for this run only, never saved.
+    #[serde(skip)]
+    pub relay_insecure: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            relay: DEFAULT_RELAY.into(),
+            recent_relays: vec![DEFAULT_RELAY.into()],
+            relay_cert_fingerprint: None,
+            post_sign_command: None,
+            network: DEFAULT_NETWORK,
+            esplora_urls: BTreeMap::new(),
+            esplora_url: None,
+            ceremony_timeout_secs: DEFAULT_CEREMONY_TIMEOUT_SECS,
+            offline_timeout_secs: PhaseTimeouts::default().offline.as_secs(),
+            online_timeout_secs: PhaseTimeouts::default().online.as_secs(),
+            broadcast_timeout_secs: PhaseTimeouts::default().broadcast.as_secs(),
+            broadcast_backend: BroadcastBackend::default(),
+            bitcoind_rpc: None,
+            ceremony_retries: 0,
+            participant_index: 0,
+            share_dir: None,
+            relay_auth_token: None,
+            relay_reconnect: false,
+            relay_max_backoff_secs: DEFAULT_MAX_BACKOFF_SECS,
+            script_multisig: false,
+            rbf: false,
+            relay_insecure: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn relay_url(&self) -> Result<surf::Url> {
+        parse_relay(&self.relay)
+    }
+
+    /// Where UTXOs are looked up and transactions broadcast, without a
+    /// trailing slash.
+    pub fn esplora_url(&self) -> Result<String> {
+        let url = match self
+            .esplora_urls
+            .get(&self.network)
+            .or(self.esplora_url.as_ref())
+        {
+            Some(url) => {
+                surf::Url::parse(url.trim()).context(format!("invalid esplora url {:?}", url))?;
+                url.trim()
+            }
+            None => esplora_url(self.network)?,
+        };
+        Ok(url.trim_end_matches('/').to_string())
+    }
+
+    pub fn share_dir(&self) -> PathBuf {
+        self.share_dir.clone().unwrap_or_else(data_dir)
+    }
+
+    pub fn share_path(&self, index: impl Display) -> PathBuf {
+        share_path_in(&self.share_dir(), index)
+    }
+
+    pub fn ceremony_timeout(&self) -> Duration {
+        Duration::from_secs(self.ceremony_timeout_secs.max(1))
+    }
+
+    pub fn phase_timeouts(&self) -> PhaseTimeouts {
+        PhaseTimeouts {
+            offline: Duration::from_secs(self.offline_timeout_secs.max(1)),
+            online: Duration::from_secs(self.online_timeout_secs.max(1)),
+            broadcast: Duration::from_secs(self.broadcast_timeout_secs.max(1)),
+        }
+    }
+
+    /// The whole signing of one input: gathering, then both stages.
+    pub fn signing_timeout(&self) -> Duration {
+        self.ceremony_timeout() + self.phase_timeouts().stages()
+    }
+
+    /// Where signed transactions go, as `broadcast_backend` picks.
+    pub fn broadcaster(&self) -> Result<Broadcaster> {
+        match self.broadcast_backend {
+            BroadcastBackend::Esplora => Ok(Broadcaster::Esplora(self.esplora_url()?)),
+            BroadcastBackend::Bitcoind => {
+                let rpc = self
+                    .bitcoind_rpc
+                    .clone()
+                    .context("the bitcoind broadcast backend needs bitcoind_rpc in config.json")?;
+                surf::Url::parse(rpc.url.trim())
+                    .context(format!("invalid bitcoind url {:?}", rpc.url))?;
+                Ok(Broadcaster::Bitcoind(rpc))
+            }
+        }
+    }
+
+    pub fn broadcast_config(&self) -> BroadcastConfig {
+        BroadcastConfig {
+            timeout: self.phase_timeouts().broadcast,
+            ..BroadcastConfig::default()
+        }
+    }
+
+    pub fn relay_options(&self) -> Result<RelayOptions> {
+        Ok(RelayOptions {
+            cert_fingerprint: self
+                .relay_cert_fingerprint
+                .as_deref()
+                .map(parse_fingerprint)
+                .transpose()?,
+            insecure: self.relay_insecure,
+            auth_token: self.relay_auth_token.clone(),
+            reconnect: match self.relay_reconnect {
+                true => Some(Duration::from_secs(self.relay_max_backoff_secs.max(1))),
+                false => None,
+            },
+        })
+    }
+
+    /// Makes `relay` the current relay and moves it to the front of the
+    /// recently used list.
+    pub fn use_relay(&mut self, relay: &str) -> Result<()> {
+        let url = parse_relay(relay)?;
+        let relay = url.as_str().trim_end_matches('/').to_string();
+
+        self.recent_relays.retain(|recent| recent != &relay);
+        self.recent_relays.insert(0, relay.clone());
+        self.recent_relays.truncate(MAX_RECENT_RELAYS);
+        self.relay = relay;
+        Ok(())
+    }
+}
+
+fn parse_relay(relay: &str) -> Result<surf::Url> {
+    relay
+        .trim()
+        .parse()
+        .context(format!("invalid relay address {:?}", relay))
+}
+
+pub fn config_path() -> PathBuf {
+    data_dir().join("config.json")
+}
+
+/// The saved config, or the defaults when none was saved yet.
+pub fn load_config(path: &Path) -> Result<Config> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).context("parse config"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e).context("cannot read config"),
+    }
+}
+
+/// Readable by us alone, like a share, since the config may hold the relay
+/// token and bitcoind's password. Written aside and renamed into place, so
+/// the permissions hold from the first byte on.
+pub fn save_config(path: &Path, config: &Config) -> Result<()> {
+    let bytes = serde_json::to_vec_pretty(config).context("serialize config")?;
+    let name = path
+        .file_name()
+        .context(format!("{} is not a file path", path.display()))?;
+    let temporary = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+        .open(&temporary)
+        .and_then(|mut file| {
+            file.write_all(&bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    written.context("cannot write config")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn saved_config_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path =
+            std::env::temp_dir().join(format!("boomersig-config-{}.json", std::process::id()));
+        std::fs::write(&path, "{}").unwrap();
+        let config = Config {
+            relay_auth_token: Some("secret".into()),
+            ..Config::default()
+        };
+        save_config(&path, &config).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let saved = load_config(&path).unwrap();
+        assert_eq!(saved.relay_auth_token.as_deref(), Some("secret"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn use_relay_dedupes_and_caps_recent_relays() {
+        let mut config = Config::default();
+        for port in 0..MAX_RECENT_RELAYS + 2 {
+            config
+                .use_relay(&format!("http://relay.example:{}", 8000 + port))
+                .unwrap();
+        }
+        config.use_relay("http://relay.example:8003/").unwrap();
+
+        assert_eq!(config.relay, "http://relay.example:8003");
+        assert_eq!(config.recent_relays.len(), MAX_RECENT_RELAYS);
+        assert_eq!(config.recent_relays[0], "http://relay.example:8003");
+        assert_eq!(
+            config
+                .recent_relays
+                .iter()
+                .filter(|relay| relay.as_str() == "http://relay.example:8003")
+                .count(),
+            1
+        );
+        assert!(config.use_relay("not a url").is_err());
+        assert_eq!(config.relay, "http://relay.example:8003");
+    }
+
+    #[test]
+    fn esplora_url_defaults_to_the_network() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.esplora_url().unwrap(),
+            "https://mempool.space/signet/api"
+        );
+
+        config.network = Network::Regtest;
+        assert!(config.esplora_url().is_err());
+        config.esplora_url = Some("http://127.0.0.1:3002/".into());
+        assert_eq!(config.esplora_url().unwrap(), "http://127.0.0.1:3002");
+
+        config
+            .esplora_urls
+            .insert(Network::Regtest, "http://127.0.0.1:3003".into());
+        assert_eq!(config.esplora_url().unwrap(), "http://127.0.0.1:3003");
+        config.network = Network::Signet;
+        config.esplora_url = None;
+        assert_eq!(
+            config.esplora_url().unwrap(),
+            "https://mempool.space/signet/api"
+        );
+    }
+
+    #[test]
+    fn broadcast_backend_picks_the_broadcaster() {
+        let mut config: Config =
+            serde_json::from_str(r#"{"broadcast_backend": "bitcoind"}"#).unwrap();
+        assert!(config.broadcaster().is_err());
+
+        config.bitcoind_rpc = Some(BitcoindRpc {
+            url: "http://127.0.0.1:38332".into(),
+            ..BitcoindRpc::default()
+        });
+        assert!(matches!(
+            config.broadcaster().unwrap(),
+            Broadcaster::Bitcoind(_)
+        ));
+
+        config.broadcast_backend = BroadcastBackend::Esplora;
+        assert_eq!(
+            config.broadcaster().unwrap(),
+            Broadcaster::Esplora("https://mempool.space/signet/api".into())
+        );
+    }
+
+    #[test]
+    fn ceremony_settings_default_when_missing() {
+        let config: Config = serde_json::from_str(r#"{"ceremony_timeout_secs": 0}"#).unwrap();
+        assert_eq!(config.ceremony_timeout(), Duration::from_secs(1));
+        assert_eq!(config.ceremony_retries, 0);
+        assert_eq!(config.phase_timeouts(), PhaseTimeouts::default());
+        assert_eq!(config.signing_timeout(), Duration::from_secs(1 + 18 + 8));
+        assert_eq!(
+            Config::default().ceremony_timeout(),
+            Duration::from_secs(DEFAULT_CEREMONY_TIMEOUT_SECS)
+        );
+    }
+
+    #[test]
+    fn share_dir_overrides_the_data_dir() {
+        let mut config = Config::default();
+        assert_eq!(config.share_path(2), data_dir().join("local-share2.json"));
+
+        config.share_dir = Some(PathBuf::from("/media/usb/shares"));
+        assert_eq!(
+            config.share_path(2),
+            PathBuf::from("/media/usb/shares/local-share2.json")
+        );
+
+        let saved = serde_json::to_vec(&config).unwrap();
+        let loaded: Config = serde_json::from_slice(&saved).unwrap();
+        assert_eq!(loaded, config);
+    }
+}
@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::bs_error::BoomerError;
+
+/// Read by the headless subcommands, the TUI asks for it in Settings.
+pub const PASSPHRASE_ENV: &str = "BOOMERSIG_PASSPHRASE";
+
+// Can't start a JSON document, so plaintext shares are told apart by it.
+const MAGIC: &[u8] = b"BSENC";
+// Bumped whenever the KDF or its parameters change, `derive_key` then has to
+// keep deriving keys for the older versions.
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+
+/// The passphrase set in the environment, if any. An empty one counts as
+/// unset.
+pub fn env_passphrase() -> Option<String> {
+    std::env::var(PASSPHRASE_ENV)
+        .ok()
+        .filter(|passphrase| !passphrase.is_empty())
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// `MAGIC | version | salt | nonce | ciphertext`, the header is authenticated
+/// along with the share.
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut out = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, &salt)?;
+    let ciphertext = Aes256Gcm::new(Key::from_slice(key.as_ref()))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &out,
+            },
+        )
+        .map_err(|_| anyhow!("cannot encrypt share"))?;
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypts a file written by `encrypt`, `path` is only used in errors.
+pub fn decrypt(bytes: &[u8], passphrase: &str, path: &Path) -> Result<Vec<u8>> {
+    if bytes.len() < HEADER_LEN || !is_encrypted(bytes) {
+        return Err(BoomerError::WrongPassphrase {
+            path: path.to_owned(),
+        }
+        .into());
+    }
+    let version = bytes[MAGIC.len()];
+    if version != VERSION {
+        return Err(BoomerError::UnsupportedShareVersion {
+            path: path.to_owned(),
+            version,
+        }
+        .into());
+    }
+    let salt = &bytes[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &bytes[HEADER_LEN - NONCE_LEN..HEADER_LEN];
+
+    let key = derive_key(passphrase, salt)?;
+    Aes256Gcm::new(Key::from_slice(key.as_ref()))
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: &bytes[HEADER_LEN..],
+                aad: &bytes[..HEADER_LEN],
+            },
+        )
+        .map_err(|_| {
+            BoomerError::WrongPassphrase {
+                path: path.to_owned(),
+            }
+            .into()
+        })
+}
+
+/// Argon2id with the OWASP recommended 19 MiB and 2 passes.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let params = Params::new(19 * 1024, 2, 1, Some(32))
+        .map_err(|e| anyhow!("{}", e))
+        .context("argon2 parameters")?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| anyhow!("{}", e))
+        .context("derive share key")?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_share_round_trips_only_with_its_passphrase() {
+        let path = Path::new("local-share1.json");
+        let share = br#"{"i": 1, "network": "signet"}"#;
+
+        let encrypted = encrypt(share, "correct horse").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(share));
+        assert_eq!(decrypt(&encrypted, "correct horse", path).unwrap(), share);
+
+        let err = decrypt(&encrypted, "battery staple", path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BoomerError>(),
+            Some(BoomerError::WrongPassphrase { .. })
+        ));
+
+        let mut future = encrypted.clone();
+        future[MAGIC.len()] = VERSION + 1;
+        let err = decrypt(&future, "correct horse", path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BoomerError>(),
+            Some(BoomerError::UnsupportedShareVersion { version: 2, .. })
+        ));
+    }
+}
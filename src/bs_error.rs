@@ -0,0 +1,452 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BoomerError {
+    #[error("share file {} is empty, re-run keygen to create it again", path.display())]
+    EmptyShareFile { path: PathBuf },
+    #[error("{} is corrupt or was tampered with: {reason}", path.display())]
+    CorruptShare { path: PathBuf, reason: String },
+    #[error(
+        "{} already holds a share, keygen never overwrites one: back it up or move it away first",
+        path.display()
+    )]
+    ShareExists { path: PathBuf },
+    #[error(
+        "keygen parameters disagree with ours (threshold {threshold} of {number_of_parties} parties on {network}): {}",
+        .mismatches.join(", ")
+    )]
+    ParameterMismatch {
+        threshold: u16,
+        number_of_parties: u16,
+        network: bitcoin::Network,
+        mismatches: Vec<String>,
+    },
+    #[error(
+        "parties {} derived another group key than ours ({pubkey}), no share was written",
+        .parties.iter().map(u16::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    GroupKeyMismatch { pubkey: String, parties: Vec<u16> },
+    #[error("relay rejected credentials (HTTP {status}), check relay_auth_token in config.json")]
+    RelayRejectedCredentials { status: u16 },
+    #[error("transaction {txid} does not signal replace-by-fee (BIP 125), it can't be bumped")]
+    NotReplaceable { txid: String },
+    #[error("fee rate of {fee_rate} sat/vB is above the {max} sat/vB limit, check for a typo")]
+    FeeRateTooHigh { fee_rate: u64, max: u64 },
+    #[error("broadcast timed out after {attempts} attempts of {}s each", timeout.as_secs())]
+    BroadcastTimeout { attempts: u32, timeout: Duration },
+    #[error(
+        "transaction {txid} never showed up on esplora within {}s, the node likely rejected it",
+        waited.as_secs()
+    )]
+    TransactionNotSeen { txid: String, waited: Duration },
+    #[error(
+        "permission denied creating data directory {}, set {} to a writable location",
+        path.display(),
+        crate::bs_share::DATA_DIR_ENV
+    )]
+    DataDirPermission { path: PathBuf },
+    #[error("{stage} failed: {description}{}", flagged_parties(bad_actors))]
+    ProtocolFailure {
+        stage: &'static str,
+        description: &'static str,
+        bad_actors: Vec<usize>,
+        #[source]
+        cause: ProtocolCause,
+    },
+    #[error("offline stage incomplete after {}s, a signer never joined or dropped out", timeout.as_secs())]
+    OfflineStageIncomplete { timeout: Duration },
+    #[error(
+        "online stage incomplete after {}s: received {received} of {expected} partial signatures, a signer dropped after the offline stage",
+        timeout.as_secs()
+    )]
+    OnlineStageIncomplete {
+        received: usize,
+        expected: usize,
+        timeout: Duration,
+    },
+    #[error("cannot extract transaction, inputs {inputs:?} are missing signatures")]
+    UnsignedInputs { inputs: Vec<usize> },
+    #[error("inputs {inputs:?} carry neither a witness_utxo nor a non_witness_utxo, their type is unknown")]
+    MissingUtxo { inputs: Vec<usize> },
+    #[error(
+        "saved offline stage {} does not match this signing: {}",
+        path.display(),
+        .mismatches.join(", ")
+    )]
+    OfflineStageMismatch {
+        path: PathBuf,
+        mismatches: Vec<String>,
+    },
+    #[error("cannot recover the signing key with recid {recid} or the other parity: {reason}")]
+    RecoveryFailed { recid: u8, reason: String },
+    #[error("signature does not verify against the signed digest and recovered key: {reason}")]
+    InvalidSignature { reason: String },
+    #[error("signed input {input} fails script verification: {reason}")]
+    ScriptVerificationFailed { input: usize, reason: String },
+    #[error("Your index ({idx}) must be included in the parties list, e.g. {suggestion:?}")]
+    SignerNotInParties { idx: u16, suggestion: Vec<u16> },
+    #[error("invalid party index {entry:?} in the parties list")]
+    InvalidPartyEntry { entry: String },
+    #[error("signing needs {needed} distinct signers (threshold + 1), got {got}")]
+    TooFewSigners { needed: usize, got: usize },
+    #[error("party {party} is not one of the share's parties 1..={parties}")]
+    SignerOutOfRange { party: u16, parties: u16 },
+    #[error(
+        "{} belongs to party {share_index}, not party {index}: set the participant index to {share_index}",
+        path.display()
+    )]
+    ShareIndexMismatch {
+        path: PathBuf,
+        share_index: u16,
+        index: u16,
+    },
+    #[error(
+        "share {} was created for {share}, but the configured network is {expected}",
+        path.display()
+    )]
+    ShareNetworkMismatch {
+        path: PathBuf,
+        share: bitcoin::Network,
+        expected: bitcoin::Network,
+    },
+    #[error(
+        "share {} is encrypted, enter its passphrase in Settings or set {}",
+        path.display(),
+        crate::bs_encryption::PASSPHRASE_ENV
+    )]
+    ShareLocked { path: PathBuf },
+    #[error("cannot decrypt share {}, wrong passphrase or damaged file", path.display())]
+    WrongPassphrase { path: PathBuf },
+    #[error(
+        "share {} uses encryption version {version}, upgrade boomersig to read it",
+        path.display()
+    )]
+    UnsupportedShareVersion { path: PathBuf, version: u8 },
+    #[error("{location} belongs to {found}, but signing is for {expected}")]
+    NetworkMismatch {
+        location: String,
+        found: &'static str,
+        expected: bitcoin::Network,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Config,
+    Network,
+    Protocol,
+    Crypto,
+}
+
+impl ErrorCategory {
+    /// The process exit code used by the headless subcommands, documented in
+    /// the README.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorCategory::Config => 2,
+            ErrorCategory::Network => 3,
+            ErrorCategory::Protocol => 4,
+            ErrorCategory::Crypto => 5,
+        }
+    }
+}
+
+impl BoomerError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            BoomerError::EmptyShareFile { .. } => ErrorCategory::Config,
+            BoomerError::CorruptShare { .. } => ErrorCategory::Config,
+            BoomerError::ShareExists { .. } => ErrorCategory::Config,
+            BoomerError::ParameterMismatch { .. } => ErrorCategory::Config,
+            BoomerError::GroupKeyMismatch { .. } => ErrorCategory::Protocol,
+            BoomerError::BroadcastTimeout { .. } => ErrorCategory::Network,
+            BoomerError::TransactionNotSeen { .. } => ErrorCategory::Network,
+            BoomerError::NotReplaceable { .. } => ErrorCategory::Config,
+            BoomerError::FeeRateTooHigh { .. } => ErrorCategory::Config,
+            BoomerError::RelayRejectedCredentials { .. } => ErrorCategory::Config,
+            BoomerError::DataDirPermission { .. } => ErrorCategory::Config,
+            BoomerError::UnsignedInputs { .. } => ErrorCategory::Protocol,
+            BoomerError::MissingUtxo { .. } => ErrorCategory::Config,
+            BoomerError::NetworkMismatch { .. } => ErrorCategory::Config,
+            BoomerError::ShareNetworkMismatch { .. } => ErrorCategory::Config,
+            BoomerError::ShareLocked { .. } => ErrorCategory::Config,
+            BoomerError::WrongPassphrase { .. } => ErrorCategory::Config,
+            BoomerError::UnsupportedShareVersion { .. } => ErrorCategory::Config,
+            BoomerError::SignerNotInParties { .. } => ErrorCategory::Config,
+            BoomerError::InvalidPartyEntry { .. } => ErrorCategory::Config,
+            BoomerError::TooFewSigners { .. } => ErrorCategory::Config,
+            BoomerError::SignerOutOfRange { .. } => ErrorCategory::Config,
+            BoomerError::ShareIndexMismatch { .. } => ErrorCategory::Config,
+            BoomerError::OfflineStageMismatch { .. } => ErrorCategory::Config,
+            BoomerError::RecoveryFailed { .. } => ErrorCategory::Crypto,
+            BoomerError::InvalidSignature { .. } => ErrorCategory::Crypto,
+            BoomerError::ScriptVerificationFailed { .. } => ErrorCategory::Crypto,
+            BoomerError::ProtocolFailure { .. } => ErrorCategory::Protocol,
+            BoomerError::OfflineStageIncomplete { .. } => ErrorCategory::Protocol,
+            BoomerError::OnlineStageIncomplete { .. } => ErrorCategory::Protocol,
+        }
+    }
+
+    /// Wraps an error returned by `AsyncProtocol::run`. Its errors are generic
+    /// over the relay stream errors, which are `anyhow::Error` and so can't be
+    /// kept as a typed source; the Debug rendering keeps every nested variant.
+    pub fn protocol_failure(stage: &'static str, error: impl Debug) -> Self {
+        let cause = format!("{:?}", error);
+        BoomerError::ProtocolFailure {
+            stage,
+            description: describe_protocol_error(&cause),
+            bad_actors: bad_actors(&cause),
+            cause: ProtocolCause(cause),
+        }
+    }
+}
+
+/// What stopped `do_sign`, each variant wrapping the error that did. Its
+/// category is the one of the `BoomerError` behind it, when there is one.
+#[derive(Debug, Error)]
+pub enum SigningError {
+    #[error("cannot read the share")]
+    ShareReadError(#[source] anyhow::Error),
+    #[error("cannot use the share")]
+    ShareParseError(#[source] anyhow::Error),
+    #[error("cannot join the signing ceremony")]
+    JoinError(#[source] anyhow::Error),
+    #[error("offline stage failed")]
+    OfflineStageError(#[source] anyhow::Error),
+    #[error("signing protocol failed")]
+    ProtocolError(#[source] anyhow::Error),
+    #[error("cannot sign this psbt")]
+    PsbtParseError(#[source] anyhow::Error),
+    #[error("cannot compute what to sign")]
+    SighashError(#[source] anyhow::Error),
+    #[error("signature incomplete")]
+    IncompleteSignature(#[source] anyhow::Error),
+}
+
+impl SigningError {
+    pub fn cause(&self) -> &anyhow::Error {
+        match self {
+            SigningError::ShareReadError(e)
+            | SigningError::ShareParseError(e)
+            | SigningError::JoinError(e)
+            | SigningError::OfflineStageError(e)
+            | SigningError::ProtocolError(e)
+            | SigningError::PsbtParseError(e)
+            | SigningError::SighashError(e)
+            | SigningError::IncompleteSignature(e) => e,
+        }
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        let typed = self
+            .cause()
+            .chain()
+            .find_map(|e| e.downcast_ref::<BoomerError>());
+        if let Some(e) = typed {
+            return e.category();
+        }
+        match self {
+            SigningError::ShareReadError(_) => ErrorCategory::Config,
+            SigningError::ShareParseError(_) => ErrorCategory::Config,
+            SigningError::JoinError(_) => ErrorCategory::Network,
+            SigningError::OfflineStageError(_) => ErrorCategory::Protocol,
+            SigningError::ProtocolError(_) => ErrorCategory::Protocol,
+            SigningError::PsbtParseError(_) => ErrorCategory::Config,
+            SigningError::SighashError(_) => ErrorCategory::Config,
+            SigningError::IncompleteSignature(_) => ErrorCategory::Crypto,
+        }
+    }
+
+    /// What to try next, shown under the error on the error screen.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            SigningError::ShareReadError(_) => "Check the share directory in Settings.",
+            SigningError::ShareParseError(_) => {
+                "Pick the share of this group, for the configured network."
+            }
+            SigningError::JoinError(_) => {
+                "Check the relay in Settings and that every signer lists the same parties."
+            }
+            SigningError::OfflineStageError(_) | SigningError::ProtocolError(_) => {
+                "A signer dropped out or misbehaved, retry in a fresh room."
+            }
+            SigningError::PsbtParseError(_) | SigningError::SighashError(_) => {
+                "Check the PSBT with whoever created it."
+            }
+            SigningError::IncompleteSignature(_) => {
+                "Nothing was broadcast, check the PSBT's inputs and retry."
+            }
+        }
+    }
+}
+
+/// The category of the first typed error in the chain, if any.
+pub fn error_category(error: &anyhow::Error) -> Option<ErrorCategory> {
+    error.chain().find_map(|e| {
+        e.downcast_ref::<SigningError>()
+            .map(SigningError::category)
+            .or_else(|| e.downcast_ref::<BoomerError>().map(BoomerError::category))
+    })
+}
+
+/// The raw error of a failed protocol run.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct ProtocolCause(String);
+
+// Most specific variants first, the wrapping ones (`ProceedRound`, ...) only
+// match when nothing more precise does.
+const KNOWN_PROTOCOL_ERRORS: [(&str, &str); 12] = [
+    (
+        "Round2VerifyCommitments",
+        "a party's commitment did not match its decommitment",
+    ),
+    (
+        "Round3VerifyVssConstruct",
+        "a party's secret share failed VSS verification",
+    ),
+    (
+        "Round4VerifyDLogProof",
+        "a party's discrete log proof is invalid",
+    ),
+    ("TooFewParties", "at least 2 parties are required"),
+    (
+        "InvalidThreshold",
+        "the threshold must be between 1 and n-1",
+    ),
+    (
+        "InvalidPartyIndex",
+        "the party index must be between 1 and the number of parties",
+    ),
+    (
+        "HandleIncomingTimeout",
+        "timed out waiting for another party's message",
+    ),
+    ("RecvEof", "the relay closed the connection"),
+    ("Recv(", "receiving a message from the relay failed"),
+    ("Send(", "sending a message to the relay failed"),
+    (
+        "HandleIncoming(",
+        "a party sent a message that failed validation",
+    ),
+    ("ProceedRound", "a protocol round could not be completed"),
+];
+
+fn describe_protocol_error(cause: &str) -> &'static str {
+    KNOWN_PROTOCOL_ERRORS
+        .iter()
+        .find(|(variant, _)| cause.contains(variant))
+        .map(|(_, description)| *description)
+        .unwrap_or("protocol execution terminated")
+}
+
+/// The parties blamed by GG20's `ErrorType { bad_actors, .. }`, if any.
+fn bad_actors(cause: &str) -> Vec<usize> {
+    let start = match cause.find("bad_actors: [") {
+        Some(start) => start + "bad_actors: [".len(),
+        None => return Vec::new(),
+    };
+    cause[start..]
+        .split(']')
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|index| index.trim().parse().ok())
+        .collect()
+}
+
+fn flagged_parties(bad_actors: &[usize]) -> String {
+    match bad_actors {
+        [] => String::new(),
+        _ => format!(" (flagged parties {:?})", bad_actors),
+    }
+}
+
+/// One line per error in the chain, for the error screen.
+pub fn describe_chain(error: &anyhow::Error) -> String {
+    error
+        .chain()
+        .enumerate()
+        .map(|(depth, cause)| match depth {
+            0 => cause.to_string(),
+            _ => format!("caused by: {}", cause),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct ErrorType {
+        error_type: String,
+        bad_actors: Vec<usize>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    enum Keygen {
+        ProceedRound(Round),
+    }
+
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    enum Round {
+        Round2VerifyCommitments(ErrorType),
+    }
+
+    #[test]
+    fn protocol_failure_describes_known_variants() {
+        let error = BoomerError::protocol_failure(
+            "keygen",
+            Keygen::ProceedRound(Round::Round2VerifyCommitments(ErrorType {
+                error_type: "invalid key".into(),
+                bad_actors: vec![1, 2],
+            })),
+        );
+
+        assert_eq!(
+            error.to_string(),
+            "keygen failed: a party's commitment did not match its decommitment (flagged parties [1, 2])"
+        );
+        assert_eq!(error.category(), ErrorCategory::Protocol);
+    }
+
+    #[test]
+    fn signing_error_takes_the_category_of_its_cause() {
+        let typed = SigningError::OfflineStageError(
+            anyhow::Error::new(BoomerError::ShareLocked {
+                path: PathBuf::from("local-share1.json"),
+            })
+            .context("load share"),
+        );
+        assert_eq!(typed.category(), ErrorCategory::Config);
+        let untyped = SigningError::JoinError(anyhow::anyhow!("connection refused"));
+        assert_eq!(untyped.category(), ErrorCategory::Network);
+
+        let error = anyhow::Error::new(untyped).context("sign");
+        assert_eq!(error_category(&error), Some(ErrorCategory::Network));
+        assert_eq!(
+            describe_chain(&error),
+            "sign\ncaused by: cannot join the signing ceremony\ncaused by: connection refused"
+        );
+    }
+
+    #[test]
+    fn describe_chain_lists_every_cause() {
+        let error = anyhow::Error::new(BoomerError::protocol_failure("keygen", "RecvEof"))
+            .context("join computation");
+
+        assert_eq!(
+            describe_chain(&error),
+            "join computation\ncaused by: keygen failed: the relay closed the connection\ncaused by: \"RecvEof\""
+        );
+    }
+}
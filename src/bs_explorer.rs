@@ -0,0 +1,246 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::{Address, Txid};
+use serde::Deserialize;
+
+use crate::bs_error::BoomerError;
+
+/// How often `wait_for_confirmation` asks esplora about the transaction.
+pub const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a broadcast transaction gets to show up on esplora before it is
+/// taken for rejected.
+pub const MEMPOOL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// One entry of esplora's `/address/:address/utxo`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Utxo {
+    pub txid: Txid,
+    pub vout: u32,
+    pub value: u64,
+    pub status: UtxoStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+pub struct UtxoStatus {
+    pub confirmed: bool,
+    /// Only set once confirmed.
+    pub block_height: Option<u32>,
+}
+
+/// What an address holds, in sats. Unconfirmed funds can still disappear,
+/// e.g. when their transaction is replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Balance {
+    pub confirmed: u64,
+    pub unconfirmed: u64,
+}
+
+impl Balance {
+    pub fn of(utxos: &[Utxo]) -> Self {
+        utxos.iter().fold(Self::default(), |mut balance, utxo| {
+            match utxo.status.confirmed {
+                true => balance.confirmed += utxo.value,
+                false => balance.unconfirmed += utxo.value,
+            }
+            balance
+        })
+    }
+
+    pub fn total(&self) -> u64 {
+        self.confirmed + self.unconfirmed
+    }
+}
+
+/// Looks up the UTXOs of `address` on the esplora API at `esplora`, see
+/// `Config::esplora_url` for the one of each network.
+pub async fn fetch_utxos(esplora: &str, address: &Address) -> Result<Vec<Utxo>> {
+    let url = format!("{}/address/{}/utxo", esplora, address);
+    let mut response = surf::get(&url)
+        .await
+        .map_err(|e| e.into_inner())
+        .context("cannot reach esplora")?;
+    let body = response
+        .body_string()
+        .await
+        .map_err(|e| e.into_inner())
+        .context("read utxo list")?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "esplora rejected the utxo request ({}): {}",
+            response.status(),
+            body.trim()
+        ));
+    }
+    serde_json::from_str(&body).context("parse utxo list")
+}
+
+/// Where a broadcast transaction stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxProgress {
+    InMempool,
+    Confirmed { confirmations: u32 },
+}
+
+impl TxProgress {
+    fn of(status: &UtxoStatus, tip_height: u32) -> Self {
+        match status.block_height {
+            Some(height) if status.confirmed => TxProgress::Confirmed {
+                confirmations: tip_height.saturating_sub(height) + 1,
+            },
+            _ => TxProgress::InMempool,
+        }
+    }
+
+    pub fn confirmations(&self) -> u32 {
+        match self {
+            TxProgress::InMempool => 0,
+            TxProgress::Confirmed { confirmations } => *confirmations,
+        }
+    }
+}
+
+impl std::fmt::Display for TxProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TxProgress::InMempool => write!(f, "in mempool"),
+            TxProgress::Confirmed { confirmations: 1 } => write!(f, "confirmed (1 block)"),
+            TxProgress::Confirmed { confirmations } => {
+                write!(f, "confirmed ({} blocks)", confirmations)
+            }
+        }
+    }
+}
+
+/// Polls esplora until `txid` has `confirmations` confirmations, calling
+/// `on_progress` each time its progress changes. A transaction that doesn't
+/// show up within `MEMPOOL_TIMEOUT`, or that leaves the mempool again, was
+/// rejected or replaced. Failed polls are retried on the next one.
+pub async fn wait_for_confirmation(
+    esplora: &str,
+    txid: &Txid,
+    confirmations: u32,
+    mut on_progress: impl FnMut(TxProgress),
+) -> Result<TxProgress> {
+    let started = Instant::now();
+    let mut last = None;
+    loop {
+        match fetch_tx_status(esplora, txid).await {
+            Ok(Some(status)) => {
+                let tip_height = match status.confirmed {
+                    true => fetch_tip_height(esplora).await,
+                    false => Ok(0),
+                };
+                if let Ok(tip_height) = tip_height {
+                    let progress = TxProgress::of(&status, tip_height);
+                    if last != Some(progress) {
+                        on_progress(progress);
+                        last = Some(progress);
+                    }
+                    if progress.confirmations() >= confirmations {
+                        return Ok(progress);
+                    }
+                }
+            }
+            Ok(None) if last.is_some() => {
+                bail!(
+                    "transaction {} left the mempool, it may have been replaced",
+                    txid
+                )
+            }
+            Ok(None) if started.elapsed() >= MEMPOOL_TIMEOUT => {
+                return Err(BoomerError::TransactionNotSeen {
+                    txid: txid.to_string(),
+                    waited: MEMPOOL_TIMEOUT,
+                }
+                .into())
+            }
+            Ok(None) | Err(_) => {}
+        }
+        tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+    }
+}
+
+/// esplora's `/tx/:txid/status`, None while it doesn't know the transaction.
+async fn fetch_tx_status(esplora: &str, txid: &Txid) -> Result<Option<UtxoStatus>> {
+    let url = format!("{}/tx/{}/status", esplora, txid);
+    let mut response = surf::get(&url)
+        .await
+        .map_err(|e| e.into_inner())
+        .context("cannot reach esplora")?;
+    if response.status() == surf::StatusCode::NotFound {
+        return Ok(None);
+    }
+    let body = response
+        .body_string()
+        .await
+        .map_err(|e| e.into_inner())
+        .context("read transaction status")?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "esplora rejected the status request ({}): {}",
+            response.status(),
+            body.trim()
+        ));
+    }
+    serde_json::from_str(&body)
+        .map(Some)
+        .context("parse transaction status")
+}
+
+async fn fetch_tip_height(esplora: &str) -> Result<u32> {
+    let url = format!("{}/blocks/tip/height", esplora);
+    let body = surf::get(&url)
+        .recv_string()
+        .await
+        .map_err(|e| e.into_inner())
+        .context("cannot reach esplora")?;
+    body.trim()
+        .parse()
+        .context(format!("unexpected tip height {:?}", body.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utxos_parse_and_split_by_confirmation() {
+        let utxos: Vec<Utxo> = serde_json::from_str(
+            r#"[
+                {"txid": "4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a",
+                 "vout": 1, "value": 40000,
+                 "status": {"confirmed": true, "block_height": 200123,
+                            "block_hash": "00", "block_time": 1700000000}},
+                {"txid": "4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a4d3c2b1a",
+                 "vout": 0, "value": 1500, "status": {"confirmed": false}}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(utxos[0].status.block_height, Some(200123));
+        assert_eq!(utxos[1].status, UtxoStatus::default());
+        let balance = Balance::of(&utxos);
+        assert_eq!((balance.confirmed, balance.unconfirmed), (40000, 1500));
+        assert_eq!(balance.total(), 41500);
+    }
+
+    #[test]
+    fn confirmations_count_the_block_of_the_transaction() {
+        let status: UtxoStatus =
+            serde_json::from_str(r#"{"confirmed": true, "block_height": 200123}"#).unwrap();
+        let progress = TxProgress::of(&status, 200125);
+        assert_eq!(progress, TxProgress::Confirmed { confirmations: 3 });
+        assert_eq!(progress.to_string(), "confirmed (3 blocks)");
+        assert_eq!(
+            TxProgress::of(&status, 200123).to_string(),
+            "confirmed (1 block)"
+        );
+
+        let progress = TxProgress::of(&UtxoStatus::default(), 200125);
+        assert_eq!(
+            (progress.confirmations(), progress.to_string()),
+            (0, "in mempool".into())
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::bs_history::raw_txid;
+use crate::bs_signing::{parse_psbt, SigningResult};
+
+/// What a signing leaves to be saved: the transaction in hex once complete,
+/// otherwise the PSBT in base64 for the next signer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Export {
+    SignedTx(String),
+    Psbt(String),
+}
+
+impl Export {
+    pub fn of(result: &SigningResult) -> Option<Self> {
+        match (&result.signined_tx, &result.partially_signed_psbt) {
+            (Some(tx), _) => Some(Export::SignedTx(tx.clone())),
+            (None, Some(psbt)) => Some(Export::Psbt(psbt.clone())),
+            (None, None) => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Export::SignedTx(_) => "Signed transaction",
+            Export::Psbt(_) => "PSBT",
+        }
+    }
+
+    /// `signed-<txid>.hex` or `psbt-<txid>.psbt`, the txid being the one the
+    /// transaction has or will have once signed.
+    pub fn file_name(&self) -> String {
+        match self {
+            Export::SignedTx(tx) => {
+                format!("signed-{}.hex", raw_txid(tx).unwrap_or_else(|| "tx".into()))
+            }
+            Export::Psbt(psbt) => format!(
+                "psbt-{}.psbt",
+                parse_psbt(psbt)
+                    .map_or_else(|_| "tx".into(), |psbt| psbt.unsigned_tx.txid().to_string())
+            ),
+        }
+    }
+
+    pub fn default_path(&self, dir: &Path) -> PathBuf {
+        dir.join(self.file_name())
+    }
+
+    /// Writes the bare hex or base64, as `sendrawtransaction` or another
+    /// wallet reads it. An existing file is never replaced.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = match self {
+            Export::SignedTx(data) | Export::Psbt(data) => data,
+        };
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .context(format!("cannot write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_signing::tests::psbt_spending;
+    use bitcoin::ScriptBuf;
+
+    #[test]
+    fn export_is_saved_bare_and_never_overwrites() {
+        let psbt = psbt_spending(ScriptBuf::new());
+        let export = Export::Psbt(psbt.to_string());
+        assert_eq!(
+            export.file_name(),
+            format!("psbt-{}.psbt", psbt.unsigned_tx.txid())
+        );
+
+        let path = std::env::temp_dir().join(format!(
+            "boomersig-export-{}-{}",
+            std::process::id(),
+            export.file_name()
+        ));
+        export.save(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), psbt.to_string());
+        assert!(Export::SignedTx("00".into()).save(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), psbt.to_string());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use bitcoin::psbt::PartiallySignedTransaction;
+
+use crate::bs_error::BoomerError;
+use crate::bs_signing::{finalized_vsize, spent_output};
+
+/// Fees above this percentage of the spent amount are flagged.
+pub const HIGH_FEE_PERCENT: u64 = 10;
+
+/// Rates above this many sat/vB are taken for a typo, no fee spike came close.
+pub const MAX_FEE_RATE: u64 = 10_000;
+
+/// Input amounts in sats supplied by the user, keyed by input index.
+pub type InputValues = BTreeMap<usize, u64>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeInfo {
+    pub fee: u64,
+    pub input_total: u64,
+    /// At least one input amount came from the user rather than the PSBT.
+    pub user_supplied: bool,
+    /// Of the transaction once signed, see `finalized_vsize`.
+    pub vsize: Option<usize>,
+}
+
+impl FeeInfo {
+    pub fn is_high(&self) -> bool {
+        self.fee.saturating_mul(100) > self.input_total.saturating_mul(HIGH_FEE_PERCENT)
+    }
+
+    /// In sat/vB.
+    pub fn fee_rate(&self) -> Option<f64> {
+        self.vsize.map(|vsize| self.fee as f64 / vsize as f64)
+    }
+}
+
+/// Parses a whole number of sat/vB, up to `MAX_FEE_RATE`.
+pub fn parse_fee_rate(text: &str) -> Result<u64> {
+    let fee_rate = text
+        .trim()
+        .parse()
+        .context("fee rate must be a whole number of sat/vB")?;
+    check_fee_rate(fee_rate)?;
+    Ok(fee_rate)
+}
+
+pub fn check_fee_rate(fee_rate: u64) -> Result<()> {
+    if fee_rate > MAX_FEE_RATE {
+        return Err(BoomerError::FeeRateTooHigh {
+            fee_rate,
+            max: MAX_FEE_RATE,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// What `vsize` vbytes pay at `fee_rate` sat/vB.
+pub fn fee_at(vsize: u64, fee_rate: u64) -> Result<u64> {
+    check_fee_rate(fee_rate)?;
+    vsize
+        .checked_mul(fee_rate)
+        .with_context(|| format!("fee of {} vB at {} sat/vB overflows", vsize, fee_rate))
+}
+
+/// Parses input amounts written as `index=sats`, separated by commas, e.g.
+/// `0=50000, 1=1200`.
+pub fn parse_input_values(text: &str) -> Result<InputValues> {
+    let mut values = InputValues::new();
+    for entry in text
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (index, amount) = match entry.split_once('=') {
+            Some(parts) => parts,
+            None => bail!("expected index=sats, got {:?}", entry),
+        };
+        let index = index
+            .trim()
+            .parse()
+            .context(format!("invalid input index in {:?}", entry))?;
+        let amount = amount
+            .trim()
+            .parse()
+            .context(format!("invalid amount in {:?}", entry))?;
+        values.insert(index, amount);
+    }
+    Ok(values)
+}
+
+/// The fee paid by the PSBT. Amounts the PSBT carries always win, `overrides`
+/// only fill in the inputs whose previous output is missing.
+pub fn compute_fee(psbt: &PartiallySignedTransaction, overrides: &InputValues) -> Result<FeeInfo> {
+    if let Some(index) = overrides.keys().find(|&&index| index >= psbt.inputs.len()) {
+        bail!(
+            "input {} does not exist, the PSBT has {}",
+            index,
+            psbt.inputs.len()
+        );
+    }
+
+    let mut input_total: u64 = 0;
+    let mut user_supplied = false;
+    let mut missing = Vec::new();
+    for index in 0..psbt.inputs.len() {
+        let value = match (spent_output(psbt, index), overrides.get(&index)) {
+            (Some(output), _) => output.value,
+            (None, Some(&value)) => {
+                user_supplied = true;
+                value
+            }
+            (None, None) => {
+                missing.push(index);
+                continue;
+            }
+        };
+        input_total = input_total
+            .checked_add(value)
+            .context("input amounts overflow")?;
+    }
+    if !missing.is_empty() {
+        bail!(
+            "missing amounts for inputs {:?}, enter them as index=sats",
+            missing
+        );
+    }
+
+    let output_total = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .try_fold(0u64, |total, output| total.checked_add(output.value))
+        .context("output amounts overflow")?;
+    let fee = match input_total.checked_sub(output_total) {
+        Some(fee) => fee,
+        None => bail!(
+            "inputs ({} sats) are less than outputs ({} sats), the fee would be negative",
+            input_total,
+            output_total
+        ),
+    };
+
+    Ok(FeeInfo {
+        fee,
+        input_total,
+        user_supplied,
+        vsize: finalized_vsize(psbt),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_signing::tests::psbt_spending;
+    use bitcoin::ScriptBuf;
+
+    fn amountless_psbt() -> PartiallySignedTransaction {
+        let mut psbt = psbt_spending(ScriptBuf::new());
+        psbt.inputs[0].witness_utxo = None;
+        psbt
+    }
+
+    #[test]
+    fn parse_input_values_accepts_index_amount_pairs() {
+        let values = parse_input_values(" 0=50000, 2 = 1200 ,").unwrap();
+        assert_eq!(values, InputValues::from([(0, 50000), (2, 1200)]));
+        assert!(parse_input_values("0:50000").is_err());
+    }
+
+    #[test]
+    fn absurd_fee_rates_are_refused() {
+        assert_eq!(parse_fee_rate(" 10000 ").unwrap(), MAX_FEE_RATE);
+        assert!(parse_fee_rate("2.5").is_err());
+        let err = parse_fee_rate("18446744073709551615").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "fee rate of 18446744073709551615 sat/vB is above the 10000 sat/vB limit, check for a typo"
+        );
+        assert_eq!(fee_at(141, 2).unwrap(), 282);
+        assert!(fee_at(u64::MAX, MAX_FEE_RATE).is_err());
+    }
+
+    #[test]
+    fn compute_fee_uses_supplied_amounts() {
+        let psbt = amountless_psbt();
+        assert!(compute_fee(&psbt, &InputValues::new()).is_err());
+
+        let fee = compute_fee(&psbt, &InputValues::from([(0, 1000)])).unwrap();
+        assert_eq!(
+            fee,
+            FeeInfo {
+                fee: 100,
+                input_total: 1000,
+                user_supplied: true,
+                vsize: None,
+            }
+        );
+        assert_eq!(fee.fee_rate(), None);
+        assert!(fee.is_high());
+
+        let fee = compute_fee(&psbt_spending(ScriptBuf::new()), &InputValues::new()).unwrap();
+        assert!(!fee.user_supplied);
+    }
+
+    #[test]
+    fn compute_fee_rejects_negative_fee() {
+        let psbt = amountless_psbt();
+        assert!(compute_fee(&psbt, &InputValues::from([(0, 899)])).is_err());
+        assert!(compute_fee(&psbt, &InputValues::from([(1, 1000)])).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::bs_share::data_dir;
+
+/// One signing ceremony this party took part in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SigningRecord {
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    /// Our own party index.
+    pub signer: u16,
+    /// Every party index that contributed to the signature.
+    pub parties: Vec<u16>,
+    pub address: String,
+    /// Set once the transaction is fully signed.
+    pub txid: Option<String>,
+}
+
+impl SigningRecord {
+    pub fn now(signer: u16, parties: Vec<u16>, address: String, txid: Option<String>) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            signer,
+            parties,
+            address,
+            txid,
+        }
+    }
+}
+
+/// The txid of a hex encoded raw transaction.
+pub fn raw_txid(raw_tx: &str) -> Option<String> {
+    let bytes = hex::decode(raw_tx).ok()?;
+    let tx: bitcoin::Transaction = bitcoin::consensus::encode::deserialize(&bytes).ok()?;
+    Some(tx.txid().to_string())
+}
+
+pub fn history_path() -> PathBuf {
+    data_dir().join("signing-history.jsonl")
+}
+
+/// Appends the record as one JSON line, so an interrupted write can only lose
+/// the last entry.
+pub fn append_record(path: &Path, record: &SigningRecord) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("cannot open signing history")?;
+    let mut line = serde_json::to_vec(record).context("serialize signing record")?;
+    line.push(b'\n');
+    file.write_all(&line).context("write signing history")
+}
+
+/// The recorded signings, oldest first. A missing file is an empty history.
+pub fn load_history(path: &Path) -> Result<Vec<SigningRecord>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("cannot read signing history"),
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line).context(format!("parse signing history line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_timestamp_is_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29 12:34:56 UTC");
+    }
+
+    #[test]
+    fn history_round_trips_through_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("boomersig-history-{}.jsonl", std::process::id()));
+        assert!(load_history(&path).unwrap().is_empty());
+
+        let first = SigningRecord::now(1, vec![1, 2], "address".into(), None);
+        let second = SigningRecord::now(2, vec![2, 3], "address".into(), Some("txid".into()));
+        append_record(&path, &first).unwrap();
+        append_record(&path, &second).unwrap();
+
+        assert_eq!(load_history(&path).unwrap(), vec![first, second]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::{Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use crate::bs_share::data_dir;
+
+/// How long the post-sign command may run before it is killed.
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Output kept per stream, so a chatty command can't flood the screen.
+const MAX_HOOK_OUTPUT: usize = 4096;
+
+#[derive(Debug)]
+pub struct HookOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl HookOutput {
+    /// The exit status followed by whatever the command printed.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Post-sign command exited with {}", self.status);
+        for output in [&self.stdout, &self.stderr] {
+            let output = output.trim();
+            if !output.is_empty() {
+                summary.push('\n');
+                summary.push_str(output);
+            }
+        }
+        summary
+    }
+}
+
+/// Runs the user's post-sign command with the txid as its last argument and
+/// the raw transaction hex on stdin.
+///
+/// The command is split on whitespace and run without a shell, with an empty
+/// environment apart from `PATH`, from the data directory.
+pub fn run_post_sign(
+    command: &str,
+    txid: &str,
+    raw_tx: &str,
+    wait: Duration,
+) -> Result<HookOutput> {
+    let mut words = command.split_whitespace();
+    let program = match words.next() {
+        Some(program) => program,
+        None => bail!("post-sign command is empty"),
+    };
+
+    let mut cmd = Command::new(program);
+    cmd.args(words)
+        .arg(txid)
+        .env_clear()
+        .current_dir(data_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(path) = std::env::var_os("PATH") {
+        cmd.env("PATH", path);
+    }
+    let mut child = cmd
+        .spawn()
+        .context(format!("cannot run post-sign command {:?}", program))?;
+
+    // Written from its own thread, a command that never reads stdin must not
+    // block us.
+    let mut stdin = child.stdin.take().context("post-sign command stdin")?;
+    let raw_tx = raw_tx.to_string();
+    thread::spawn(move || stdin.write_all(raw_tx.as_bytes()));
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let deadline = Instant::now() + wait;
+    let status = loop {
+        if let Some(status) = child.try_wait().context("wait for post-sign command")? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("post-sign command killed after {}s", wait.as_secs());
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    Ok(HookOutput {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(pipe) = pipe {
+            let _ = pipe.take(MAX_HOOK_OUTPUT as u64).read_to_end(&mut output);
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    #[test]
+    fn post_sign_command_gets_the_txid_and_hex() {
+        let output = run_post_sign("echo signed", "abcd", "0200", HOOK_TIMEOUT).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, "signed abcd\n");
+
+        let output = run_post_sign("sha256sum", "-", "0200", HOOK_TIMEOUT).unwrap();
+        assert!(output
+            .stdout
+            .starts_with(&hex::encode(sha2::Sha256::digest(b"0200"))));
+    }
+
+    #[test]
+    fn post_sign_command_is_killed_after_the_timeout() {
+        let err = run_post_sign("sleep", "5", "", Duration::from_millis(50)).unwrap_err();
+        assert!(err.to_string().contains("killed"));
+
+        let output = run_post_sign("false", "abcd", "", HOOK_TIMEOUT).unwrap();
+        assert!(!output.status.success());
+    }
+}
@@ -1,83 +1,604 @@
-use anyhow::{anyhow, Context, Result};
-use futures::StreamExt;
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use bitcoin::sighash::EcdsaSighashType;
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::Keygen;
 use round_based::async_runtime::AsyncProtocol;
+use round_based::Msg;
 
 use crate::{
-    bs_client::join_computation,
-    bs_signing::{do_sign, SigningConfig},
+    bs_address::{descriptor, parse_pubkey, AddressType, ScriptMultisig},
+    bs_client::{check_relay, join_computation, wait_in_lobby, RelayOptions},
+    bs_encryption::encrypt,
+    bs_error::BoomerError,
+    bs_progress::{ProgressHandle, Tracked},
+    bs_share::{
+        serialize_share, share_multisig, share_party_keys, share_public_key, share_vss_commitments,
+        write_share,
+    },
+    bs_signing::{do_sign, HashScheme, InputSelection, PhaseTimeouts, SigningConfig},
 };
 
+// Signed by the optional test round, it only has to be the same for every party.
+const TEST_SIGNATURE_MESSAGE: &str = "boomersig go brrrr";
+
 pub struct KeygenConfig {
     pub address: surf::Url,
+    pub relay_options: RelayOptions,
     pub room: String,
     pub output: PathBuf,
 
     pub index: u16,
     pub threshold: u16,
     pub number_of_parties: u16,
+    /// Run a signing round among all parties once the shares are written. Every
+    /// party must opt in and stay online until it completes.
+    pub test_signature: bool,
+    pub progress: ProgressHandle,
+    /// Recorded in the share, which can then only be used on this network.
+    pub network: bitcoin::Network,
+    /// Encrypts the share file, the plaintext is never written to disk.
+    pub passphrase: Option<String>,
+    /// Also reports the script multisig of the parties' share keys.
+    pub script_multisig: bool,
+    /// Of the test signature's stages.
+    pub timeouts: PhaseTimeouts,
+    /// Told about every round and message of the DKG as it runs. Events that
+    /// don't fit in the channel are dropped rather than stall the protocol.
+    pub events: Option<tokio::sync::mpsc::Sender<KeygenProgress>>,
+}
+
+/// What the DKG is up to, for a caller that wants more than `progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeygenProgress {
+    RoundStarted(u16),
+    MessageReceived {
+        from: u16,
+    },
+    /// The protocol produced our share, it is about to be written.
+    Completed,
+}
+
+/// The parameters every party must agree on before running the ceremony.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeygenParams {
+    pub threshold: u16,
+    pub number_of_parties: u16,
+    /// Recorded in every share, which is then only used on it.
+    pub network: bitcoin::Network,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ParamsAnnouncement {
+    index: u16,
+    params: KeygenParams,
 }
 
+/// Every party posts its parameters to the room and checks that the others
+/// configured the same ones, so a typo doesn't produce a broken share.
+async fn check_parameters(config: &KeygenConfig) -> Result<()> {
+    let params = KeygenParams {
+        threshold: config.threshold,
+        number_of_parties: config.number_of_parties,
+        network: config.network,
+    };
+
+    let (i, incoming, outgoing) = join_computation(
+        config.address.clone(),
+        &format!("{}-params", config.room),
+        &config.relay_options,
+    )
+    .await
+    .context("join parameters exchange")?;
+
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    outgoing
+        .send(Msg {
+            sender: i,
+            receiver: None,
+            body: ParamsAnnouncement {
+                index: config.index,
+                params: params.clone(),
+            },
+        })
+        .await?;
+
+    let announcements: Vec<ParamsAnnouncement> = incoming
+        .take(usize::from(config.number_of_parties).saturating_sub(1))
+        .map_ok(|msg| msg.body)
+        .try_collect()
+        .await?;
+
+    let mismatches = parameter_mismatches(&params, &announcements);
+    if !mismatches.is_empty() {
+        return Err(BoomerError::ParameterMismatch {
+            threshold: params.threshold,
+            number_of_parties: params.number_of_parties,
+            network: params.network,
+            mismatches,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// What each party that disagrees with `params` configured instead.
+fn parameter_mismatches(
+    params: &KeygenParams,
+    announcements: &[ParamsAnnouncement],
+) -> Vec<String> {
+    announcements
+        .iter()
+        .filter(|announcement| &announcement.params != params)
+        .map(|announcement| {
+            format!(
+                "party {} configured threshold {} of {} parties on {}",
+                announcement.index,
+                announcement.params.threshold,
+                announcement.params.number_of_parties,
+                announcement.params.network
+            )
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GroupKeyAnnouncement {
+    index: u16,
+    /// SHA-256 of the group key as the party derived it, in hex.
+    key_hash: String,
+}
+
+fn group_key_hash(public_key: &bitcoin::PublicKey) -> String {
+    hex::encode(Sha256::digest(&public_key.to_bytes()))
+}
+
+/// The indexes of the parties that announced another group key than ours.
+fn divergent_parties(key_hash: &str, announcements: &[GroupKeyAnnouncement]) -> Vec<u16> {
+    let mut parties: Vec<u16> = announcements
+        .iter()
+        .filter(|announcement| announcement.key_hash != key_hash)
+        .map(|announcement| announcement.index)
+        .collect();
+    parties.sort_unstable();
+    parties
+}
+
+/// Every party posts a hash of the group key it derived and checks that the
+/// others derived the same one, so a share that disagrees with the group's
+/// is never written.
+async fn check_group_key(config: &KeygenConfig, public_key: &bitcoin::PublicKey) -> Result<()> {
+    let key_hash = group_key_hash(public_key);
+    let (i, incoming, outgoing) = join_computation(
+        config.address.clone(),
+        &format!("{}-pubkey", config.room),
+        &config.relay_options,
+    )
+    .await
+    .context("join group key exchange")?;
+
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    outgoing
+        .send(Msg {
+            sender: i,
+            receiver: None,
+            body: GroupKeyAnnouncement {
+                index: config.index,
+                key_hash: key_hash.clone(),
+            },
+        })
+        .await?;
+
+    let announcements: Vec<GroupKeyAnnouncement> = incoming
+        .take(usize::from(config.number_of_parties).saturating_sub(1))
+        .map_ok(|msg| msg.body)
+        .try_collect()
+        .await?;
+
+    let parties = divergent_parties(&key_hash, &announcements);
+    if !parties.is_empty() {
+        return Err(BoomerError::GroupKeyMismatch {
+            pubkey: hex::encode(public_key.to_bytes()),
+            parties,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The outcome of one local readiness check.
 #[derive(Debug)]
-pub struct KeygenResult {
-    pubkey: String,
-    address: String,
-    out_dir: PathBuf,
+pub struct PreflightCheck {
+    pub name: &'static str,
+    pub result: std::result::Result<(), String>,
 }
 
-pub async fn do_keygen(config: KeygenConfig) -> Result<KeygenResult> {
-    let mut output_file = tokio::fs::OpenOptions::new()
+fn check(name: &'static str, result: Result<()>) -> PreflightCheck {
+    PreflightCheck {
+        name,
+        result: result.map_err(|e| format!("{:#}", e)),
+    }
+}
+
+fn check_parameters_locally(config: &KeygenConfig) -> Result<()> {
+    let (t, n, i) = (config.threshold, config.number_of_parties, config.index);
+    if n < 2 {
+        bail!("at least 2 parties are required, got {}", n);
+    }
+    // GG20 needs t+1 signers, so t must leave at least one party out.
+    if t < 1 || t >= n {
+        bail!("threshold must be between 1 and {}, got {}", n - 1, t);
+    }
+    if i < 1 || i > n {
+        bail!("index must be between 1 and {}, got {}", n, i);
+    }
+    Ok(())
+}
+
+fn check_output(output: &Path) -> Result<()> {
+    if output.exists() {
+        return Err(BoomerError::ShareExists {
+            path: output.to_owned(),
+        }
+        .into());
+    }
+    let dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        bail!("directory {} does not exist", dir.display());
+    }
+
+    let probe = dir.join(format!(".boomersig-probe-{}", std::process::id()));
+    std::fs::OpenOptions::new()
         .write(true)
         .create_new(true)
-        .open(&config.output)
-        .await
-        .context("cannot create output file")?;
+        .open(&probe)
+        .context(format!("directory {} is not writable", dir.display()))?;
+    std::fs::remove_file(&probe).context("remove write probe")
+}
 
-    let (_i, incoming, outgoing) = join_computation(config.address, &config.room)
-        .await
-        .context("join computation")?;
+/// Runs every check that doesn't need the other parties, so each of them can
+/// confirm their setup before coordinating the live ceremony.
+pub fn preflight(config: &KeygenConfig) -> Vec<PreflightCheck> {
+    vec![
+        check("parameters", check_parameters_locally(config)),
+        check("output file", check_output(&config.output)),
+        check(
+            "relay address",
+            check_relay(&config.address, &config.relay_options),
+        ),
+    ]
+}
+
+#[derive(Debug)]
+pub struct KeygenResult {
+    pub pubkey: String,
+    pub address: String,
+    pub out_dir: PathBuf,
+    pub tested: bool,
+    /// Signing takes `threshold + 1` of the `parties`.
+    pub threshold: u16,
+    pub parties: u16,
+    /// Each party's public share of `pubkey`, compressed and hex encoded, by
+    /// keygen index. GG20 keys have no chain code, there is no xpub to give.
+    pub party_pubkeys: Vec<String>,
+    pub vss_commitments: Vec<String>,
+    /// The p2wsh address and descriptor of the script multisig, when asked for.
+    pub multisig_address: Option<String>,
+    pub multisig_descriptor: Option<String>,
+}
+
+impl KeygenResult {
+    /// The group key's output descriptor, e.g. to watch the multisig in
+    /// Bitcoin Core or Sparrow. Descriptors of a bare key are the same on
+    /// every network.
+    pub fn descriptor(&self, address_type: AddressType) -> Result<String> {
+        Ok(descriptor(&parse_pubkey(&self.pubkey)?, address_type))
+    }
+}
+
+/// Signs a dummy message with the freshly written share and checks that the
+/// recovered key is the group key, proving the shares actually work together.
+async fn test_signature(config: &KeygenConfig, public_key: &bitcoin::PublicKey) -> Result<()> {
+    let args = SigningConfig {
+        room: format!("{}-test-signature", config.room),
+        address: config.address.clone(),
+        relay_options: config.relay_options.clone(),
+        parties: (1..=config.number_of_parties).collect(),
+        local_share: config.output.clone(),
+        data_to_sign: TEST_SIGNATURE_MESSAGE.into(),
+        transaction: false,
+        idx: config.index,
+        address_type: AddressType::default(),
+        network: config.network,
+        hash_scheme: HashScheme::default(),
+        sighash_type: EcdsaSighashType::All,
+        progress: config.progress.clone(),
+        save_offline_stage: None,
+        resume_offline_stage: None,
+        inputs: InputSelection::default(),
+        passphrase: config.passphrase.clone(),
+        script_multisig: false,
+        timeouts: config.timeouts,
+        dry_run: false,
+        rbf: false,
+        bip322: false,
+    };
+
+    let res = do_sign(args).await?;
+    if res.pubkey != hex::encode(public_key.to_bytes()) {
+        bail!(
+            "test signature was made by {}, not the group key",
+            res.pubkey
+        );
+    }
+    Ok(())
+}
+
+/// Moves an existing share out of the way of a new keygen, to
+/// `<share>.bak-<unix time>` next to it, and returns where it went. An existing
+/// backup is never replaced either.
+pub fn back_up_share(path: &Path) -> Result<PathBuf> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let mut backup = PathBuf::from(format!("{}.bak-{}", path.display(), now));
+    let mut n = 1;
+    while backup.exists() {
+        backup = PathBuf::from(format!("{}.bak-{}-{}", path.display(), now, n));
+        n += 1;
+    }
+    std::fs::rename(path, &backup).context(format!(
+        "cannot back up {} to {}",
+        path.display(),
+        backup.display()
+    ))?;
+    Ok(backup)
+}
+
+#[tracing::instrument(
+    skip(config),
+    fields(
+        room = %config.room,
+        party = config.index,
+        threshold = config.threshold,
+        parties = config.number_of_parties
+    ),
+    err
+)]
+pub async fn do_keygen(config: KeygenConfig) -> Result<KeygenResult> {
+    // Nothing is created at the path until the share is complete, however the
+    // ceremony ends. `write_share` still refuses a share that appeared since.
+    if config.output.exists() {
+        return Err(BoomerError::ShareExists {
+            path: config.output.clone(),
+        }
+        .into());
+    }
+
+    let parties: Vec<u16> = (1..=config.number_of_parties).collect();
+    wait_in_lobby(
+        config.address.clone(),
+        &config.room,
+        &config.relay_options,
+        config.index,
+        &parties,
+        config.number_of_parties,
+        &config.progress,
+    )
+    .await?;
+    check_parameters(&config).await?;
+
+    let (_i, incoming, outgoing) =
+        join_computation(config.address.clone(), &config.room, &config.relay_options)
+            .await
+            .context("join computation")?;
 
     let incoming = incoming.fuse();
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
     let keygen = Keygen::new(config.index, config.threshold, config.number_of_parties)?;
+    let keygen = Tracked::new(
+        keygen,
+        "keygen",
+        (1..=config.number_of_parties).collect(),
+        config.progress.clone(),
+    )
+    .with_events(config.events.clone());
     let output = AsyncProtocol::new(keygen, incoming, outgoing)
         .run()
         .await
-        .map_err(|e| anyhow!("protocol execution terminated with error: {}", e))?;
+        .map_err(|e| BoomerError::protocol_failure("keygen", e))?;
+    if let Some(events) = &config.events {
+        let _ = events.try_send(KeygenProgress::Completed);
+    }
 
-    let output = serde_json::to_vec_pretty(&output).context("serialize output")?;
-    tokio::io::copy(&mut output.as_slice(), &mut output_file)
-        .await
+    // The group key is part of the keygen output, no signing needed to learn
+    // it. The opt-in test signature only proves the shares work together.
+    let public_key = share_public_key(&output)?;
+    check_group_key(&config, &public_key).await?;
+
+    let mut serialized = serialize_share(&output, config.network)?;
+    if let Some(passphrase) = &config.passphrase {
+        serialized = encrypt(&serialized, passphrase)?;
+    }
+    write_share(&config.output, |file| file.write_all(&serialized))
         .context("save output to file")?;
+    tracing::info!(share = %config.output.display(), "share saved");
 
-    return Ok(KeygenResult {
-        address: Default::default(),
-        out_dir: Default::default(),
-        pubkey: Default::default(),
-    });
+    let address = AddressType::default()
+        .deriver()
+        .derive(&public_key, config.network)?
+        .to_string();
+    if config.test_signature {
+        test_signature(&config, &public_key)
+            .await
+            .context("keygen completed but the test signature failed, do not fund this address")?;
+    }
 
-    /*
-    let args = SigningConfig {
-        room: "default-signing".into(),
-        address: "http://127.0.0.1:8000".parse()?,
-        parties: vec![1, 2],
-        local_share: config.output,
-        data_to_sign: "boomersig go brrrr".into(),
-        transaction: false,
-        idx: config.index,
+    let multisig = match config.script_multisig {
+        true => Some(share_multisig(&output)?),
+        false => None,
     };
 
-    tokio::time::sleep(std::time::Duration::from_secs(15)).await;
-
-    let res = do_sign(args.clone()).await?;
-
     Ok(KeygenResult {
-        pubkey: res.pubkey,
-        address: res.address,
-        out_dir: res.out_dir,
+        address,
+        pubkey: hex::encode(public_key.to_bytes()),
+        out_dir: config.output,
+        tested: config.test_signature,
+        threshold: output.t,
+        parties: output.n,
+        party_pubkeys: share_party_keys(&output)?
+            .iter()
+            .map(|key| hex::encode(key.to_bytes()))
+            .collect(),
+        vss_commitments: share_vss_commitments(&output),
+        multisig_address: multisig
+            .as_ref()
+            .map(|multisig| multisig.address(config.network).to_string()),
+        multisig_descriptor: multisig.as_ref().map(ScriptMultisig::descriptor),
     })
-    */
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(threshold: u16, number_of_parties: u16, index: u16) -> KeygenConfig {
+        KeygenConfig {
+            address: "http://127.0.0.1:8000".parse().unwrap(),
+            relay_options: RelayOptions::default(),
+            room: "default-keygen".into(),
+            output: std::env::temp_dir().join(format!(
+                "boomersig-preflight-{}-{}.json",
+                std::process::id(),
+                index
+            )),
+            index,
+            threshold,
+            number_of_parties,
+            test_signature: false,
+            progress: ProgressHandle::default(),
+            network: bitcoin::Network::Signet,
+            passphrase: None,
+            script_multisig: false,
+            timeouts: PhaseTimeouts::default(),
+            events: None,
+        }
+    }
+
+    fn failed(checks: &[PreflightCheck]) -> Vec<&'static str> {
+        checks
+            .iter()
+            .filter(|check| check.result.is_err())
+            .map(|check| check.name)
+            .collect()
+    }
+
+    #[test]
+    fn back_up_share_never_replaces_a_backup() {
+        let path = config(1, 3, 8).output;
+        std::fs::write(&path, "first").unwrap();
+        let first = back_up_share(&path).unwrap();
+        std::fs::write(&path, "second").unwrap();
+        let second = back_up_share(&path).unwrap();
+
+        assert!(!path.exists());
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "first");
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "second");
+        std::fs::remove_file(first).unwrap();
+        std::fs::remove_file(second).unwrap();
+    }
+
+    #[test]
+    fn preflight_passes_a_valid_setup() {
+        assert!(failed(&preflight(&config(1, 3, 2))).is_empty());
+    }
+
+    #[test]
+    fn preflight_reports_each_failing_check() {
+        assert_eq!(failed(&preflight(&config(3, 3, 1))), vec!["parameters"]);
+        assert_eq!(failed(&preflight(&config(1, 3, 4))), vec!["parameters"]);
+
+        let mut existing = config(1, 3, 1);
+        existing.output = std::env::temp_dir();
+        existing.address = "ftp://relay.example".parse().unwrap();
+        assert_eq!(
+            failed(&preflight(&existing)),
+            vec!["output file", "relay address"]
+        );
+    }
+
+    #[test]
+    fn parties_announcing_another_group_key_are_named() {
+        let ours = group_key_hash(
+            &parse_pubkey("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap(),
+        );
+        let announce = |index: u16, key_hash: &str| GroupKeyAnnouncement {
+            index,
+            key_hash: key_hash.into(),
+        };
+
+        assert!(divergent_parties(&ours, &[announce(1, &ours), announce(3, &ours)]).is_empty());
+        assert_eq!(
+            divergent_parties(
+                &ours,
+                &[announce(4, "00"), announce(1, &ours), announce(2, "00")]
+            ),
+            vec![2, 4]
+        );
+    }
+
+    #[test]
+    fn parties_configured_differently_are_named() {
+        let params = KeygenParams {
+            threshold: 1,
+            number_of_parties: 3,
+            network: bitcoin::Network::Signet,
+        };
+        let announce = |index: u16, params: KeygenParams| ParamsAnnouncement { index, params };
+
+        assert!(parameter_mismatches(&params, &[announce(2, params.clone())]).is_empty());
+        assert_eq!(
+            parameter_mismatches(
+                &params,
+                &[
+                    announce(
+                        2,
+                        KeygenParams {
+                            threshold: 2,
+                            ..params.clone()
+                        }
+                    ),
+                    announce(3, params.clone()),
+                    announce(
+                        4,
+                        KeygenParams {
+                            network: bitcoin::Network::Testnet,
+                            ..params.clone()
+                        }
+                    ),
+                ]
+            ),
+            vec![
+                "party 2 configured threshold 2 of 3 parties on signet",
+                "party 4 configured threshold 1 of 3 parties on testnet",
+            ]
+        );
+    }
 }
@@ -0,0 +1,41 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use tracing_subscriber::EnvFilter;
+
+use crate::bs_share::ensure_data_dir;
+
+/// Turns logging on, with the usual `tracing` filter syntax, e.g.
+/// `RUST_LOG=boomersig=debug`.
+pub const LOG_FILTER_ENV: &str = "RUST_LOG";
+
+pub fn log_path(dir: &Path) -> PathBuf {
+    dir.join("boomersig.log")
+}
+
+/// Appends logs to `boomersig.log` in `dir` when `RUST_LOG` is set, and logs
+/// nothing otherwise. Never to stdout or stderr, which belong to the UI and to
+/// the JSON results of the headless commands.
+pub fn init_logging(dir: &Path) -> Result<()> {
+    let filter = match std::env::var(LOG_FILTER_ENV) {
+        Ok(filter) if !filter.trim().is_empty() => filter,
+        _ => return Ok(()),
+    };
+    let filter = EnvFilter::try_new(&filter).context(format!("invalid {}", LOG_FILTER_ENV))?;
+
+    ensure_data_dir(dir)?;
+    let path = log_path(dir);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context(format!("cannot open log file {}", path.display()))?;
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(Mutex::new(file))
+        .with_ansi(false)
+        .try_init()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("install logger")
+}
@@ -0,0 +1,149 @@
+//! A stand-in for `bs_server` in tests, running in-process on a free local
+//! port: the same three endpoints over plain HTTP/1.1, with every room kept
+//! in memory for as long as the test runs.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+#[derive(Default)]
+struct Room {
+    messages: Vec<String>,
+    issued: u16,
+}
+
+#[derive(Default)]
+struct Rooms {
+    rooms: Mutex<HashMap<String, Room>>,
+    published: Condvar,
+}
+
+pub struct MockRelay {
+    pub url: surf::Url,
+}
+
+impl MockRelay {
+    /// Serves every connection on a thread of its own, the relay lives until
+    /// the test process exits.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock relay");
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let rooms = Arc::new(Rooms::default());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let rooms = rooms.clone();
+                thread::spawn(move || serve(stream, &rooms));
+            }
+        });
+        Self { url }
+    }
+}
+
+/// Answers the requests of one keep-alive connection until the client hangs
+/// up, or hands it over to a subscription for good.
+fn serve(stream: TcpStream, rooms: &Rooms) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+        let mut content_length = 0;
+        let mut last_event = None;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                    "last-event-id" => last_event = value.trim().parse::<usize>().ok(),
+                    _ => {}
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let mut parts = request_line.split_whitespace();
+        let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let route = path
+            .strip_prefix("/rooms/")
+            .and_then(|rest| rest.split_once('/'));
+        match (method, route) {
+            ("POST", Some((room, "issue_unique_idx"))) => {
+                let mut rooms = rooms.rooms.lock().unwrap();
+                let room = rooms.entry(room.to_string()).or_default();
+                room.issued += 1;
+                let issued = format!(r#"{{"unique_idx":{}}}"#, room.issued);
+                drop(rooms);
+                respond(&mut writer, "200 OK", &issued)?;
+            }
+            ("POST", Some((room, "broadcast"))) => {
+                let message = String::from_utf8_lossy(&body).into_owned();
+                rooms
+                    .rooms
+                    .lock()
+                    .unwrap()
+                    .entry(room.to_string())
+                    .or_default()
+                    .messages
+                    .push(message);
+                rooms.published.notify_all();
+                respond(&mut writer, "200 OK", "")?;
+            }
+            ("GET", Some((room, "subscribe"))) => {
+                let next = last_event.map_or(0, |id| id + 1);
+                return subscribe(writer, rooms, room, next);
+            }
+            _ => respond(&mut writer, "404 Not Found", "")?,
+        }
+    }
+}
+
+fn respond(writer: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+/// Streams the messages of `room` from the `next`th on as server-sent events,
+/// replaying the earlier ones like the relay does. Chunked, since the stream
+/// has no length, until the client hangs up.
+fn subscribe(mut writer: TcpStream, rooms: &Rooms, room: &str, mut next: usize) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nTransfer-Encoding: chunked\r\n\r\n"
+    )?;
+    writer.flush()?;
+    loop {
+        let mut guard = rooms.rooms.lock().unwrap();
+        let pending = loop {
+            let messages = &guard.entry(room.to_string()).or_default().messages;
+            if messages.len() > next {
+                break messages[next..].to_vec();
+            }
+            guard = rooms.published.wait(guard).unwrap();
+        };
+        drop(guard);
+        for message in pending {
+            let event = format!("event: new-message\nid: {}\ndata: {}\n\n", next, message);
+            write!(writer, "{:x}\r\n{}\r\n", event.len(), event)?;
+            next += 1;
+        }
+        writer.flush()?;
+    }
+}
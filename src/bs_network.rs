@@ -0,0 +1,155 @@
+use anyhow::{bail, Result};
+use bitcoin::bip32::{ChildNumber, DerivationPath, KeySource};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::Network;
+
+use crate::bs_error::BoomerError;
+
+/// The network used unless the config picks another one, and the one every
+/// share written before shares recorded their network was made for.
+pub const DEFAULT_NETWORK: Network = Network::Signet;
+
+/// The mempool.space esplora API serving `network`, used to look up UTXOs and
+/// to broadcast.
+pub fn esplora_url(network: Network) -> Result<&'static str> {
+    match network {
+        Network::Bitcoin => Ok("https://mempool.space/api"),
+        Network::Testnet => Ok("https://mempool.space/testnet/api"),
+        Network::Signet => Ok("https://mempool.space/signet/api"),
+        _ => bail!("no public esplora serves {}, use your own node", network),
+    }
+}
+
+// BIP 44/49/84/86 and BIP 48 multisig paths, all `purpose'/coin_type'/...`.
+const PURPOSES_WITH_COIN_TYPE: [u32; 5] = [44, 48, 49, 84, 86];
+
+/// Scripts don't say which network they are for, but a PSBT carries other
+/// hints: the version of its global xpubs and the BIP 44 coin type of every
+/// derivation path. Fails on the first hint that disagrees with `network`.
+pub fn check_psbt_network(
+    psbt: &PartiallySignedTransaction,
+    network: Network,
+) -> Result<(), BoomerError> {
+    let mut hints = Vec::new();
+    for (xpub, _) in &psbt.xpub {
+        hints.push((format!("global xpub {}", xpub.fingerprint()), xpub.network));
+    }
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let sources = input
+            .bip32_derivation
+            .values()
+            .chain(input.tap_key_origins.values().map(|(_, source)| source));
+        hints.extend(path_hints(&format!("input {}", index), sources));
+    }
+    for (index, output) in psbt.outputs.iter().enumerate() {
+        let sources = output
+            .bip32_derivation
+            .values()
+            .chain(output.tap_key_origins.values().map(|(_, source)| source));
+        hints.extend(path_hints(&format!("output {}", index), sources));
+    }
+
+    match hints
+        .into_iter()
+        .find(|(_, found)| is_mainnet(*found) != is_mainnet(network))
+    {
+        Some((location, found)) => Err(BoomerError::NetworkMismatch {
+            location,
+            found: network_kind(found),
+            expected: network,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn path_hints<'a>(
+    location: &str,
+    sources: impl Iterator<Item = &'a KeySource>,
+) -> Vec<(String, Network)> {
+    sources
+        .filter_map(|(_, path)| {
+            coin_type_network(path).map(|network| (format!("{} path {}", location, path), network))
+        })
+        .collect()
+}
+
+fn coin_type_network(path: &DerivationPath) -> Option<Network> {
+    match path.as_ref() {
+        [ChildNumber::Hardened { index: purpose }, ChildNumber::Hardened { index: coin_type }, ..]
+            if PURPOSES_WITH_COIN_TYPE.contains(purpose) =>
+        {
+            match coin_type {
+                0 => Some(Network::Bitcoin),
+                1 => Some(Network::Testnet),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Testnet, signet and regtest share xpub versions and coin type, so they
+/// can't be told apart.
+fn is_mainnet(network: Network) -> bool {
+    network == Network::Bitcoin
+}
+
+fn network_kind(network: Network) -> &'static str {
+    match is_mainnet(network) {
+        true => "mainnet",
+        false => "a test network",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_signing::tests::psbt_spending;
+    use bitcoin::bip32::Fingerprint;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::ScriptBuf;
+    use std::str::FromStr;
+
+    fn with_path(path: &str, output: bool) -> PartiallySignedTransaction {
+        let mut psbt = psbt_spending(ScriptBuf::new());
+        let key = SecretKey::from_slice(&[1; 32])
+            .unwrap()
+            .public_key(&Secp256k1::new());
+        let source = (
+            Fingerprint::default(),
+            DerivationPath::from_str(path).unwrap(),
+        );
+        match output {
+            true => psbt.outputs[0].bip32_derivation.insert(key, source),
+            false => psbt.inputs[0].bip32_derivation.insert(key, source),
+        };
+        psbt
+    }
+
+    #[test]
+    fn testnet_paths_pass_for_signet() {
+        assert!(check_psbt_network(&with_path("m/84'/1'/0'/0/3", false), Network::Signet).is_ok());
+        // Not a BIP 44 style path, so no hint either way.
+        assert!(check_psbt_network(&with_path("m/0/0", true), Network::Signet).is_ok());
+        assert!(check_psbt_network(&with_path("m/84'/0'/0'/0/3", false), Network::Bitcoin).is_ok());
+    }
+
+    #[test]
+    fn mainnet_output_is_reported() {
+        let err =
+            check_psbt_network(&with_path("m/44'/0'/0'/1/0", true), Network::Signet).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "output 0 path m/44'/0'/0'/1/0 belongs to mainnet, but signing is for signet"
+        );
+    }
+
+    #[test]
+    fn regtest_has_no_public_esplora() {
+        assert_eq!(
+            esplora_url(Network::Testnet).unwrap(),
+            "https://mempool.space/testnet/api"
+        );
+        assert!(esplora_url(Network::Regtest).is_err());
+    }
+}
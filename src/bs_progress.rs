@@ -0,0 +1,488 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use round_based::{Msg, StateMachine};
+use tokio::sync::mpsc::Sender;
+
+use crate::bs_keygen::KeygenProgress;
+
+/// What the local party is doing in a ceremony.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Activity {
+    NotStarted,
+    /// Blocked on messages, from the listed parties if some are known to be
+    /// behind the others, from every other party otherwise.
+    Waiting {
+        parties: Vec<u16>,
+    },
+    Computing,
+    /// Gathering in the lobby before the protocol starts. `expected` are the
+    /// parties taking part out of the group's `parties`.
+    Lobby {
+        present: Vec<u16>,
+        expected: Vec<u16>,
+        parties: u16,
+    },
+}
+
+/// How a party shows in the lobby: there, still awaited, or sitting this
+/// ceremony out.
+pub fn lobby_mark(party: u16, present: &[u16], expected: &[u16]) -> char {
+    if present.contains(&party) {
+        '✓'
+    } else if expected.contains(&party) {
+        '…'
+    } else {
+        '✗'
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub stage: &'static str,
+    pub round: u16,
+    pub total_rounds: Option<u16>,
+    pub activity: Activity,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            stage: "ceremony",
+            round: 0,
+            total_rounds: None,
+            activity: Activity::NotStarted,
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rounds = match self.total_rounds {
+            Some(total) => format!("{}/{}", self.round, total),
+            None => self.round.to_string(),
+        };
+        match &self.activity {
+            Activity::NotStarted => write!(f, "{} not started", self.stage),
+            Activity::Waiting { parties } if parties.is_empty() => write!(
+                f,
+                "{}: waiting for the other parties (round {})",
+                self.stage, rounds
+            ),
+            Activity::Waiting { parties } => write!(
+                f,
+                "{}: waiting for party {} (round {})",
+                self.stage,
+                parties
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                rounds
+            ),
+            Activity::Computing => write!(f, "{}: computing round {}...", self.stage, rounds),
+            Activity::Lobby {
+                present,
+                expected,
+                parties,
+            } => write!(
+                f,
+                "{}: {}",
+                self.stage,
+                (1..=*parties)
+                    .map(|party| format!(
+                        "party {} {}",
+                        party,
+                        lobby_mark(party, present, expected)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Shared between a running ceremony and whoever reports on it, who can also
+/// ask it to leave the lobby without waiting for everyone.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressHandle {
+    progress: Arc<Mutex<Progress>>,
+    start_now: Arc<AtomicBool>,
+}
+
+impl ProgressHandle {
+    pub fn get(&self) -> Progress {
+        self.progress.lock().map(|p| p.clone()).unwrap_or_default()
+    }
+
+    pub fn set(&self, progress: Progress) {
+        if let Ok(mut current) = self.progress.lock() {
+            *current = progress;
+        }
+    }
+
+    pub fn start_now(&self) {
+        self.start_now.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `start_now` was called since the last call, which it resets.
+    pub fn take_start_now(&self) -> bool {
+        self.start_now.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Wraps a protocol state machine to report whether it is blocked on incoming
+/// messages or busy computing the next round.
+#[derive(Debug)]
+pub struct Tracked<SM> {
+    inner: SM,
+    stage: &'static str,
+    /// Party labels, by protocol index starting at 1.
+    parties: Vec<u16>,
+    received: BTreeMap<u16, usize>,
+    progress: ProgressHandle,
+    /// Logs of the current round, `span_round` is the round it was opened for.
+    round_span: tracing::Span,
+    span_round: u16,
+    events: Option<Sender<KeygenProgress>>,
+}
+
+impl<SM: StateMachine> Tracked<SM> {
+    /// `parties` are the labels shown for protocol indexes 1, 2..., e.g. the
+    /// keygen indexes of the signers in a signing ceremony.
+    pub fn new(
+        inner: SM,
+        stage: &'static str,
+        parties: Vec<u16>,
+        progress: ProgressHandle,
+    ) -> Self {
+        let tracked = Self {
+            inner,
+            stage,
+            parties,
+            received: BTreeMap::new(),
+            progress,
+            round_span: tracing::Span::none(),
+            span_round: 0,
+            events: None,
+        };
+        tracked.report(Activity::Waiting {
+            parties: Vec::new(),
+        });
+        tracked
+    }
+
+    /// Also sends the rounds and messages to `events`, if any.
+    pub fn with_events(mut self, events: Option<Sender<KeygenProgress>>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Best effort, a full channel must not hold the protocol up.
+    fn send_event(&self, event: KeygenProgress) {
+        if let Some(events) = &self.events {
+            let _ = events.try_send(event);
+        }
+    }
+
+    /// The span of the round the protocol is in, opened when it starts.
+    fn round_span(&mut self) -> tracing::Span {
+        let round = self.inner.current_round();
+        if round != self.span_round {
+            self.send_event(KeygenProgress::RoundStarted(round));
+            self.span_round = round;
+            self.round_span = tracing::info_span!(
+                "round",
+                stage = self.stage,
+                round,
+                total = ?self.inner.total_rounds()
+            );
+            tracing::info!(parent: &self.round_span, "round started");
+        }
+        self.round_span.clone()
+    }
+
+    fn report(&self, activity: Activity) {
+        self.progress.set(Progress {
+            stage: self.stage,
+            round: self.inner.current_round(),
+            total_rounds: self.inner.total_rounds(),
+            activity,
+        });
+    }
+
+    /// Every round each party sends us the same number of messages, so the
+    /// parties that sent fewer than the others are the ones holding us up.
+    fn lagging_parties(&self) -> Vec<u16> {
+        let others: Vec<u16> = (1..=self.inner.parties())
+            .filter(|&index| index != self.inner.party_ind())
+            .collect();
+        let count = |index: &u16| self.received.get(index).copied().unwrap_or(0);
+        let most = others.iter().map(count).max().unwrap_or(0);
+        others
+            .iter()
+            .filter(|index| count(index) < most)
+            .map(|&index| self.label(index))
+            .collect()
+    }
+
+    fn label(&self, index: u16) -> u16 {
+        self.parties
+            .get(usize::from(index).wrapping_sub(1))
+            .copied()
+            .unwrap_or(index)
+    }
+
+    fn report_waiting(&self) {
+        if !self.inner.is_finished() && !self.inner.wants_to_proceed() {
+            self.report(Activity::Waiting {
+                parties: self.lagging_parties(),
+            });
+        }
+    }
+}
+
+impl<SM: StateMachine> StateMachine for Tracked<SM> {
+    type MessageBody = SM::MessageBody;
+    type Err = SM::Err;
+    type Output = SM::Output;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<(), Self::Err> {
+        let span = self.round_span();
+        let _entered = span.enter();
+        tracing::debug!(sender = msg.sender, "handling message");
+        *self.received.entry(msg.sender).or_insert(0) += 1;
+        self.send_event(KeygenProgress::MessageReceived {
+            from: self.label(msg.sender),
+        });
+        let result = self.inner.handle_incoming(msg);
+        self.report_waiting();
+        result
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        self.inner.message_queue()
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        self.inner.wants_to_proceed()
+    }
+
+    fn proceed(&mut self) -> Result<(), Self::Err> {
+        let span = self.round_span();
+        let _entered = span.enter();
+        tracing::debug!("computing");
+        self.report(Activity::Computing);
+        let result = self.inner.proceed();
+        self.report_waiting();
+        result
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        self.inner.round_timeout()
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        self.inner.round_timeout_reached()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output, Self::Err>> {
+        self.inner.pick_output()
+    }
+
+    fn current_round(&self) -> u16 {
+        self.inner.current_round()
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        self.inner.total_rounds()
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.inner.party_ind()
+    }
+
+    fn parties(&self) -> u16 {
+        self.inner.parties()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs one message from every other party to finish its single round.
+    #[derive(Debug)]
+    struct OneRound {
+        received: usize,
+        queue: Vec<Msg<()>>,
+    }
+
+    impl StateMachine for OneRound {
+        type MessageBody = ();
+        type Err = std::convert::Infallible;
+        type Output = ();
+
+        fn handle_incoming(&mut self, _msg: Msg<()>) -> Result<(), Self::Err> {
+            self.received += 1;
+            Ok(())
+        }
+
+        fn message_queue(&mut self) -> &mut Vec<Msg<()>> {
+            &mut self.queue
+        }
+
+        fn wants_to_proceed(&self) -> bool {
+            false
+        }
+
+        fn proceed(&mut self) -> Result<(), Self::Err> {
+            Ok(())
+        }
+
+        fn round_timeout(&self) -> Option<Duration> {
+            None
+        }
+
+        fn round_timeout_reached(&mut self) -> Self::Err {
+            unreachable!()
+        }
+
+        fn is_finished(&self) -> bool {
+            self.received == 3
+        }
+
+        fn pick_output(&mut self) -> Option<Result<(), Self::Err>> {
+            None
+        }
+
+        fn current_round(&self) -> u16 {
+            1
+        }
+
+        fn total_rounds(&self) -> Option<u16> {
+            Some(1)
+        }
+
+        fn party_ind(&self) -> u16 {
+            1
+        }
+
+        fn parties(&self) -> u16 {
+            4
+        }
+    }
+
+    #[test]
+    fn tracked_reports_parties_behind_the_others() {
+        let progress = ProgressHandle::default();
+        let inner = OneRound {
+            received: 0,
+            queue: Vec::new(),
+        };
+        let mut tracked = Tracked::new(inner, "keygen", vec![1, 3, 5, 7], progress.clone());
+        assert_eq!(
+            progress.get().activity,
+            Activity::Waiting {
+                parties: Vec::new()
+            }
+        );
+
+        let msg = |sender| Msg {
+            sender,
+            receiver: None,
+            body: (),
+        };
+        tracked.handle_incoming(msg(2)).unwrap();
+        tracked.handle_incoming(msg(4)).unwrap();
+        // Protocol index 3 is the party with keygen index 5.
+        assert_eq!(
+            progress.get().activity,
+            Activity::Waiting { parties: vec![5] }
+        );
+    }
+
+    #[test]
+    fn tracked_sends_rounds_and_messages() {
+        let (events, mut received) = tokio::sync::mpsc::channel(2);
+        let inner = OneRound {
+            received: 0,
+            queue: Vec::new(),
+        };
+        let mut tracked =
+            Tracked::new(inner, "keygen", vec![1, 3, 5, 7], ProgressHandle::default())
+                .with_events(Some(events));
+
+        let msg = |sender| Msg {
+            sender,
+            receiver: None,
+            body: (),
+        };
+        tracked.handle_incoming(msg(2)).unwrap();
+        assert_eq!(received.try_recv(), Ok(KeygenProgress::RoundStarted(1)));
+        assert_eq!(
+            received.try_recv(),
+            Ok(KeygenProgress::MessageReceived { from: 3 })
+        );
+
+        // Nobody reads the channel, the protocol goes on without the events.
+        for sender in [3, 4, 2] {
+            tracked.handle_incoming(msg(sender)).unwrap();
+        }
+        assert_eq!(
+            received.try_recv(),
+            Ok(KeygenProgress::MessageReceived { from: 5 })
+        );
+        assert_eq!(
+            received.try_recv(),
+            Ok(KeygenProgress::MessageReceived { from: 7 })
+        );
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn progress_describes_what_blocks_the_ceremony() {
+        let mut progress = Progress {
+            stage: "offline stage",
+            round: 2,
+            total_rounds: Some(6),
+            activity: Activity::Waiting { parties: vec![3] },
+        };
+        assert_eq!(
+            progress.to_string(),
+            "offline stage: waiting for party 3 (round 2/6)"
+        );
+
+        progress.activity = Activity::Waiting {
+            parties: Vec::new(),
+        };
+        assert_eq!(
+            progress.to_string(),
+            "offline stage: waiting for the other parties (round 2/6)"
+        );
+
+        progress.activity = Activity::Computing;
+        assert_eq!(
+            progress.to_string(),
+            "offline stage: computing round 2/6..."
+        );
+
+        progress.stage = "lobby";
+        progress.activity = Activity::Lobby {
+            present: vec![1],
+            expected: vec![1, 2],
+            parties: 3,
+        };
+        assert_eq!(
+            progress.to_string(),
+            "lobby: party 1 ✓, party 2 …, party 3 ✗"
+        );
+    }
+}
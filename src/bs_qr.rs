@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bitcoin::psbt::PartiallySignedTransaction;
+use qrcode::{Color, EcLevel, QrCode};
+
+use crate::bs_signing::parse_psbt;
+
+/// Characters of data in each part of an animated QR code, few enough for a
+/// part to stay a version 4 code, which fits an 80x24 terminal.
+pub const PART_CHARS: usize = 70;
+/// How long each part of an animated QR code stays on screen.
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(800);
+const QUIET_ZONE: usize = 2;
+/// The UR types a PSBT is scanned as, BCR-2020-006's `crypto-psbt` and its
+/// newer name.
+const UR_TYPES: [&str; 2] = ["crypto-psbt", "psbt"];
+
+/// Splits `data` into the parts of an animated QR code, in the `pMofN` format
+/// Specter and other air-gapped signers read: `p1of3 <first chunk>`, ... Data
+/// that fits a single part is left as it is, so an address stays scannable by
+/// any wallet.
+pub fn qr_parts(data: &str) -> Vec<String> {
+    let chars: Vec<char> = data.chars().collect();
+    if chars.len() <= PART_CHARS {
+        return vec![data.to_string()];
+    }
+    let chunks: Vec<&[char]> = chars.chunks(PART_CHARS).collect();
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            format!(
+                "p{}of{} {}",
+                i + 1,
+                chunks.len(),
+                chunk.iter().collect::<String>()
+            )
+        })
+        .collect()
+}
+
+/// Draws `data` as a QR code with its quiet zone, in half-block characters
+/// holding two rows of modules each. Light modules are the filled halves, so
+/// the code has to be shown light on dark.
+pub fn qr_lines(data: &str) -> Result<Vec<String>> {
+    let code = QrCode::with_error_correction_level(data, EcLevel::L)
+        .context("data is too long for a qr code")?;
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = width + 2 * QUIET_ZONE;
+    let light = |x: usize, y: usize| {
+        let inside = |i: usize| (QUIET_ZONE..width + QUIET_ZONE).contains(&i);
+        !inside(x)
+            || !inside(y)
+            || colors[(y - QUIET_ZONE) * width + x - QUIET_ZONE] == Color::Light
+    };
+    Ok((0..size)
+        .step_by(2)
+        .map(|y| {
+            (0..size)
+                .map(|x| match (light(x, y), y + 1 < size && light(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Reassembles a PSBT out of scanned QR codes given in any order, repeats
+/// included: `ur:crypto-psbt` fragments, or the `pMofN` parts of `qr_parts`.
+#[derive(Default)]
+pub struct PsbtAssembler {
+    parts: Option<Parts>,
+    psbt: Option<PartiallySignedTransaction>,
+}
+
+impl fmt::Debug for PsbtAssembler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PsbtAssembler")
+            .field("progress", &self.progress())
+            .field("complete", &self.psbt.is_some())
+            .finish()
+    }
+}
+
+enum Parts {
+    Ur {
+        decoder: ur::Decoder,
+        seen: BTreeSet<String>,
+        fragments: usize,
+    },
+    Numbered {
+        total: usize,
+        chunks: BTreeMap<usize, String>,
+    },
+}
+
+impl PsbtAssembler {
+    /// Adds one scanned part, returning whether the PSBT is complete.
+    pub fn receive(&mut self, part: &str) -> Result<bool> {
+        if self.psbt.is_none() {
+            let part = part.trim();
+            // Scanners return the uppercase of QR codes' alphanumeric mode.
+            match part.get(..3) {
+                Some(scheme) if scheme.eq_ignore_ascii_case("ur:") => {
+                    self.receive_ur(&part.to_ascii_lowercase())?
+                }
+                _ => self.receive_numbered(part)?,
+            }
+        }
+        Ok(self.psbt.is_some())
+    }
+
+    /// Distinct parts received so far, out of how many the first one
+    /// announced. A fountain-coded UR may need more parts than that.
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        match &self.parts {
+            Some(Parts::Ur {
+                seen, fragments, ..
+            }) => Some((seen.len(), *fragments)),
+            Some(Parts::Numbered { total, chunks }) => Some((chunks.len(), *total)),
+            None => None,
+        }
+    }
+
+    pub fn psbt(&self) -> Option<&PartiallySignedTransaction> {
+        self.psbt.as_ref()
+    }
+
+    fn receive_ur(&mut self, part: &str) -> Result<()> {
+        let path: Vec<&str> = part["ur:".len()..].split('/').collect();
+        if !UR_TYPES.contains(&path[0]) {
+            bail!("expected a ur:crypto-psbt part, got ur:{}", path[0]);
+        }
+        let fragments = match path.len() {
+            // A whole PSBT in a single code.
+            2 => {
+                let (_, cbor) = ur::decode(part).map_err(|e| anyhow!("invalid ur: {:?}", e))?;
+                return self.complete_ur(&cbor);
+            }
+            3 => fragment_count(path[1])?,
+            _ => bail!("invalid ur part {:?}", part),
+        };
+
+        let parts = self.parts.get_or_insert_with(|| Parts::Ur {
+            decoder: ur::Decoder::default(),
+            seen: BTreeSet::new(),
+            fragments,
+        });
+        let message = match parts {
+            Parts::Ur {
+                decoder,
+                seen,
+                fragments: expected,
+            } => {
+                if fragments != *expected {
+                    bail!(
+                        "part of another psbt, of {} fragments instead of {}",
+                        fragments,
+                        expected
+                    );
+                }
+                if !seen.insert(part.to_string()) {
+                    return Ok(());
+                }
+                decoder
+                    .receive(part)
+                    .map_err(|e| anyhow!("invalid ur part: {:?}", e))?;
+                match decoder.complete() {
+                    true => decoder
+                        .message()
+                        .map_err(|e| anyhow!("cannot reassemble ur: {:?}", e))?,
+                    false => None,
+                }
+            }
+            Parts::Numbered { .. } => bail!("ur part among pMofN parts"),
+        };
+        match message {
+            Some(cbor) => self.complete_ur(&cbor),
+            None => Ok(()),
+        }
+    }
+
+    fn complete_ur(&mut self, cbor: &[u8]) -> Result<()> {
+        let psbt = PartiallySignedTransaction::deserialize(cbor_bytes(cbor)?)
+            .context("scanned psbt is invalid")?;
+        self.psbt = Some(psbt);
+        Ok(())
+    }
+
+    fn receive_numbered(&mut self, part: &str) -> Result<()> {
+        let (index, total, chunk) =
+            parse_numbered(part).context("not a ur:crypto-psbt or pMofN part")?;
+        let parts = self.parts.get_or_insert_with(|| Parts::Numbered {
+            total,
+            chunks: BTreeMap::new(),
+        });
+        let joined = match parts {
+            Parts::Numbered {
+                total: expected,
+                chunks,
+            } => {
+                if total != *expected {
+                    bail!(
+                        "part of another psbt, of {} parts instead of {}",
+                        total,
+                        expected
+                    );
+                }
+                match chunks.get(&index) {
+                    Some(known) if known != chunk => {
+                        bail!("part {} scanned twice with different content", index)
+                    }
+                    Some(_) => return Ok(()),
+                    None => chunks.insert(index, chunk.to_string()),
+                };
+                match chunks.len() == total {
+                    true => chunks.values().map(String::as_str).collect::<String>(),
+                    false => return Ok(()),
+                }
+            }
+            Parts::Ur { .. } => bail!("pMofN part among ur parts"),
+        };
+        let psbt = parse_psbt(&joined).context("scanned psbt is invalid")?;
+        self.psbt = Some(psbt);
+        Ok(())
+    }
+}
+
+/// `M`, `N` and the chunk of a `pMofN <chunk>` part, if it is one.
+fn parse_numbered(part: &str) -> Option<(usize, usize, &str)> {
+    let mut split = part.splitn(2, ' ');
+    let header = split.next()?.strip_prefix('p')?;
+    let chunk = split.next()?;
+    let mut numbers = header.splitn(2, "of");
+    let index: usize = numbers.next()?.parse().ok()?;
+    let total: usize = numbers.next()?.parse().ok()?;
+    match (1..=total).contains(&index) {
+        true => Some((index, total, chunk)),
+        false => None,
+    }
+}
+
+/// How many fragments the `seq-len` of a multi-part UR announces.
+fn fragment_count(sequence: &str) -> Result<usize> {
+    let mut numbers = sequence.splitn(2, '-');
+    let parsed = match (numbers.next(), numbers.next()) {
+        (Some(seq), Some(len)) => seq.parse::<usize>().ok().and(len.parse::<usize>().ok()),
+        _ => None,
+    };
+    match parsed {
+        Some(len) if len > 0 => Ok(len),
+        _ => bail!("invalid ur sequence {:?}", sequence),
+    }
+}
+
+/// The content of the CBOR byte string a `crypto-psbt` wraps its PSBT in.
+fn cbor_bytes(cbor: &[u8]) -> Result<&[u8]> {
+    let (&head, rest) = cbor.split_first().context("empty ur payload")?;
+    if head >> 5 != 2 {
+        bail!("ur payload is not a cbor byte string");
+    }
+    let (len, rest) = match head & 0x1f {
+        len @ 0..=23 => (usize::from(len), rest),
+        size @ 24..=27 => {
+            let size = 1 << (size - 24);
+            if rest.len() < size {
+                bail!("truncated ur payload");
+            }
+            let len = rest[..size]
+                .iter()
+                .fold(0usize, |len, &byte| len << 8 | usize::from(byte));
+            (len, &rest[size..])
+        }
+        _ => bail!("unsupported cbor length in ur payload"),
+    };
+    if rest.len() != len {
+        bail!(
+            "ur payload holds {} bytes, its header says {}",
+            rest.len(),
+            len
+        );
+    }
+    Ok(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_data_is_split_into_numbered_parts() {
+        let address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        assert_eq!(qr_parts(address), vec![address.to_string()]);
+
+        let psbt = "cHNidP8B".repeat(20);
+        let parts = qr_parts(&psbt);
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0].starts_with("p1of3 "));
+        assert!(parts[2].starts_with("p3of3 "));
+        assert!(qr_lines(&parts[0]).unwrap().len() <= 19);
+        let joined: String = parts
+            .iter()
+            .map(|part| part.splitn(2, ' ').nth(1).unwrap())
+            .collect();
+        assert_eq!(joined, psbt);
+    }
+
+    #[test]
+    fn qr_lines_pack_two_module_rows_per_line() {
+        let lines = qr_lines("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        let size = lines[0].chars().count();
+        assert_eq!(lines.len(), (size + 1) / 2);
+        assert!(lines.iter().all(|line| line.chars().count() == size));
+        // The quiet zone is two light rows, one full line.
+        assert!(lines[0].chars().all(|c| c == '█'));
+    }
+
+    #[test]
+    fn assembler_takes_parts_in_any_order_and_twice() {
+        let psbt = crate::bs_signing::tests::psbt_spending(bitcoin::ScriptBuf::new());
+        let parts = qr_parts(&psbt.to_string());
+        assert!(parts.len() >= 2);
+
+        let mut assembler = PsbtAssembler::default();
+        for part in parts.iter().skip(1).chain(&parts[1..2]) {
+            assert!(!assembler.receive(part).unwrap());
+        }
+        assert_eq!(assembler.progress(), Some((parts.len() - 1, parts.len())));
+        assert!(assembler.receive("p1of9 cHNidP8B").is_err());
+        assert!(assembler.receive(&format!("{}x", parts[1])).is_err());
+        assert!(assembler.receive(&parts[0]).unwrap());
+        assert_eq!(assembler.psbt(), Some(&psbt));
+    }
+
+    #[test]
+    fn assembler_refuses_other_data() {
+        let mut assembler = PsbtAssembler::default();
+        assert!(assembler.receive("ur:crypto-seed/1-2/lpadao").is_err());
+        assert!(assembler.receive("cHNidP8B").is_err());
+        assert!(assembler.receive("p3of2 cHNidP8B").is_err());
+        assert_eq!(assembler.progress(), None);
+    }
+
+    #[test]
+    fn cbor_byte_strings_unwrap() {
+        assert_eq!(cbor_bytes(&[0x43, 1, 2, 3]).unwrap(), &[1, 2, 3]);
+        let mut long = vec![0x58, 30];
+        long.extend([7; 30]);
+        assert_eq!(cbor_bytes(&long).unwrap(), &[7; 30][..]);
+        assert!(cbor_bytes(&[0x44, 1, 2, 3]).is_err());
+        assert!(cbor_bytes(&[0x83, 1, 2, 3]).is_err());
+    }
+}
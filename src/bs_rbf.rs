@@ -0,0 +1,210 @@
+use anyhow::{bail, Context, Result};
+use bitcoin::psbt::{Input, PartiallySignedTransaction};
+use bitcoin::{Sequence, Transaction};
+
+use crate::bs_error::BoomerError;
+use crate::bs_fee::{compute_fee, fee_at, InputValues};
+use crate::bs_signing::{finalized_vsize, spent_output};
+use crate::bs_sweep::DUST_LIMIT;
+
+/// What a replacement pays per vbyte on top of the original's fee, the
+/// incremental relay fee of Bitcoin Core (BIP 125 rule 4).
+const INCREMENTAL_FEE_RATE: u64 = 1;
+
+/// Whether the transaction can be replaced by a higher fee one, which takes a
+/// single input to opt in (BIP 125).
+pub fn signals_rbf(tx: &Transaction) -> bool {
+    tx.input.iter().any(|input| input.sequence.is_rbf())
+}
+
+/// Makes every input of the PSBT signal replace-by-fee, returning whether it
+/// had to change any. Changing the sequences invalidates every signature, so
+/// a PSBT that already carries some is refused instead.
+pub fn signal_rbf(psbt: &mut PartiallySignedTransaction) -> Result<bool> {
+    if psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .all(|input| input.sequence.is_rbf())
+    {
+        return Ok(false);
+    }
+    if psbt.inputs.iter().any(|input| {
+        !input.partial_sigs.is_empty()
+            || input.final_script_sig.is_some()
+            || input.final_script_witness.is_some()
+    }) {
+        bail!("the PSBT is already partly signed, enabling replace-by-fee would invalidate its signatures");
+    }
+    for input in &mut psbt.unsigned_tx.input {
+        if !input.sequence.is_rbf() {
+            input.sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        }
+    }
+    Ok(true)
+}
+
+/// A replacement of a transaction, paying a higher fee out of the same UTXOs.
+#[derive(Debug)]
+pub struct Bump {
+    pub psbt: PartiallySignedTransaction,
+    pub original_fee: u64,
+    pub fee: u64,
+}
+
+/// Builds the replacement of the transaction of `original`, signed or not, at
+/// `fee_rate` sat/vB. It spends the same inputs to the same outputs, the fee
+/// increase comes out of the change back to where the inputs came from, or
+/// out of the only output of a sweep. Change that would be left as dust goes
+/// to the fee entirely.
+pub fn bump_fee(original: &PartiallySignedTransaction, fee_rate: u64) -> Result<Bump> {
+    let tx = &original.unsigned_tx;
+    if !signals_rbf(tx) {
+        return Err(BoomerError::NotReplaceable {
+            txid: tx.txid().to_string(),
+        }
+        .into());
+    }
+    let original_fee = compute_fee(original, &InputValues::new())?.fee;
+    let vsize =
+        finalized_vsize(original).context("cannot estimate the size of the transaction")? as u64;
+    let minimum = original_fee + vsize * INCREMENTAL_FEE_RATE;
+    let fee = fee_at(vsize, fee_rate)?;
+    if fee < minimum {
+        bail!(
+            "{} sat/vB pays {} sat, a replacement must pay at least {} sat ({} sat/vB)",
+            fee_rate,
+            fee,
+            minimum,
+            (minimum + vsize - 1) / vsize
+        );
+    }
+
+    let change_script = spent_output(original, 0).map(|output| output.script_pubkey);
+    let paying = tx
+        .output
+        .iter()
+        .position(|output| Some(&output.script_pubkey) == change_script.as_ref());
+    let (paying, change) = match (paying, tx.output.len()) {
+        (Some(index), _) => (index, true),
+        (None, 1) => (0, false),
+        _ => bail!("the transaction has no change output to pay a higher fee out of"),
+    };
+
+    let mut replacement = tx.clone();
+    let mut outputs = original.outputs.clone();
+    let extra = fee - original_fee;
+    match replacement.output[paying].value.checked_sub(extra) {
+        Some(value) if value >= DUST_LIMIT => replacement.output[paying].value = value,
+        _ if change && tx.output.len() > 1 => {
+            replacement.output.remove(paying);
+            outputs.remove(paying);
+        }
+        _ => bail!(
+            "output {} of {} sat can't pay {} sat more in fees",
+            paying,
+            tx.output[paying].value,
+            extra
+        ),
+    }
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(replacement)?;
+    for (input, original) in psbt.inputs.iter_mut().zip(&original.inputs) {
+        *input = Input {
+            partial_sigs: Default::default(),
+            final_script_sig: None,
+            final_script_witness: None,
+            ..original.clone()
+        };
+    }
+    psbt.outputs = outputs;
+    let fee = compute_fee(&psbt, &InputValues::new())?.fee;
+    Ok(Bump {
+        psbt,
+        original_fee,
+        fee,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_explorer::{Utxo, UtxoStatus};
+    use crate::bs_send::build_spend;
+    use crate::bs_sweep::parse_destination;
+    use bitcoin::{Network, Txid};
+    use std::str::FromStr;
+
+    fn utxo(value: u64) -> Utxo {
+        Utxo {
+            txid: Txid::from_str(&"4d3c2b1a".repeat(8)).unwrap(),
+            vout: 0,
+            value,
+            status: UtxoStatus::default(),
+        }
+    }
+
+    #[test]
+    fn bump_takes_the_higher_fee_out_of_the_change() {
+        let group =
+            parse_destination("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", Network::Signet).unwrap();
+        let destination =
+            parse_destination("mfcHP2WMCVLsVZA8yrovmhMgxNFW9r98xw", Network::Signet).unwrap();
+        let spend = build_spend(
+            &[utxo(60_000)],
+            &group.script_pubkey(),
+            true,
+            &destination,
+            50_000,
+            2,
+        )
+        .unwrap();
+
+        let bump = bump_fee(&spend.psbt, 10).unwrap();
+        assert_eq!(bump.original_fee, spend.fee);
+        assert!(bump.fee > spend.fee);
+        let outputs = &bump.psbt.unsigned_tx.output;
+        assert_eq!(outputs[0].value, 50_000);
+        assert_eq!(outputs[1].value, spend.change - (bump.fee - spend.fee));
+        assert!(signals_rbf(&bump.psbt.unsigned_tx));
+
+        // Not enough on top of the original fee.
+        assert!(bump_fee(&spend.psbt, 2).is_err());
+
+        // Change too small to pay for it is dropped.
+        let bump = bump_fee(&spend.psbt, 40).unwrap();
+        assert_eq!(bump.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(bump.fee, 10_000);
+    }
+
+    #[test]
+    fn only_rbf_signaling_transactions_are_bumped() {
+        let group =
+            parse_destination("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", Network::Signet).unwrap();
+        let mut psbt = build_spend(
+            &[utxo(60_000)],
+            &group.script_pubkey(),
+            true,
+            &group,
+            50_000,
+            2,
+        )
+        .unwrap()
+        .psbt;
+        psbt.unsigned_tx.input[0].sequence = Sequence::MAX;
+
+        let err = bump_fee(&psbt, 10).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BoomerError>(),
+            Some(BoomerError::NotReplaceable { .. })
+        ));
+
+        assert!(signal_rbf(&mut psbt).unwrap());
+        assert_eq!(
+            psbt.unsigned_tx.input[0].sequence,
+            Sequence::ENABLE_RBF_NO_LOCKTIME
+        );
+        assert!(!signal_rbf(&mut psbt).unwrap());
+        assert!(bump_fee(&psbt, 10).is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{bail, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, ScriptBuf, Transaction, TxOut};
+
+use crate::bs_explorer::Utxo;
+use crate::bs_fee::fee_at;
+use crate::bs_sweep::{
+    output_vsize, p2pkh_input_vsize, spending_input, DUST_LIMIT, TX_OVERHEAD_VSIZE,
+};
+
+/// A transaction paying an amount out of some of the multisig's UTXOs.
+#[derive(Debug)]
+pub struct Spend {
+    pub psbt: PartiallySignedTransaction,
+    pub amount: u64,
+    pub fee: u64,
+    /// Paid back to the group address, 0 when what was left over was too
+    /// small for an output of its own and went to the fee instead.
+    pub change: u64,
+}
+
+/// Builds the payment of `amount` to `destination` out of `utxos`, all locked
+/// to the p2pkh `script_pubkey` of the group key, at `fee_rate` sat/vB. Coin
+/// selection is naive: confirmed UTXOs before unconfirmed ones, the largest
+/// first, until they cover the amount and the fee.
+pub fn build_spend(
+    utxos: &[Utxo],
+    script_pubkey: &ScriptBuf,
+    compressed: bool,
+    destination: &Address,
+    amount: u64,
+    fee_rate: u64,
+) -> Result<Spend> {
+    if amount < DUST_LIMIT {
+        bail!(
+            "amount of {} sat is below the dust limit of {} sat",
+            amount,
+            DUST_LIMIT
+        );
+    }
+
+    let mut candidates: Vec<&Utxo> = utxos.iter().collect();
+    candidates.sort_by_key(|utxo| (!utxo.status.confirmed, std::cmp::Reverse(utxo.value)));
+
+    let destination_script = destination.script_pubkey();
+    let base_vsize = TX_OVERHEAD_VSIZE + output_vsize(&destination_script);
+    let mut selected = Vec::new();
+    let mut total: u64 = 0;
+    for utxo in candidates {
+        selected.push(utxo);
+        total += utxo.value;
+        let vsize = base_vsize + p2pkh_input_vsize(compressed) * selected.len() as u64;
+        if total < amount.saturating_add(fee_at(vsize, fee_rate)?) {
+            continue;
+        }
+
+        // Change only makes it into the transaction if it is worth more than
+        // the dust limit once it paid for its own output.
+        let change_fee = fee_at(vsize + output_vsize(script_pubkey), fee_rate)?;
+        let (fee, change) = match total.checked_sub(amount.saturating_add(change_fee)) {
+            Some(change) if change >= DUST_LIMIT => (change_fee, change),
+            _ => (total - amount, 0),
+        };
+
+        let mut output = vec![TxOut {
+            value: amount,
+            script_pubkey: destination_script,
+        }];
+        if change > 0 {
+            output.push(TxOut {
+                value: change,
+                script_pubkey: script_pubkey.clone(),
+            });
+        }
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: selected.iter().map(|utxo| spending_input(utxo)).collect(),
+            output,
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)?;
+        for (psbt_input, utxo) in psbt.inputs.iter_mut().zip(&selected) {
+            psbt_input.witness_utxo = Some(TxOut {
+                value: utxo.value,
+                script_pubkey: script_pubkey.clone(),
+            });
+        }
+        return Ok(Spend {
+            psbt,
+            amount,
+            fee,
+            change,
+        });
+    }
+    bail!(
+        "balance of {} sat does not cover {} sat plus the fee",
+        total,
+        amount
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_explorer::UtxoStatus;
+    use crate::bs_fee::compute_fee;
+    use crate::bs_sweep::parse_destination;
+    use bitcoin::{Network, Txid};
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    fn utxo(value: u64, confirmed: bool) -> Utxo {
+        Utxo {
+            txid: Txid::from_str(&"4d3c2b1a".repeat(8)).unwrap(),
+            vout: 0,
+            value,
+            status: UtxoStatus {
+                confirmed,
+                block_height: None,
+            },
+        }
+    }
+
+    #[test]
+    fn spend_picks_the_largest_utxos_and_returns_change() {
+        let destination =
+            parse_destination("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", Network::Signet).unwrap();
+        let script = destination.script_pubkey();
+        let utxos = [utxo(40_000, true), utxo(5_000, false), utxo(60_000, true)];
+
+        // 10 + 34 + 148 vbytes, plus 34 for the change, at 2 sat/vB.
+        let spend = build_spend(&utxos, &script, true, &destination, 50_000, 2).unwrap();
+        assert_eq!(spend.psbt.inputs.len(), 1);
+        assert_eq!((spend.fee, spend.change), (452, 9_548));
+        assert_eq!(spend.psbt.unsigned_tx.output[1].value, spend.change);
+        assert_eq!(
+            compute_fee(&spend.psbt, &BTreeMap::new()).unwrap().fee,
+            spend.fee
+        );
+
+        // 48 sat of change is dust, it goes to the fee.
+        let spend = build_spend(&utxos, &script, true, &destination, 59_500, 2).unwrap();
+        assert_eq!((spend.fee, spend.change), (500, 0));
+        assert_eq!(spend.psbt.unsigned_tx.output.len(), 1);
+
+        // The unconfirmed UTXO is only used once the confirmed ones run out.
+        let spend = build_spend(&utxos, &script, true, &destination, 100_000, 2).unwrap();
+        assert_eq!(spend.psbt.inputs.len(), 3);
+
+        assert!(build_spend(&utxos, &script, true, &destination, 200_000, 2).is_err());
+        assert!(build_spend(&utxos, &script, true, &destination, 100, 2).is_err());
+    }
+}
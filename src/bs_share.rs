@@ -0,0 +1,512 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use bitcoin::Network;
+use curv::elliptic::curves::{Point, Secp256k1};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::LocalKey;
+use serde::Deserialize;
+use sha2::Digest;
+
+use crate::bs_address::ScriptMultisig;
+use crate::bs_encryption::{decrypt, is_encrypted};
+use crate::bs_error::BoomerError;
+use crate::bs_network::DEFAULT_NETWORK;
+
+/// Overrides where shares are stored, the current directory by default.
+pub const DATA_DIR_ENV: &str = "BOOMERSIG_DATA_DIR";
+
+pub fn data_dir() -> PathBuf {
+    std::env::var_os(DATA_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+pub fn share_path_in(dir: &Path, index: impl Display) -> PathBuf {
+    dir.join(format!("local-share{}.json", index))
+}
+
+/// Creates the data directory on first run. Shares are secret material, so the
+/// directory is only accessible by its owner.
+pub fn ensure_data_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+    builder.create(dir).map_err(|e| match e.kind() {
+        ErrorKind::PermissionDenied => BoomerError::DataDirPermission {
+            path: dir.to_owned(),
+        }
+        .into(),
+        _ => {
+            anyhow::Error::new(e).context(format!("cannot create data directory {}", dir.display()))
+        }
+    })
+}
+
+/// Writes a share so that `path` holds either all of it or nothing, even if
+/// the process dies halfway: `write` fills a temporary file next to it,
+/// readable by us only, which is synced to disk and only then linked at
+/// `path`. An existing file at `path` is never replaced, `ShareExists` is
+/// returned instead.
+pub fn write_share(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> Result<()> {
+    let name = path
+        .file_name()
+        .context(format!("{} is not a file path", path.display()))?;
+    // Per process, so concurrent keygens don't write into each other's.
+    let temporary = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+        .open(&temporary)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()
+        })
+        .and_then(|()| publish(&temporary, path));
+    let _ = std::fs::remove_file(&temporary);
+    match written {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(BoomerError::ShareExists {
+                path: path.to_owned(),
+            }
+            .into())
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e).context(format!("cannot write {}", path.display())))
+        }
+        Ok(()) => {}
+    }
+
+    // The rename itself only survives a crash once the directory is synced.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .context(format!("cannot sync {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Makes the complete `temporary` appear at `path` in one step, failing with
+/// `AlreadyExists` rather than replacing a file there. A hard link does both
+/// atomically, filesystems without them fall back to a checked rename.
+fn publish(temporary: &Path, path: &Path) -> std::io::Result<()> {
+    match std::fs::hard_link(temporary, path) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => {
+            if path.exists() {
+                return Err(ErrorKind::AlreadyExists.into());
+            }
+            std::fs::rename(temporary, path)
+        }
+        linked => linked,
+    }
+}
+
+/// What can be learned about a share without running a ceremony.
+#[derive(Debug, Clone)]
+pub struct ShareInfo {
+    pub public_key: bitcoin::PublicKey,
+    pub index: u16,
+    pub threshold: u16,
+    pub parties: u16,
+    pub network: Network,
+    pub multisig: ScriptMultisig,
+}
+
+impl ShareInfo {
+    /// The smallest signer set able to sign: `threshold + 1` parties, always
+    /// including our own index.
+    pub fn default_signers(&self) -> Vec<u16> {
+        let mut signers: Vec<u16> = (1..=self.parties)
+            .filter(|&party| party != self.index)
+            .take(usize::from(self.threshold))
+            .collect();
+        signers.push(self.index);
+        signers.sort_unstable();
+        signers
+    }
+
+    /// Like `check_share_network`, for a share that was already inspected.
+    pub fn require_network(&self, path: &Path, expected: Network) -> Result<()> {
+        ensure_network(path, self.network, expected)
+    }
+}
+
+/// The plaintext share file contents, decrypted with `passphrase` if the share
+/// was written encrypted.
+pub fn unlock_share(bytes: Vec<u8>, path: &Path, passphrase: Option<&str>) -> Result<Vec<u8>> {
+    if !is_encrypted(&bytes) {
+        return Ok(bytes);
+    }
+    match passphrase {
+        Some(passphrase) => decrypt(&bytes, passphrase, path),
+        None => Err(BoomerError::ShareLocked {
+            path: path.to_owned(),
+        }
+        .into()),
+    }
+}
+
+pub fn parse_share(bytes: &[u8], path: &Path) -> Result<LocalKey<Secp256k1>> {
+    // An interrupted keygen leaves an empty file behind, which would otherwise
+    // surface as a confusing JSON error.
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return Err(BoomerError::EmptyShareFile {
+            path: path.to_owned(),
+        }
+        .into());
+    }
+
+    let corrupt = |reason: String| BoomerError::CorruptShare {
+        path: path.to_owned(),
+        reason,
+    };
+    let share: serde_json::Value = serde_json::from_slice(bytes)
+        .map_err(|e| corrupt(format!("not valid JSON, it may be truncated: {}", e)))?;
+    // Shares written before the checksum was recorded are taken as they are.
+    if let Some(stored) = share.get(CHECKSUM_FIELD) {
+        if stored.as_str() != Some(share_checksum(&share)?.as_str()) {
+            return Err(corrupt("its checksum does not match".into()).into());
+        }
+    }
+    let local_key: LocalKey<Secp256k1> =
+        serde_json::from_value(share).map_err(|e| corrupt(format!("not a GG20 share: {}", e)))?;
+    check_local_key(&local_key).map_err(corrupt)?;
+    Ok(local_key)
+}
+
+const CHECKSUM_FIELD: &str = "checksum";
+
+/// SHA-256 of the key's own fields, which leaves out the network and the
+/// checksum itself. The keys of a `Value` are sorted, so the same share always
+/// hashes the same however its file is laid out.
+fn share_checksum(share: &serde_json::Value) -> Result<String> {
+    let mut key = share.clone();
+    if let Some(fields) = key.as_object_mut() {
+        fields.remove("network");
+        fields.remove(CHECKSUM_FIELD);
+    }
+    let bytes = serde_json::to_vec(&key).context("serialize local share")?;
+    Ok(hex::encode(sha2::Sha256::digest(&bytes)))
+}
+
+/// What a checksum can't tell, a key that was written wrong to begin with:
+/// our public share must be the one of our secret share, and the group key a
+/// point other than infinity.
+fn check_local_key(local_key: &LocalKey<Secp256k1>) -> std::result::Result<(), String> {
+    let own = usize::from(local_key.i);
+    if own == 0 || own > local_key.pk_vec.len() || local_key.t >= local_key.n {
+        return Err(format!(
+            "party {} with threshold {} of {} parties",
+            local_key.i, local_key.t, local_key.n
+        ));
+    }
+    if local_key.y_sum_s.is_zero() || local_key.keys_linear.y != local_key.y_sum_s {
+        return Err("malformed group public key".into());
+    }
+    if Point::generator() * &local_key.keys_linear.x_i != local_key.pk_vec[own - 1] {
+        return Err("secret share does not match its public share".into());
+    }
+    Ok(())
+}
+
+/// The share file contents: the key with the network it was made for and its
+/// checksum next to its own fields.
+pub fn serialize_share(local_key: &LocalKey<Secp256k1>, network: Network) -> Result<Vec<u8>> {
+    let mut share = serde_json::to_value(local_key).context("serialize output")?;
+    let checksum = share_checksum(&share)?;
+    if let Some(fields) = share.as_object_mut() {
+        fields.insert("network".into(), network.to_string().into());
+        fields.insert(CHECKSUM_FIELD.into(), checksum.into());
+    }
+    serde_json::to_vec_pretty(&share).context("serialize output")
+}
+
+/// The network a share was made for. Shares written before it was recorded
+/// were all made for `DEFAULT_NETWORK`.
+pub fn share_network(bytes: &[u8]) -> Result<Network> {
+    #[derive(Deserialize)]
+    struct Tagged {
+        network: Option<String>,
+    }
+
+    let tagged: Tagged = serde_json::from_slice(bytes).context("parse local share")?;
+    match tagged.network {
+        Some(network) => Network::from_str(&network)
+            .context(format!("unknown network {:?} in local share", network)),
+        None => Ok(DEFAULT_NETWORK),
+    }
+}
+
+/// Refuses a share made for another network, so that e.g. a testnet key never
+/// ends up behind a mainnet address.
+pub fn check_share_network(bytes: &[u8], path: &Path, expected: Network) -> Result<()> {
+    ensure_network(path, share_network(bytes)?, expected)
+}
+
+fn ensure_network(path: &Path, share: Network, expected: Network) -> Result<()> {
+    if share != expected {
+        return Err(BoomerError::ShareNetworkMismatch {
+            path: path.to_owned(),
+            share,
+            expected,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// A share only signs as the party it was made for, any other index fails
+/// deep in the offline stage.
+pub fn check_share_index(path: &Path, share_index: u16, index: u16) -> Result<(), BoomerError> {
+    if share_index != index {
+        return Err(BoomerError::ShareIndexMismatch {
+            path: path.to_owned(),
+            share_index,
+            index,
+        });
+    }
+    Ok(())
+}
+
+/// The group public key, uncompressed like the one recovered when signing.
+pub fn share_public_key(local_key: &LocalKey<Secp256k1>) -> Result<bitcoin::PublicKey> {
+    Ok(bitcoin::PublicKey::from_slice(
+        &local_key.public_key().to_bytes(false),
+    )?)
+}
+
+/// The script multisig of every party's own share key, needing as many
+/// signatures as a GG20 signing needs signers.
+pub fn share_multisig(local_key: &LocalKey<Secp256k1>) -> Result<ScriptMultisig> {
+    ScriptMultisig::new(usize::from(local_key.t) + 1, share_party_keys(local_key)?)
+}
+
+/// Every party's public share of the group key, by keygen index.
+pub fn share_party_keys(local_key: &LocalKey<Secp256k1>) -> Result<Vec<bitcoin::PublicKey>> {
+    local_key
+        .pk_vec
+        .iter()
+        .map(|point| bitcoin::PublicKey::from_slice(&point.to_bytes(true)))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .context("invalid party public key in local share")
+}
+
+/// The Feldman VSS commitments recorded in the share, compressed and hex
+/// encoded, `threshold + 1` of them.
+pub fn share_vss_commitments(local_key: &LocalKey<Secp256k1>) -> Vec<String> {
+    local_key
+        .vss_scheme
+        .commitments
+        .iter()
+        .map(|point| hex::encode(&*point.to_bytes(true)))
+        .collect()
+}
+
+/// Our own share of the group key, on its own the key of our `share_multisig`
+/// entry.
+pub fn share_secret_key(local_key: &LocalKey<Secp256k1>) -> Result<bitcoin::secp256k1::SecretKey> {
+    bitcoin::secp256k1::SecretKey::from_slice(&local_key.keys_linear.x_i.to_bytes())
+        .context("invalid secret share in local share")
+}
+
+pub fn inspect_share(path: &Path, passphrase: Option<&str>) -> Result<ShareInfo> {
+    let bytes = std::fs::read(path).context("cannot read local share")?;
+    let bytes = unlock_share(bytes, path, passphrase)?;
+    let local_key = parse_share(&bytes, path)?;
+    Ok(ShareInfo {
+        public_key: share_public_key(&local_key)?,
+        index: local_key.i,
+        threshold: local_key.t,
+        parties: local_key.n,
+        network: share_network(&bytes)?,
+        multisig: share_multisig(&local_key)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn share_info(index: u16, threshold: u16, parties: u16) -> ShareInfo {
+        let public_key = bitcoin::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        ShareInfo {
+            public_key,
+            index,
+            threshold,
+            parties,
+            network: DEFAULT_NETWORK,
+            multisig: ScriptMultisig::new(1, vec![public_key]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn default_signers_include_own_index() {
+        assert_eq!(share_info(1, 1, 3).default_signers(), vec![1, 2]);
+        assert_eq!(share_info(3, 1, 3).default_signers(), vec![1, 3]);
+        assert_eq!(share_info(4, 2, 5).default_signers(), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn share_network_defaults_for_untagged_shares() {
+        let path = Path::new("local-share1.json");
+        assert_eq!(share_network(br#"{"i": 1}"#).unwrap(), DEFAULT_NETWORK);
+        assert!(
+            check_share_network(br#"{"i": 1, "network": "testnet"}"#, path, Network::Testnet)
+                .is_ok()
+        );
+
+        let err = check_share_network(br#"{"i": 1, "network": "testnet"}"#, path, Network::Bitcoin)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "share local-share1.json was created for testnet, but the configured network is bitcoin"
+        );
+    }
+
+    #[test]
+    fn share_only_signs_as_its_own_party() {
+        let path = Path::new("local-share2.json");
+        assert!(check_share_index(path, 2, 2).is_ok());
+        assert_eq!(
+            check_share_index(path, 1, 2).unwrap_err().to_string(),
+            "local-share2.json belongs to party 1, not party 2: set the participant index to 1"
+        );
+    }
+
+    #[test]
+    fn share_checksum_covers_the_key_fields_only() {
+        let share = serde_json::json!({"i": 1, "t": 1, "n": 3});
+        let checksum = share_checksum(&share).unwrap();
+
+        let mut tagged = share.clone();
+        tagged["network"] = "signet".into();
+        tagged[CHECKSUM_FIELD] = checksum.clone().into();
+        assert_eq!(share_checksum(&tagged).unwrap(), checksum);
+
+        tagged["t"] = 2.into();
+        assert_ne!(share_checksum(&tagged).unwrap(), checksum);
+    }
+
+    #[test]
+    fn tampered_or_truncated_shares_are_corrupt() {
+        let path = Path::new("local-share1.json");
+        let corrupt = |bytes: &[u8]| {
+            matches!(
+                parse_share(bytes, path).unwrap_err().downcast_ref(),
+                Some(BoomerError::CorruptShare { .. })
+            )
+        };
+
+        let mut share = serde_json::json!({"i": 1, "t": 1, "n": 3});
+        share[CHECKSUM_FIELD] = share_checksum(&share).unwrap().into();
+        share["t"] = 2.into();
+        let tampered = serde_json::to_vec_pretty(&share).unwrap();
+        assert!(corrupt(&tampered));
+        assert!(corrupt(&tampered[..tampered.len() / 2]));
+    }
+
+    #[tokio::test]
+    async fn interrupted_share_write_leaves_nothing_at_the_path() {
+        use crate::bs_client::RelayOptions;
+        use crate::bs_keygen::{do_keygen, KeygenConfig};
+        use crate::bs_mock_relay::MockRelay;
+        use crate::bs_progress::ProgressHandle;
+        use crate::bs_signing::PhaseTimeouts;
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("boomersig-write-{}", std::process::id()));
+        ensure_data_dir(&dir).unwrap();
+        let path = share_path_in(&dir, 1);
+        let interrupted = |file: &mut File| {
+            file.write_all(b"{\"y_sum_s\":")?;
+            Err(std::io::Error::new(ErrorKind::Interrupted, "killed"))
+        };
+
+        assert!(write_share(&path, interrupted).is_err());
+        assert!(!path.exists());
+        // Nor is the temporary file left behind.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // A ceremony is only ever killed while the other parties are awaited,
+        // nothing may be on disk for it by then.
+        let relay = MockRelay::start();
+        let keygen = do_keygen(KeygenConfig {
+            address: relay.url.clone(),
+            relay_options: RelayOptions::default(),
+            room: "killed-keygen".into(),
+            output: path.clone(),
+            index: 1,
+            threshold: 1,
+            number_of_parties: 2,
+            test_signature: false,
+            progress: ProgressHandle::default(),
+            network: Network::Signet,
+            passphrase: None,
+            script_multisig: false,
+            timeouts: PhaseTimeouts::default(),
+            events: None,
+        });
+        let waited = tokio::time::timeout(std::time::Duration::from_millis(500), keygen).await;
+        assert!(waited.is_err(), "keygen finished without the other party");
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        write_share(&path, |file| file.write_all(b"share")).unwrap();
+        assert!(write_share(&path, interrupted).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"share");
+        // Not even a complete share replaces one.
+        let err = write_share(&path, |file| file.write_all(b"other")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BoomerError>(),
+            Some(BoomerError::ShareExists { .. })
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), b"share");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ensure_data_dir_creates_private_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir()
+            .join(format!("boomersig-data-{}", std::process::id()))
+            .join("shares");
+        ensure_data_dir(&dir).unwrap();
+
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        ensure_data_dir(&dir).unwrap();
+
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
@@ -1,45 +1,302 @@
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Ok;
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use bitcoin::consensus::encode::{deserialize, serialize_hex};
-use bitcoin::hashes::sha256;
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::psbt::PartiallySignedTransaction;
 use bitcoin::script::PushBytesBuf;
-use bitcoin::sighash;
+use bitcoin::sighash::{self, EcdsaSighashType};
 use bitcoin::ScriptBuf;
 use bitcoin::Transaction;
-use futures::{SinkExt, StreamExt, TryStreamExt};
+use bitcoin::TxOut;
+use bitcoin::Witness;
+use futures::{SinkExt, Stream, StreamExt};
 use hex::FromHex;
 use sha2::Digest;
 use std::str::FromStr;
 use structopt::StructOpt;
 
 use curv::arithmetic::Converter;
+use curv::elliptic::curves::Secp256k1 as Secp256k1Curve;
 use curv::BigInt;
 
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::party_i::SignatureRecid;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::LocalKey;
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::sign::{
-    OfflineStage, SignManual,
+    CompletedOfflineStage, OfflineStage, SignManual,
 };
 use round_based::async_runtime::AsyncProtocol;
 use round_based::Msg;
+use serde::{Deserialize, Serialize};
 
-use crate::bs_client::join_computation;
-
-use openssl::bn::BigNum;
+use crate::bs_address::{AddressType, ScriptMultisig};
+use crate::bs_bip322;
+use crate::bs_client::{attempt_room, join_computation, wait_in_lobby, RelayOptions};
+use crate::bs_error::{BoomerError, SigningError};
+use crate::bs_network::{check_psbt_network, DEFAULT_NETWORK};
+use crate::bs_progress::{Activity, Progress, ProgressHandle, Tracked};
+use crate::bs_rbf::signal_rbf;
+use crate::bs_share::{
+    check_share_index, check_share_network, parse_share, share_multisig, share_public_key,
+    share_secret_key, unlock_share,
+};
 
 use secp256k1::{Message, RecoverableSignature, RecoveryId, Secp256k1};
 
+/// How plain (non-transaction) data is hashed before signing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashScheme {
+    /// A single SHA-256, what generic ECDSA verifiers such as
+    /// `openssl dgst -sha256 -verify` expect.
+    #[default]
+    Sha256,
+    /// SHA-256 applied twice, what bitcoin-style verifiers that check a
+    /// `sha256d` digest expect.
+    DoubleSha256,
+    /// The data already is a 32 byte digest in hex and is signed as-is, for
+    /// verifiers that hash the message themselves.
+    RawDigest,
+    /// The Bitcoin Signed Message prefix and length are prepended and the
+    /// result hashed twice, as `signmessage` and `verifymessage` do.
+    BitcoinMessage,
+}
+
+/// How long each phase of a signing may take, so that a dropped signer is
+/// reported with the phase it dropped in and a slow offline stage doesn't eat
+/// into the online stage's time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTimeouts {
+    pub offline: Duration,
+    pub online: Duration,
+    /// Each attempt to broadcast the signed transaction, which `do_sign`
+    /// leaves to its caller.
+    pub broadcast: Duration,
+}
+
+impl Default for PhaseTimeouts {
+    fn default() -> Self {
+        Self {
+            offline: Duration::from_secs(18),
+            online: Duration::from_secs(8),
+            broadcast: Duration::from_secs(10),
+        }
+    }
+}
+
+impl PhaseTimeouts {
+    /// Both stages of one signature, on top of the time spent in the lobby.
+    pub fn stages(&self) -> Duration {
+        self.offline + self.online
+    }
+}
+
 #[derive(Clone)]
 pub struct SigningConfig {
     pub address: surf::Url,
+    pub relay_options: RelayOptions,
     pub room: String,
     pub local_share: PathBuf,
     pub parties: Vec<u16>,
     pub data_to_sign: String,
     pub transaction: bool,
     pub idx: u16,
+    pub address_type: AddressType,
+    /// Must be the network the share was made for.
+    pub network: bitcoin::Network,
+    pub hash_scheme: HashScheme,
+    /// Appended to every input signature, `All` unless the transaction is
+    /// meant to be extended by others.
+    pub sighash_type: EcdsaSighashType,
+    pub progress: ProgressHandle,
+    /// Where to keep the completed offline stage, so a failed online stage
+    /// can be retried without redoing it.
+    pub save_offline_stage: Option<PathBuf>,
+    /// A completed offline stage saved by an earlier attempt to reuse.
+    pub resume_offline_stage: Option<PathBuf>,
+    pub inputs: InputSelection,
+    /// Decrypts `local_share` if it was written encrypted.
+    pub passphrase: Option<String>,
+    /// Signs transaction inputs spending the share's `ScriptMultisig` instead
+    /// of the group key, each party on its own without a ceremony.
+    pub script_multisig: bool,
+    pub timeouts: PhaseTimeouts,
+    /// Signs all the same but keeps the signed transaction from being
+    /// broadcast, for rehearsing a ceremony against real infrastructure.
+    pub dry_run: bool,
+    /// Makes every input signal replace-by-fee before signing, see
+    /// `signal_rbf`.
+    pub rbf: bool,
+    /// Signs the message as a BIP322 proof of the `address_type` group
+    /// address instead of over its `hash_scheme` digest.
+    pub bip322: bool,
+}
+
+/// Which PSBT inputs a transaction signing covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputSelection {
+    /// Every input spending the group key.
+    #[default]
+    All,
+    /// Only this input, leaving the others to other signers.
+    Single(usize),
+}
+
+impl InputSelection {
+    fn pick(self, signable: Vec<usize>) -> Vec<usize> {
+        match self {
+            InputSelection::All => signable,
+            InputSelection::Single(index) => signable.into_iter().filter(|&i| i == index).collect(),
+        }
+    }
+}
+
+/// The inputs `selection` signs, a subset of `signable_inputs`.
+pub fn selected_inputs(
+    psbt: &PartiallySignedTransaction,
+    public_key: &bitcoin::PublicKey,
+    selection: InputSelection,
+) -> Vec<usize> {
+    selection.pick(signable_inputs(psbt, public_key))
+}
+
+/// The room every signer of `data_to_sign` meets in, which each of them
+/// derives on their own. A PSBT is named by the txid of its unsigned
+/// transaction, so signers whose copies differ in partial signatures or
+/// metadata still meet, other data by its SHA-256. The signer set is part of
+/// the name too, only parties that agree on who signs end up together. With
+/// `rbf` the txid is the one after `signal_rbf`, what is actually signed.
+pub fn signing_room(data_to_sign: &str, parties: &[u16], attempt: u8, rbf: bool) -> String {
+    let subject = match parse_psbt(data_to_sign) {
+        std::result::Result::Ok(mut psbt) => {
+            // A signed PSBT that doesn't signal is refused by `do_sign` anyway.
+            if rbf {
+                let _ = signal_rbf(&mut psbt);
+            }
+            psbt.unsigned_tx.txid().to_string()
+        }
+        Err(_) => hex::encode(sha2::Sha256::digest(data_to_sign.as_bytes())),
+    };
+    let mut signers = parties.to_vec();
+    signers.sort_unstable();
+    signers.dedup();
+    let signers: Vec<String> = signers.iter().map(u16::to_string).collect();
+    attempt_room(
+        &format!("default-signing-{}-s{}", subject, signers.join("_")),
+        attempt,
+    )
+}
+
+/// Parses a PSBT in base64, as BIP174 encodes it and Bitcoin Core, Sparrow
+/// and Electrum export it, or in hex. Whitespace, such as the line breaks of a
+/// wrapped paste, is ignored.
+pub fn parse_psbt(text: &str) -> Result<PartiallySignedTransaction> {
+    let compact = compact_psbt(text);
+    if compact.starts_with(PSBT_BASE64_MAGIC) {
+        return PartiallySignedTransaction::from_str(&compact).context("invalid base64 psbt");
+    }
+    if compact.to_ascii_lowercase().starts_with(PSBT_HEX_MAGIC) {
+        let bytes = hex::decode(&compact).context("psbt is not valid hex")?;
+        return PartiallySignedTransaction::deserialize(&bytes).context("invalid hex psbt");
+    }
+    bail!(
+        "not a psbt, expected base64 starting with {} or hex starting with {}",
+        PSBT_BASE64_MAGIC,
+        PSBT_HEX_MAGIC
+    )
+}
+
+/// The PSBT text without any whitespace, which neither encoding has.
+pub fn compact_psbt(text: &str) -> String {
+    text.split_whitespace().collect()
+}
+
+/// The `psbt\xff` magic every PSBT starts with, in either encoding.
+const PSBT_BASE64_MAGIC: &str = "cHNidP8";
+const PSBT_HEX_MAGIC: &str = "70736274ff";
+
+/// Parses a comma separated list of party indexes such as `1, 3`.
+pub fn parse_parties(text: &str) -> Result<Vec<u16>, BoomerError> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry.parse().map_err(|_| BoomerError::InvalidPartyEntry {
+                entry: entry.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parses a sighash type such as `all`, `single|anyonecanpay` or its byte
+/// value, e.g. `0x83`, refusing anything that is not a standard ECDSA one.
+pub fn parse_sighash_type(text: &str) -> Result<EcdsaSighashType> {
+    let text = text.trim();
+    let value = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    };
+    if let Some(value) = value {
+        return EcdsaSighashType::from_standard(value)
+            .map_err(|_| anyhow!("{:#x} is not a standard ecdsa sighash type", value));
+    }
+    let name: Vec<String> = text
+        .to_uppercase()
+        .split('|')
+        .map(|flag| {
+            let flag = flag.trim();
+            match flag.starts_with("SIGHASH_") {
+                true => flag.to_string(),
+                false => format!("SIGHASH_{}", flag),
+            }
+        })
+        .collect();
+    EcdsaSighashType::from_str(&name.join("|"))
+        .map_err(|_| anyhow!("unknown sighash type {:?}", text))
+}
+
+/// `OfflineStage::new` fails unless our own index is one of the signers, the
+/// error suggests the list with it added.
+pub fn check_parties(parties: &[u16], idx: u16) -> Result<(), BoomerError> {
+    if parties.contains(&idx) {
+        return std::result::Result::Ok(());
+    }
+    let mut suggestion = parties.to_vec();
+    suggestion.push(idx);
+    suggestion.sort_unstable();
+    suggestion.dedup();
+    Err(BoomerError::SignerNotInParties { idx, suggestion })
+}
+
+/// GG20 needs `threshold + 1` distinct signers, each one of the share's
+/// `number_of_parties`.
+pub fn check_signer_count(
+    parties: &[u16],
+    threshold: u16,
+    number_of_parties: u16,
+) -> Result<(), BoomerError> {
+    if let Some(&party) = parties
+        .iter()
+        .find(|&&party| party == 0 || party > number_of_parties)
+    {
+        return Err(BoomerError::SignerOutOfRange {
+            party,
+            parties: number_of_parties,
+        });
+    }
+    let mut distinct = parties.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let needed = usize::from(threshold) + 1;
+    if distinct.len() < needed {
+        return Err(BoomerError::TooFewSigners {
+            needed,
+            got: distinct.len(),
+        });
+    }
+    std::result::Result::Ok(())
 }
 
 #[derive(Debug)]
@@ -47,19 +304,99 @@ pub struct SigningResult {
     pub pubkey: String,
     pub address: String,
     pub out_dir: PathBuf,
+    /// Everyone who took part in the ceremony, ourselves included.
+    pub parties: Vec<u16>,
+    /// The PSBT inputs we signed, empty when signing a message.
+    pub signed_inputs: Vec<usize>,
     pub signined_tx: Option<String>,
+    /// `signined_tx` is only handed out as hex, whoever would broadcast it
+    /// must not.
+    pub dry_run: bool,
+    pub partially_signed_psbt: Option<String>,
+    /// The base64 BIP322 signature, when the message was signed as one.
+    pub bip322_signature: Option<String>,
+    /// The txid esplora accepted, set by whoever broadcasts `signined_tx`.
+    pub broadcast_txid: Option<String>,
 }
 
-pub async fn do_sign(args: SigningConfig) -> Result<SigningResult> {
-    let local_share = tokio::fs::read(args.local_share.clone())
-        .await
-        .context("cannot read local share")?;
+/// The output spent by the given PSBT input, if the PSBT carries it.
+pub fn spent_output(psbt: &PartiallySignedTransaction, index: usize) -> Option<TxOut> {
+    let input = psbt.inputs.get(index)?;
+    if let Some(utxo) = &input.witness_utxo {
+        return Some(utxo.clone());
+    }
+    let prevout = psbt.unsigned_tx.input.get(index)?.previous_output;
+    input
+        .non_witness_utxo
+        .as_ref()?
+        .output
+        .get(prevout.vout as usize)
+        .cloned()
+}
+
+fn spent_script_pubkey(psbt: &PartiallySignedTransaction, index: usize) -> Option<ScriptBuf> {
+    spent_output(psbt, index).map(|out| out.script_pubkey)
+}
+
+/// The type of the group key's output the given input spends, told from the
+/// spent output in its `witness_utxo` or `non_witness_utxo`. `None` when it
+/// spends someone else's output.
+pub fn input_address_type(
+    psbt: &PartiallySignedTransaction,
+    index: usize,
+    public_key: &bitcoin::PublicKey,
+) -> Result<Option<AddressType>, BoomerError> {
+    let spent = spent_script_pubkey(psbt, index).ok_or(BoomerError::MissingUtxo {
+        inputs: vec![index],
+    })?;
+    std::result::Result::Ok(AddressType::ALL.iter().copied().find(|&address_type| {
+        locking_script(public_key, address_type).map_or(false, |script| script == spent)
+    }))
+}
 
-    let local_share = serde_json::from_slice(&local_share).context("parse local share")?;
-    let number_of_parties = args.parties.len();
+/// The script an `address_type` output of the key is locked with, which is the
+/// same on every network.
+fn locking_script(public_key: &bitcoin::PublicKey, address_type: AddressType) -> Result<ScriptBuf> {
+    Ok(address_type
+        .deriver()
+        .derive(public_key, DEFAULT_NETWORK)?
+        .script_pubkey())
+}
 
+/// Loads the share, refusing one made for another network than `network` or
+/// for another party than `idx`.
+pub async fn load_share(
+    path: &Path,
+    idx: u16,
+    network: bitcoin::Network,
+    passphrase: Option<&str>,
+) -> std::result::Result<LocalKey<Secp256k1Curve>, SigningError> {
+    let local_share = tokio::fs::read(path)
+        .await
+        .context("cannot read local share")
+        .map_err(SigningError::ShareReadError)?;
+    let parsed = unlock_share(local_share, path, passphrase).and_then(|local_share| {
+        let local_key = parse_share(&local_share, path)?;
+        check_share_network(&local_share, path, network)?;
+        check_share_index(path, local_key.i, idx)?;
+        Ok(local_key)
+    });
+    parsed.map_err(SigningError::ShareParseError)
+}
+
+#[tracing::instrument(skip(address, relay_options, parties, local_share, progress))]
+pub async fn run_offline_stage(
+    address: surf::Url,
+    relay_options: &RelayOptions,
+    room: &str,
+    idx: u16,
+    parties: Vec<u16>,
+    local_share: LocalKey<Secp256k1Curve>,
+    progress: &ProgressHandle,
+    timeout: Duration,
+) -> Result<CompletedOfflineStage> {
     let (i, incoming, outgoing) =
-        join_computation(args.address.clone(), &format!("{}-offline", args.room))
+        join_computation(address, &format!("{}-offline", room), relay_options)
             .await
             .context("join offline computation")?;
 
@@ -67,36 +404,165 @@ pub async fn do_sign(args: SigningConfig) -> Result<SigningResult> {
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let signing = OfflineStage::new(args.idx, args.parties, local_share)
+    let signing = OfflineStage::new(idx, parties.clone(), local_share)
         .context(format!("error creatign offline stage {i}"))?;
-    let completed_offline_stage = AsyncProtocol::new(signing, incoming, outgoing)
-        .run()
-        .await
-        .map_err(|e| anyhow!("protocol execution terminated with error: {}", e))?;
+    let signing = Tracked::new(signing, "offline stage", parties, progress.clone());
+    let protocol = AsyncProtocol::new(signing, incoming, outgoing).run();
+    match tokio::time::timeout(timeout, protocol).await {
+        std::result::Result::Ok(result) => {
+            result.map_err(|e| BoomerError::protocol_failure("offline stage", e).into())
+        }
+        Err(_) => Err(BoomerError::OfflineStageIncomplete { timeout }.into()),
+    }
+}
 
-    let (_i, incoming, outgoing) = join_computation(args.address, &format!("{}-online", args.room))
-        .await
-        .context("join online computation")?;
+/// A completed offline stage, bound to the digest it is going to sign. Signing
+/// two different digests with the same offline stage reveals the key, so it is
+/// only ever reused to retry the very same signature.
+#[derive(Serialize, Deserialize)]
+pub struct SavedOfflineStage {
+    pub digest: String,
+    pub idx: u16,
+    pub parties: Vec<u16>,
+    pub stage: CompletedOfflineStage,
+}
+
+/// Written readable by us only, it holds secret signing material.
+pub fn save_offline_stage(path: &Path, saved: &SavedOfflineStage) -> Result<()> {
+    let bytes = serde_json::to_vec(saved).context("serialize offline stage")?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .context(format!("cannot save offline stage to {}", path.display()))
+}
 
+/// Loads an offline stage saved by `save_offline_stage`, refusing it unless it
+/// was made for exactly this digest, signer and parties.
+pub fn load_offline_stage(
+    path: &Path,
+    digest: &[u8],
+    idx: u16,
+    parties: &[u16],
+) -> Result<CompletedOfflineStage> {
+    let bytes =
+        std::fs::read(path).context(format!("cannot read offline stage {}", path.display()))?;
+    let saved: SavedOfflineStage =
+        serde_json::from_slice(&bytes).context("parse saved offline stage")?;
+
+    let mut mismatches = Vec::new();
+    if saved.digest != hex::encode(digest) {
+        mismatches.push(format!("it signs digest {}", saved.digest));
+    }
+    if saved.idx != idx {
+        mismatches.push(format!("it belongs to party {}", saved.idx));
+    }
+    if saved.parties != parties {
+        mismatches.push(format!("its signers are {:?}", saved.parties));
+    }
+    if !mismatches.is_empty() {
+        return Err(BoomerError::OfflineStageMismatch {
+            path: path.to_owned(),
+            mismatches,
+        }
+        .into());
+    }
+    Ok(saved.stage)
+}
+
+/// Collects `expected` message bodies, failing with the number that did arrive
+/// when the stream ends or `wait` runs out first.
+pub async fn collect_partials<T>(
+    incoming: impl Stream<Item = Result<Msg<T>>>,
+    expected: usize,
+    wait: Duration,
+) -> Result<Vec<T>> {
     tokio::pin!(incoming);
-    tokio::pin!(outgoing);
+    let mut partials = Vec::with_capacity(expected);
+    let collected = tokio::time::timeout(wait, async {
+        while partials.len() < expected {
+            match incoming.next().await {
+                Some(msg) => partials.push(msg?.body),
+                None => break,
+            }
+        }
+        Ok(())
+    })
+    .await;
+
+    if let std::result::Result::Ok(Err(e)) = collected {
+        return Err(e);
+    }
+    if partials.len() < expected {
+        return Err(BoomerError::OnlineStageIncomplete {
+            received: partials.len(),
+            expected,
+            timeout: wait,
+        }
+        .into());
+    }
+    Ok(partials)
+}
+
+/// The sighash of one PSBT input, legacy or BIP143 depending on the spent
+/// output.
+pub fn input_sighash(psbt: &PartiallySignedTransaction, index: usize) -> Result<Vec<u8>> {
+    let mut sighash_cache = sighash::SighashCache::new(psbt.unsigned_tx.clone());
+    let sighash_ecdsa = psbt.sighash_ecdsa(index, &mut sighash_cache)?;
+    hex::decode(sighash_ecdsa.0.to_string()).context("cannot decode sighash")
+}
 
-    let data = match args.transaction {
-        true => {
-            let tx = PartiallySignedTransaction::from_str(args.data_to_sign.as_str())?;
-            let mut sighash_cache = sighash::SighashCache::new(tx.clone().extract_tx());
-            let sighash_ecdsa = tx.sighash_ecdsa(0, &mut sighash_cache)?;
-            hex::decode(sighash_ecdsa.0.to_string()).context("cannot decode sighash")?
+/// The digest the parties sign for a message, the hash of the data according
+/// to `hash_scheme`. Transactions sign the `input_sighash` of every input
+/// instead.
+pub fn message_digest(data_to_sign: &str, hash_scheme: HashScheme) -> Result<Vec<u8>> {
+    match hash_scheme {
+        HashScheme::RawDigest => {
+            let digest = hex::decode(data_to_sign.trim()).context("digest is not valid hex")?;
+            if digest.len() != 32 {
+                bail!("digest must be 32 bytes, got {} bytes", digest.len());
+            }
+            Ok(digest)
         }
-        false => {
+        HashScheme::BitcoinMessage => Ok(bitcoin::sign_message::signed_msg_hash(data_to_sign)
+            .to_byte_array()
+            .to_vec()),
+        _ => {
             let mut a = sha2::Sha256::default();
-            a.write(&args.data_to_sign.as_bytes())?;
-            a.finalize().to_vec()
+            a.write(data_to_sign.as_bytes())?;
+            let digest = a.finalize();
+            match hash_scheme {
+                HashScheme::DoubleSha256 => Ok(sha2::Sha256::digest(&digest).to_vec()),
+                _ => Ok(digest.to_vec()),
+            }
         }
-    };
+    }
+}
+
+#[tracing::instrument(skip(address, relay_options, data, completed_offline_stage, progress))]
+pub async fn run_online_stage(
+    address: surf::Url,
+    relay_options: &RelayOptions,
+    room: &str,
+    data: &[u8],
+    completed_offline_stage: CompletedOfflineStage,
+    number_of_parties: usize,
+    progress: &ProgressHandle,
+    timeout: Duration,
+) -> Result<SignatureRecid> {
+    let (i, incoming, outgoing) =
+        join_computation(address, &format!("{}-online", room), relay_options)
+            .await
+            .context("join online computation")?;
+
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
 
     let (signing, partial_signature) =
-        SignManual::new(BigInt::from_bytes(&data), completed_offline_stage)?;
+        SignManual::new(BigInt::from_bytes(data), completed_offline_stage)?;
 
     outgoing
         .send(Msg {
@@ -106,71 +572,1603 @@ pub async fn do_sign(args: SigningConfig) -> Result<SigningResult> {
         })
         .await?;
 
-    let partial_signatures: Vec<_> = incoming
-        .take(number_of_parties - 1)
-        .map_ok(|msg| msg.body)
-        .try_collect()
-        .await?;
+    let online_progress = |activity| Progress {
+        stage: "online stage",
+        round: 1,
+        total_rounds: Some(1),
+        activity,
+    };
+    progress.set(online_progress(Activity::Waiting {
+        parties: Vec::new(),
+    }));
+    let partial_signatures = collect_partials(incoming, number_of_parties - 1, timeout).await?;
 
-    let signature = signing
+    progress.set(online_progress(Activity::Computing));
+    signing
         .complete(&partial_signatures)
-        .context("online stage failed")?;
+        .context("online stage failed")
+}
 
-    let r_bn = BigNum::from_slice(&signature.r.to_bytes())?;
-    let s_bn = BigNum::from_slice(&signature.s.to_bytes())?;
+/// Converts the GG20 signature into a bitcoin ECDSA signature and recovers the
+/// group public key that produced it.
+///
+/// `group_key` is the uncompressed key the signature must recover to, when
+/// known.
+pub fn assemble_signature(
+    signature: &SignatureRecid,
+    data: &[u8],
+    group_key: Option<&[u8]>,
+    sighash_type: EcdsaSighashType,
+) -> Result<(bitcoin::ecdsa::Signature, bitcoin::PublicKey)> {
+    signature_from_scalars(
+        &signature.r.to_bigint().to_bytes(),
+        &signature.s.to_bigint().to_bytes(),
+        signature.recid,
+        data,
+        group_key,
+        sighash_type,
+    )
+}
 
+/// `assemble_signature` from the minimal big-endian encodings of `r` and `s`,
+/// as `BigInt` gives them.
+fn signature_from_scalars(
+    r: &[u8],
+    s: &[u8],
+    recid: u8,
+    data: &[u8],
+    group_key: Option<&[u8]>,
+    sighash_type: EcdsaSighashType,
+) -> Result<(bitcoin::ecdsa::Signature, bitcoin::PublicKey)> {
+    let compact = compact_signature(r, s)?;
     let secp = Secp256k1::new();
-    let recid = RecoveryId::from_i32(signature.recid as i32)?;
-    let sig =
-        RecoverableSignature::from_compact(&secp, &[r_bn.to_vec(), s_bn.to_vec()].concat(), recid)?;
-    // println!(
-    //     "sig: {:?}",
-    //     hex::encode(sig.to_standard(&secp).serialize_der(&secp))
-    // );
-    let msg = Message::from_slice(&data)?;
-    let public_key = secp.recover(&msg, &sig)?;
-    let public_key_hex = hex::encode(public_key.serialize_vec(&secp, false));
-    // println!(
-    //     "pubkey {:?}",
-    //     hex::encode(public_key.serialize_vec(&secp, false))
-    // );
-    // let signature = serde_json::to_string(&signature).context("serialize signature")?;
-    // println!("sig {}", signature);
-
-    if args.transaction {
-        let mut script_sig = ScriptBuf::new();
-        let mut v = PushBytesBuf::new();
-        let mut sig = sig.to_standard(&secp).serialize_der(&secp);
-        sig.push(1);
-        v.extend_from_slice(&sig)?;
-        script_sig.push_slice(&v);
-
-        let mut v = PushBytesBuf::new();
-        v.extend_from_slice(&public_key.serialize_vec(&secp, false))?;
-        script_sig.push_slice(&v);
-        let mut tx = PartiallySignedTransaction::from_str(args.data_to_sign.as_str())?;
-        tx.inputs[0].final_script_sig = Some(script_sig);
-
-        let tx = tx.extract_tx();
-
-        let public_key = bitcoin::PublicKey::from_slice(&hex::decode(&public_key_hex)?)?;
-        let address = bitcoin::Address::p2pkh(&public_key, bitcoin::Network::Signet);
+    let (sig, public_key) = recover_public_key(
+        &secp,
+        &compact,
+        recid,
+        &Message::from_slice(data)?,
+        group_key,
+    )?;
 
-        return Ok(SigningResult {
-            pubkey: public_key_hex,
-            address: address.to_string(),
-            out_dir: args.local_share,
-            signined_tx: Some(serialize_hex(&tx)),
-        });
+    let mut der = sig.to_standard(&secp).serialize_der(&secp);
+    der.push(sighash_type.to_u32() as u8);
+
+    let signature = bitcoin::ecdsa::Signature::from_slice(&der)?;
+    let public_key = bitcoin::PublicKey::from_slice(&public_key.serialize_vec(&secp, false))?;
+    verify_signature(&signature, data, &public_key)?;
+    Ok((signature, public_key))
+}
+
+/// Checks the converted signature the way bitcoin will, catching byte order
+/// or recid mix-ups before anything is built from it.
+pub fn verify_signature(
+    signature: &bitcoin::ecdsa::Signature,
+    data: &[u8],
+    public_key: &bitcoin::PublicKey,
+) -> Result<(), BoomerError> {
+    let invalid = |e: bitcoin::secp256k1::Error| BoomerError::InvalidSignature {
+        reason: e.to_string(),
+    };
+    let message = bitcoin::secp256k1::Message::from_slice(data).map_err(invalid)?;
+    bitcoin::secp256k1::Secp256k1::verification_only()
+        .verify_ecdsa(&message, &signature.sig, &public_key.inner)
+        .map_err(invalid)
+}
+
+/// The compact `r | s` encoding of a signature from the big-endian `r` and
+/// `s`. Their minimal encoding drops leading zero bytes, the compact one needs
+/// all 32 of each.
+fn compact_signature(r: &[u8], s: &[u8]) -> Result<[u8; 64]> {
+    let mut compact = [0u8; 64];
+    for (half, bytes) in compact.chunks_mut(32).zip([r, s]) {
+        if bytes.len() > 32 {
+            bail!(
+                "signature scalar is {} bytes, expected at most 32",
+                bytes.len()
+            );
+        }
+        half[32 - bytes.len()..].copy_from_slice(bytes);
+    }
+    Ok(compact)
+}
+
+/// Recovers the signing key with `recid`, falling back to the recid of the
+/// other parity of R when that fails or, given `group_key`, recovers some
+/// other key.
+fn recover_public_key(
+    secp: &Secp256k1,
+    compact: &[u8],
+    recid: u8,
+    msg: &Message,
+    group_key: Option<&[u8]>,
+) -> Result<(RecoverableSignature, secp256k1::PublicKey), BoomerError> {
+    let mut reason = String::new();
+    for candidate in [recid, recid ^ 1] {
+        let recovered = RecoveryId::from_i32(i32::from(candidate))
+            .and_then(|id| RecoverableSignature::from_compact(secp, compact, id))
+            .and_then(|sig| secp.recover(msg, &sig).map(|public_key| (sig, public_key)));
+        match recovered {
+            std::result::Result::Ok((sig, public_key)) => {
+                let key = public_key.serialize_vec(secp, false);
+                match group_key {
+                    Some(group_key) if group_key != &key[..] => {
+                        reason = format!("recid {} recovers key {}", candidate, hex::encode(&key))
+                    }
+                    _ => return std::result::Result::Ok((sig, public_key)),
+                }
+            }
+            Err(e) => reason = format!("recid {}: {:?}", candidate, e),
+        }
+    }
+    Err(BoomerError::RecoveryFailed { recid, reason })
+}
+
+/// The key `signature` over `data` was made with: `expected` if either recid
+/// recovers it, the key of the first recid that recovers one otherwise.
+pub fn recover_signer(
+    signature: &bitcoin::ecdsa::Signature,
+    data: &[u8],
+    expected: &bitcoin::PublicKey,
+) -> Result<bitcoin::PublicKey> {
+    let secp = Secp256k1::new();
+    let compact = signature.sig.serialize_compact();
+    let msg = Message::from_slice(data)?;
+    let expected_key = expected.inner.serialize_uncompressed();
+    let (_, public_key) = recover_public_key(&secp, &compact, 0, &msg, Some(&expected_key[..]))
+        .or_else(|_| recover_public_key(&secp, &compact, 0, &msg, None))?;
+    let key = bitcoin::PublicKey::from_slice(&public_key.serialize_vec(&secp, false))?;
+    Ok(match expected.compressed {
+        true => bitcoin::PublicKey::new(key.inner),
+        false => key,
+    })
+}
+
+/// The key as it appears in `address_type` scripts, segwit only allows
+/// compressed keys.
+fn script_key(public_key: &bitcoin::PublicKey, address_type: AddressType) -> bitcoin::PublicKey {
+    match address_type {
+        AddressType::P2pkh => *public_key,
+        _ => bitcoin::PublicKey::new(public_key.inner),
+    }
+}
+
+/// Fills in what the sighash of an `address_type` input needs beyond the
+/// spent output: the `sighash_type` and the p2wpkh redeem script of a
+/// p2sh-p2wpkh input.
+pub fn prepare_input(
+    psbt: &mut PartiallySignedTransaction,
+    index: usize,
+    public_key: &bitcoin::PublicKey,
+    address_type: AddressType,
+    sighash_type: EcdsaSighashType,
+) -> Result<()> {
+    // Without an output of the same index a legacy SIGHASH_SINGLE signs the
+    // constant 1, which anyone could reuse to spend the input.
+    let single = matches!(
+        sighash_type,
+        EcdsaSighashType::Single | EcdsaSighashType::SinglePlusAnyoneCanPay
+    );
+    if single && index >= psbt.unsigned_tx.output.len() {
+        bail!(
+            "input {} has no output of its own to sign with {}",
+            index,
+            sighash_type
+        );
+    }
+
+    let input = &mut psbt.inputs[index];
+    // BIP174 signers must use the sighash type the PSBT asks for.
+    match input.sighash_type {
+        Some(requested) if requested != sighash_type.into() => bail!(
+            "input {} asks for {}, not {}",
+            index,
+            requested,
+            sighash_type
+        ),
+        _ => input.sighash_type = Some(sighash_type.into()),
+    }
+    if address_type == AddressType::P2shP2wpkh && input.redeem_script.is_none() {
+        input.redeem_script = Some(locking_script(public_key, AddressType::P2wpkh)?);
+    }
+    Ok(())
+}
+
+/// Adds our signature to the given PSBT input. The input is finalized only
+/// when it spends our own `address_type` output, otherwise another signer
+/// still has to contribute.
+pub fn finalize_tx(
+    mut psbt: PartiallySignedTransaction,
+    index: usize,
+    public_key: &bitcoin::PublicKey,
+    address_type: AddressType,
+    signature: bitcoin::ecdsa::Signature,
+) -> Result<PartiallySignedTransaction> {
+    let key = script_key(public_key, address_type);
+    psbt.inputs[index].partial_sigs.insert(key, signature);
+
+    if spent_script_pubkey(&psbt, index) != Some(locking_script(public_key, address_type)?) {
+        return Ok(psbt);
+    }
+
+    let input = &mut psbt.inputs[index];
+    match address_type {
+        AddressType::P2pkh => {
+            let mut script_sig = ScriptBuf::new();
+            let mut v = PushBytesBuf::new();
+            v.extend_from_slice(&signature.to_vec())?;
+            script_sig.push_slice(&v);
+
+            let mut v = PushBytesBuf::new();
+            v.extend_from_slice(&key.to_bytes())?;
+            script_sig.push_slice(&v);
+            input.final_script_sig = Some(script_sig);
+        }
+        AddressType::P2wpkh | AddressType::P2shP2wpkh => {
+            if address_type == AddressType::P2shP2wpkh {
+                let redeem_script = locking_script(&key, AddressType::P2wpkh)?;
+                let mut v = PushBytesBuf::new();
+                v.extend_from_slice(redeem_script.as_bytes())?;
+                let mut script_sig = ScriptBuf::new();
+                script_sig.push_slice(&v);
+                input.final_script_sig = Some(script_sig);
+            }
+            input.final_script_witness =
+                Some(Witness::from_slice(&[signature.to_vec(), key.to_bytes()]));
+        }
+        AddressType::P2wsh => bail!("p2wsh inputs can't be finalized"),
+    }
+    input.partial_sigs.clear();
+
+    Ok(psbt)
+}
+
+/// Indexes of the PSBT inputs spending an output locked to the group key, in
+/// any of the supported address types.
+pub fn signable_inputs(
+    psbt: &PartiallySignedTransaction,
+    public_key: &bitcoin::PublicKey,
+) -> Vec<usize> {
+    let scripts: Vec<ScriptBuf> = AddressType::ALL
+        .iter()
+        .filter_map(|&address_type| locking_script(public_key, address_type).ok())
+        .collect();
+
+    (0..psbt.inputs.len())
+        .filter(|&index| {
+            spent_script_pubkey(psbt, index).map_or(false, |script| scripts.contains(&script))
+        })
+        .collect()
+}
+
+/// The vsize of the transaction once every input is finalized. Inputs still
+/// unsigned are counted with a maximum size signature and, for p2pkh, an
+/// uncompressed key, so the real vsize can only be smaller. `None` when an
+/// unsigned input is neither p2pkh, p2wpkh nor p2sh-p2wpkh, or its spent
+/// output is missing.
+pub fn finalized_vsize(psbt: &PartiallySignedTransaction) -> Option<usize> {
+    // 72 bytes of DER at most, plus the sighash type.
+    const SIGNATURE: usize = 73;
+    let witness = || Witness::from_slice(&[vec![0u8; SIGNATURE], vec![0u8; 33]]);
+
+    let mut tx = psbt.unsigned_tx.clone();
+    for (index, (txin, input)) in tx.input.iter_mut().zip(&psbt.inputs).enumerate() {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+            txin.witness = input.final_script_witness.clone().unwrap_or_default();
+            continue;
+        }
+        let script = spent_script_pubkey(psbt, index)?;
+        if script.is_p2pkh() {
+            txin.script_sig = ScriptBuf::from(vec![0u8; 1 + SIGNATURE + 1 + 65]);
+        } else if script.is_v0_p2wpkh() {
+            txin.witness = witness();
+        } else if script.is_p2sh()
+            && input
+                .redeem_script
+                .as_ref()
+                .map_or(true, |redeem| redeem.is_v0_p2wpkh())
+        {
+            // A push of the 22 byte p2wpkh redeem script.
+            txin.script_sig = ScriptBuf::from(vec![0u8; 23]);
+            txin.witness = witness();
+        } else {
+            return None;
+        }
+    }
+    Some(tx.vsize())
+}
+
+/// An output of the transaction being signed, as the review screen shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedOutput {
+    pub script_pubkey: ScriptBuf,
+    /// `None` for scripts without one, such as OP_RETURN.
+    pub address: Option<bitcoin::Address>,
+    pub value: u64,
+    /// Pays back to the group key, in any of the supported address types.
+    pub change: bool,
+}
+
+/// The outputs of the PSBT, with the addresses they pay on `network`. No
+/// output counts as change without the group key.
+pub fn decode_outputs(
+    psbt: &PartiallySignedTransaction,
+    public_key: Option<&bitcoin::PublicKey>,
+    network: bitcoin::Network,
+) -> Vec<DecodedOutput> {
+    let own_scripts: Vec<ScriptBuf> = match public_key {
+        Some(public_key) => AddressType::ALL
+            .iter()
+            .filter_map(|&address_type| locking_script(public_key, address_type).ok())
+            .collect(),
+        None => Vec::new(),
+    };
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .map(|output| DecodedOutput {
+            script_pubkey: output.script_pubkey.clone(),
+            address: bitcoin::Address::from_script(&output.script_pubkey, network).ok(),
+            value: output.value,
+            change: own_scripts.contains(&output.script_pubkey),
+        })
+        .collect()
+}
+
+/// Indexes of the PSBT inputs spending the script multisig.
+pub fn multisig_inputs(psbt: &PartiallySignedTransaction, multisig: &ScriptMultisig) -> Vec<usize> {
+    let script_pubkey = multisig.script_pubkey();
+    (0..psbt.inputs.len())
+        .filter(|&index| spent_script_pubkey(psbt, index).as_ref() == Some(&script_pubkey))
+        .collect()
+}
+
+/// Signs a script multisig input with our share's key alone, and completes its
+/// witness once enough parties have signed.
+pub fn sign_multisig_input(
+    psbt: &mut PartiallySignedTransaction,
+    index: usize,
+    multisig: &ScriptMultisig,
+    secret_key: &bitcoin::secp256k1::SecretKey,
+    sighash_type: EcdsaSighashType,
+) -> Result<()> {
+    let secp = bitcoin::secp256k1::Secp256k1::new();
+    let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp));
+    if !multisig.keys.contains(&public_key) {
+        bail!("the share's own key {} is not in the multisig", public_key);
+    }
+    let script = multisig.witness_script();
+    let input = &mut psbt.inputs[index];
+    if input
+        .witness_script
+        .as_ref()
+        .map_or(false, |existing| existing != &script)
+    {
+        bail!(
+            "input {} carries another witness script than the multisig's",
+            index
+        );
+    }
+    input.witness_script = Some(script);
+    prepare_input(psbt, index, &public_key, AddressType::P2wsh, sighash_type)?;
+
+    let data = input_sighash(psbt, index)?;
+    let signature = bitcoin::ecdsa::Signature {
+        sig: secp.sign_ecdsa(&bitcoin::secp256k1::Message::from_slice(&data)?, secret_key),
+        hash_ty: sighash_type,
+    };
+    verify_signature(&signature, &data, &public_key)?;
+    psbt.inputs[index]
+        .partial_sigs
+        .insert(public_key, signature);
+    finalize_multisig(&mut psbt.inputs[index], multisig);
+    Ok(())
+}
+
+/// Builds the witness from the first `required` signatures in key order, after
+/// the dummy element `OP_CHECKMULTISIG` pops, or leaves the input for the
+/// next signer while there are fewer.
+fn finalize_multisig(input: &mut bitcoin::psbt::Input, multisig: &ScriptMultisig) {
+    let signatures: Vec<Vec<u8>> = multisig
+        .keys
+        .iter()
+        .filter_map(|key| input.partial_sigs.get(key))
+        .map(|signature| signature.to_vec())
+        .take(multisig.required)
+        .collect();
+    if signatures.len() < multisig.required {
+        return;
+    }
+
+    let mut witness = Witness::new();
+    witness.push(Vec::new());
+    for signature in signatures {
+        witness.push(signature);
+    }
+    witness.push(multisig.witness_script().as_bytes());
+    input.final_script_witness = Some(witness);
+    input.partial_sigs.clear();
+}
+
+/// Indexes of the PSBT inputs that have neither a final scriptSig nor a final
+/// witness yet.
+pub fn unfinalized_inputs(psbt: &PartiallySignedTransaction) -> Vec<usize> {
+    psbt.inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, input)| {
+            input.final_script_sig.is_none() && input.final_script_witness.is_none()
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Runs the script interpreter over the given finalized inputs, so that a
+/// transaction nodes would reject is never handed out. Inputs whose spent
+/// output the PSBT doesn't carry can't be checked and are skipped.
+pub fn verify_inputs(
+    psbt: &PartiallySignedTransaction,
+    inputs: &[usize],
+) -> Result<(), BoomerError> {
+    let tx = bitcoin::consensus::serialize(&psbt.clone().extract_tx());
+    for &input in inputs {
+        if let Some(spent) = spent_output(psbt, input) {
+            spent
+                .script_pubkey
+                .verify(input, bitcoin::Amount::from_sat(spent.value), &tx)
+                .map_err(|e| BoomerError::ScriptVerificationFailed {
+                    input,
+                    reason: e.to_string(),
+                })?;
+        }
+    }
+    std::result::Result::Ok(())
+}
+
+pub fn is_fully_signed(psbt: &PartiallySignedTransaction) -> bool {
+    unfinalized_inputs(psbt).is_empty()
+}
+
+/// Extracts the network transaction, refusing to do so while any input is
+/// unfinalized: `extract_tx` would otherwise leave those scriptSigs empty and
+/// produce a raw tx that looks complete but fails validation.
+pub fn extract_signed_tx(psbt: PartiallySignedTransaction) -> Result<Transaction> {
+    let inputs = unfinalized_inputs(&psbt);
+    if !inputs.is_empty() {
+        return Err(BoomerError::UnsignedInputs { inputs }.into());
+    }
+    Ok(psbt.extract_tx())
+}
+
+#[tracing::instrument(
+    skip(args),
+    fields(room = %args.room, party = args.idx, signers = ?args.parties),
+    err(Debug)
+)]
+pub async fn do_sign(args: SigningConfig) -> std::result::Result<SigningResult, SigningError> {
+    check_parties(&args.parties, args.idx).map_err(|e| SigningError::JoinError(e.into()))?;
+    if !args.transaction && args.bip322 {
+        return sign_bip322(&args).await;
+    }
+    if !args.transaction {
+        let data = message_digest(&args.data_to_sign, args.hash_scheme)
+            .map_err(SigningError::SighashError)?;
+        let (_, public_key) = sign_digest(&args, &args.room, &data).await?;
+        return signing_result(&args, &public_key).map_err(SigningError::ShareParseError);
     }
 
-    let public_key = bitcoin::PublicKey::from_slice(&hex::decode(&public_key_hex)?)?;
-    let address = bitcoin::Address::p2pkh(&public_key, bitcoin::Network::Signet);
+    let mut psbt = parse_psbt(&args.data_to_sign).map_err(SigningError::PsbtParseError)?;
+    check_psbt_network(&psbt, args.network).map_err(|e| SigningError::PsbtParseError(e.into()))?;
+    if args.rbf {
+        signal_rbf(&mut psbt).map_err(SigningError::PsbtParseError)?;
+    }
+    if args.script_multisig {
+        return sign_multisig(&args, psbt).await;
+    }
+    let local_share = load_share(
+        &args.local_share,
+        args.idx,
+        args.network,
+        args.passphrase.as_deref(),
+    )
+    .await?;
+    let group_key = share_public_key(&local_share).map_err(SigningError::ShareParseError)?;
+    // Whether an input takes a legacy or a segwit sighash and scriptSig or
+    // witness depends on the output it spends, which the PSBT must carry.
+    let candidates = match args.inputs {
+        InputSelection::All => unfinalized_inputs(&psbt),
+        InputSelection::Single(index) => vec![index],
+    };
+    let missing: Vec<usize> = candidates
+        .into_iter()
+        .filter(|&index| index < psbt.inputs.len() && spent_output(&psbt, index).is_none())
+        .collect();
+    if !missing.is_empty() {
+        return Err(SigningError::PsbtParseError(
+            BoomerError::MissingUtxo { inputs: missing }.into(),
+        ));
+    }
+    let mut inputs = Vec::new();
+    for index in selected_inputs(&psbt, &group_key, args.inputs) {
+        match input_address_type(&psbt, index, &group_key) {
+            std::result::Result::Ok(Some(AddressType::P2wsh)) => {
+                return Err(SigningError::PsbtParseError(anyhow!(
+                    "input {} spends a p2wsh output, which can't be signed yet",
+                    index
+                )))
+            }
+            std::result::Result::Ok(Some(address_type)) => inputs.push((index, address_type)),
+            std::result::Result::Ok(None) => {}
+            Err(e) => return Err(SigningError::PsbtParseError(e.into())),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(SigningError::PsbtParseError(anyhow!(
+            "no selected input spends the group key"
+        )));
+    }
+    if inputs.len() > 1
+        && (args.save_offline_stage.is_some() || args.resume_offline_stage.is_some())
+    {
+        return Err(SigningError::OfflineStageError(anyhow!(
+            "offline stages can only be saved when signing a single input"
+        )));
+    }
+
+    // Each input is its own ceremony, with its own digest and offline stage.
+    for &(index, address_type) in &inputs {
+        prepare_input(
+            &mut psbt,
+            index,
+            &group_key,
+            address_type,
+            args.sighash_type,
+        )
+        .map_err(SigningError::SighashError)?;
+        let data = input_sighash(&psbt, index).map_err(SigningError::SighashError)?;
+        let room = match inputs.len() {
+            1 => args.room.clone(),
+            _ => format!("{}-input{}", args.room, index),
+        };
+        let (signature, public_key) = sign_digest(&args, &room, &data).await?;
+        psbt = finalize_tx(psbt, index, &public_key, address_type, signature)
+            .map_err(SigningError::IncompleteSignature)?;
+    }
+    let inputs: Vec<usize> = inputs.into_iter().map(|(index, _)| index).collect();
+    // Every input signed here spends our own script, so each must now be final.
+    let unsigned: Vec<usize> = unfinalized_inputs(&psbt)
+        .into_iter()
+        .filter(|index| inputs.contains(index))
+        .collect();
+    if !unsigned.is_empty() {
+        return Err(SigningError::IncompleteSignature(
+            BoomerError::UnsignedInputs { inputs: unsigned }.into(),
+        ));
+    }
+    verify_inputs(&psbt, &inputs).map_err(|e| SigningError::IncompleteSignature(e.into()))?;
 
+    let mut result = signing_result(&args, &group_key).map_err(SigningError::ShareParseError)?;
+    result.signed_inputs = inputs;
+    complete(result, psbt)
+}
+
+/// `do_sign` for a BIP322 proof: the ceremony signs the one input of the
+/// message's virtual `to_sign` transaction as it would a PSBT input.
+async fn sign_bip322(args: &SigningConfig) -> std::result::Result<SigningResult, SigningError> {
+    if args.sighash_type != EcdsaSighashType::All {
+        return Err(SigningError::SighashError(anyhow!(
+            "BIP322 proofs are signed with SIGHASH_ALL, not {}",
+            args.sighash_type
+        )));
+    }
+    let local_share = load_share(
+        &args.local_share,
+        args.idx,
+        args.network,
+        args.passphrase.as_deref(),
+    )
+    .await?;
+    let group_key = share_public_key(&local_share).map_err(SigningError::ShareParseError)?;
+    let mut psbt = locking_script(&group_key, args.address_type)
+        .and_then(|script_pubkey| bs_bip322::to_sign(&script_pubkey, &args.data_to_sign))
+        .map_err(SigningError::SighashError)?;
+    prepare_input(
+        &mut psbt,
+        0,
+        &group_key,
+        args.address_type,
+        args.sighash_type,
+    )
+    .map_err(SigningError::SighashError)?;
+    let data = input_sighash(&psbt, 0).map_err(SigningError::SighashError)?;
+    let (signature, public_key) = sign_digest(args, &args.room, &data).await?;
+    let psbt = finalize_tx(psbt, 0, &public_key, args.address_type, signature)
+        .map_err(SigningError::IncompleteSignature)?;
+    verify_inputs(&psbt, &[0]).map_err(|e| SigningError::IncompleteSignature(e.into()))?;
+
+    let mut result = signing_result(args, &group_key).map_err(SigningError::ShareParseError)?;
+    result.bip322_signature = Some(
+        bs_bip322::encode_signature(&psbt, args.address_type)
+            .map_err(SigningError::IncompleteSignature)?,
+    );
+    std::result::Result::Ok(result)
+}
+
+/// Hands out the transaction once every input is signed, the PSBT otherwise.
+fn complete(
+    mut result: SigningResult,
+    psbt: PartiallySignedTransaction,
+) -> std::result::Result<SigningResult, SigningError> {
+    if is_fully_signed(&psbt) {
+        let tx = extract_signed_tx(psbt).map_err(SigningError::IncompleteSignature)?;
+        result.signined_tx = Some(serialize_hex(&tx));
+    } else {
+        result.partially_signed_psbt = Some(psbt.to_string());
+    }
+    std::result::Result::Ok(result)
+}
+
+/// `do_sign` for the script multisig. Each signer adds its own signature and
+/// passes the PSBT on, whoever brings it to the required count completes it.
+async fn sign_multisig(
+    args: &SigningConfig,
+    mut psbt: PartiallySignedTransaction,
+) -> std::result::Result<SigningResult, SigningError> {
+    let local_share = load_share(
+        &args.local_share,
+        args.idx,
+        args.network,
+        args.passphrase.as_deref(),
+    )
+    .await?;
+    let (multisig, secret_key, group_key) = share_multisig(&local_share)
+        .and_then(|multisig| {
+            Ok((
+                multisig,
+                share_secret_key(&local_share)?,
+                share_public_key(&local_share)?,
+            ))
+        })
+        .map_err(SigningError::ShareParseError)?;
+    let inputs = args.inputs.pick(multisig_inputs(&psbt, &multisig));
+    if inputs.is_empty() {
+        return Err(SigningError::PsbtParseError(anyhow!(
+            "no selected input spends the multisig script"
+        )));
+    }
+
+    for &index in &inputs {
+        sign_multisig_input(&mut psbt, index, &multisig, &secret_key, args.sighash_type)
+            .map_err(SigningError::SighashError)?;
+    }
+    let unfinalized = unfinalized_inputs(&psbt);
+    let finalized: Vec<usize> = inputs
+        .iter()
+        .copied()
+        .filter(|index| !unfinalized.contains(index))
+        .collect();
+    verify_inputs(&psbt, &finalized).map_err(|e| SigningError::IncompleteSignature(e.into()))?;
+
+    let mut result = signing_result(args, &group_key).map_err(SigningError::ShareParseError)?;
+    result.address = multisig.address(args.network).to_string();
+    result.parties = vec![args.idx];
+    result.signed_inputs = inputs;
+    complete(result, psbt)
+}
+
+fn signing_result(args: &SigningConfig, public_key: &bitcoin::PublicKey) -> Result<SigningResult> {
+    let address = args
+        .address_type
+        .deriver()
+        .derive(public_key, args.network)?;
     Ok(SigningResult {
-        pubkey: public_key_hex,
+        pubkey: hex::encode(public_key.to_bytes()),
         address: address.to_string(),
-        out_dir: args.local_share,
+        out_dir: args.local_share.clone(),
+        parties: args.parties.clone(),
+        signed_inputs: Vec::new(),
         signined_tx: None,
+        dry_run: args.dry_run,
+        partially_signed_psbt: None,
+        bip322_signature: None,
+        broadcast_txid: None,
     })
 }
+
+async fn signers_lobby(
+    args: &SigningConfig,
+    room: &str,
+    group_size: u16,
+) -> std::result::Result<(), SigningError> {
+    wait_in_lobby(
+        args.address.clone(),
+        room,
+        &args.relay_options,
+        args.idx,
+        &args.parties,
+        group_size,
+        &args.progress,
+    )
+    .await
+    .map_err(SigningError::JoinError)
+}
+
+/// Runs both stages of one signature over `digest` in `room`.
+#[tracing::instrument(skip(args, digest))]
+async fn sign_digest(
+    args: &SigningConfig,
+    room: &str,
+    digest: &[u8],
+) -> std::result::Result<(bitcoin::ecdsa::Signature, bitcoin::PublicKey), SigningError> {
+    let completed_offline_stage = match &args.resume_offline_stage {
+        Some(path) => {
+            let stage = load_offline_stage(path, digest, args.idx, &args.parties)
+                .map_err(SigningError::OfflineStageError)?;
+            // The share isn't loaded, its group size is only known to be at
+            // least the highest signer.
+            let group_size = args.parties.iter().copied().max().unwrap_or(args.idx);
+            signers_lobby(args, room, group_size).await?;
+            stage
+        }
+        None => {
+            let local_share = load_share(
+                &args.local_share,
+                args.idx,
+                args.network,
+                args.passphrase.as_deref(),
+            )
+            .await?;
+            check_signer_count(&args.parties, local_share.t, local_share.n)
+                .map_err(|e| SigningError::JoinError(e.into()))?;
+            signers_lobby(args, room, local_share.n).await?;
+            let stage = run_offline_stage(
+                args.address.clone(),
+                &args.relay_options,
+                room,
+                args.idx,
+                args.parties.clone(),
+                local_share,
+                &args.progress,
+                args.timeouts.offline,
+            )
+            .await
+            .map_err(SigningError::OfflineStageError)?;
+            if let Some(path) = &args.save_offline_stage {
+                save_offline_stage(
+                    path,
+                    &SavedOfflineStage {
+                        digest: hex::encode(digest),
+                        idx: args.idx,
+                        parties: args.parties.clone(),
+                        stage: stage.clone(),
+                    },
+                )
+                .map_err(SigningError::OfflineStageError)?;
+            }
+            stage
+        }
+    };
+
+    let group_key = completed_offline_stage
+        .public_key()
+        .to_bytes(false)
+        .to_vec();
+    let signature = run_online_stage(
+        args.address.clone(),
+        &args.relay_options,
+        room,
+        digest,
+        completed_offline_stage,
+        args.parties.len(),
+        &args.progress,
+        args.timeouts.online,
+    )
+    .await
+    .map_err(SigningError::ProtocolError)?;
+
+    // The offline stage is spent, keeping it around only risks reusing it.
+    for path in args
+        .resume_offline_stage
+        .iter()
+        .chain(&args.save_offline_stage)
+    {
+        let _ = std::fs::remove_file(path);
+    }
+
+    assemble_signature(&signature, digest, Some(&group_key), args.sighash_type)
+        .map_err(SigningError::ProtocolError)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::{OutPoint, Sequence, TxIn, Witness};
+
+    fn test_signature() -> (bitcoin::ecdsa::Signature, bitcoin::PublicKey) {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let msg = bitcoin::secp256k1::Message::from_slice(&[2; 32]).unwrap();
+        let sig = secp.sign_ecdsa(&msg, &secret_key);
+        (
+            bitcoin::ecdsa::Signature::sighash_all(sig),
+            bitcoin::PublicKey::new_uncompressed(secret_key.public_key(&secp)),
+        )
+    }
+
+    pub(crate) fn psbt_spending(script_pubkey: ScriptBuf) -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 900,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1000,
+            script_pubkey,
+        });
+        psbt
+    }
+
+    #[test]
+    fn psbts_parse_from_base64_or_hex() {
+        let psbt = psbt_spending(ScriptBuf::new());
+        let base64 = psbt.to_string();
+        let hex = hex::encode(psbt.serialize());
+
+        assert_eq!(parse_psbt(&base64).unwrap(), psbt);
+        assert_eq!(parse_psbt(&hex.to_uppercase()).unwrap(), psbt);
+        // As a wrapped paste comes out of the PSBT field.
+        let (head, tail) = base64.split_at(20);
+        assert_eq!(
+            parse_psbt(&format!("\n  {}\n{}\n", head, tail)).unwrap(),
+            psbt
+        );
+        assert!(parse_psbt("deadbeef").is_err());
+    }
+
+    #[test]
+    fn message_digest_hashes_plain_data() {
+        assert_eq!(
+            hex::encode(message_digest("abc", HashScheme::Sha256).unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn message_digest_signs_raw_digests_as_is() {
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(
+            hex::encode(message_digest(digest, HashScheme::RawDigest).unwrap()),
+            digest
+        );
+        assert!(message_digest("abcd", HashScheme::RawDigest).is_err());
+        assert!(message_digest("not hex", HashScheme::RawDigest).is_err());
+    }
+
+    #[test]
+    fn message_digest_double_hashes_plain_data() {
+        assert_eq!(
+            hex::encode(message_digest("abc", HashScheme::DoubleSha256).unwrap()),
+            "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"
+        );
+    }
+
+    #[test]
+    fn message_digest_prefixes_bitcoin_messages() {
+        assert_eq!(
+            hex::encode(message_digest("abc", HashScheme::BitcoinMessage).unwrap()),
+            "107a79deef4b17e0de11070a45433aebc60ab26cd3d3a0f3735accefd0fd373b"
+        );
+    }
+
+    #[test]
+    fn finalized_vsize_bounds_the_signed_transaction() {
+        let (signature, public_key) = test_signature();
+        let address = bitcoin::Address::p2pkh(&public_key, bitcoin::Network::Signet);
+        let psbt = psbt_spending(address.script_pubkey());
+        let estimate = finalized_vsize(&psbt).unwrap();
+
+        let psbt = finalize_tx(psbt, 0, &public_key, AddressType::P2pkh, signature).unwrap();
+        let vsize = psbt.clone().extract_tx().vsize();
+        assert_eq!(finalized_vsize(&psbt), Some(vsize));
+        assert!(estimate >= vsize && estimate - vsize < 4);
+
+        // 60 bytes without witness, 111 of witness.
+        let compressed = bitcoin::PublicKey::new(public_key.inner);
+        let p2wpkh = bitcoin::Address::p2wpkh(&compressed, bitcoin::Network::Signet).unwrap();
+        assert_eq!(
+            finalized_vsize(&psbt_spending(p2wpkh.script_pubkey())),
+            Some(88)
+        );
+        assert_eq!(finalized_vsize(&psbt_spending(ScriptBuf::new())), None);
+    }
+
+    #[test]
+    fn decode_outputs_tells_change_apart() {
+        let (_, public_key) = test_signature();
+        let change = bitcoin::Address::p2pkh(&public_key, bitcoin::Network::Signet);
+        let mut psbt = psbt_spending(ScriptBuf::new());
+        psbt.unsigned_tx.output.push(TxOut {
+            value: 50,
+            script_pubkey: change.script_pubkey(),
+        });
+
+        let outputs = decode_outputs(&psbt, Some(&public_key), bitcoin::Network::Signet);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!((outputs[0].address.as_ref(), outputs[0].value), (None, 900));
+        assert!(!outputs[0].change);
+        assert_eq!(outputs[1].address.as_ref(), Some(&change));
+        assert!(outputs[1].change);
+
+        let outputs = decode_outputs(&psbt, None, bitcoin::Network::Signet);
+        assert!(outputs.iter().all(|output| !output.change));
+    }
+
+    #[test]
+    fn multisig_input_completes_with_the_required_signatures() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let secret_keys: Vec<SecretKey> = (1..=3)
+            .map(|n| SecretKey::from_slice(&[n; 32]).unwrap())
+            .collect();
+        let keys = secret_keys
+            .iter()
+            .map(|key| bitcoin::PublicKey::new(key.public_key(&secp)))
+            .collect();
+        let multisig = ScriptMultisig::new(2, keys).unwrap();
+        let mut psbt = psbt_spending(multisig.script_pubkey());
+        assert_eq!(multisig_inputs(&psbt, &multisig), vec![0]);
+
+        // Signed out of key order, the witness still follows the script.
+        let all = EcdsaSighashType::All;
+        sign_multisig_input(&mut psbt, 0, &multisig, &secret_keys[2], all).unwrap();
+        assert!(!is_fully_signed(&psbt));
+        sign_multisig_input(&mut psbt, 0, &multisig, &secret_keys[0], all).unwrap();
+        assert!(is_fully_signed(&psbt));
+        assert_eq!(
+            psbt.inputs[0].final_script_witness.as_ref().unwrap().len(),
+            4
+        );
+        verify_inputs(&psbt, &[0]).unwrap();
+
+        let stranger = SecretKey::from_slice(&[9; 32]).unwrap();
+        let mut psbt = psbt_spending(multisig.script_pubkey());
+        assert!(sign_multisig_input(&mut psbt, 0, &multisig, &stranger, all).is_err());
+    }
+
+    #[test]
+    fn finalize_tx_completes_own_input() {
+        let (signature, public_key) = test_signature();
+        let address = bitcoin::Address::p2pkh(&public_key, bitcoin::Network::Signet);
+        let psbt = finalize_tx(
+            psbt_spending(address.script_pubkey()),
+            0,
+            &public_key,
+            AddressType::P2pkh,
+            signature,
+        )
+        .unwrap();
+
+        assert!(is_fully_signed(&psbt));
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+    }
+
+    #[test]
+    fn finalize_tx_fills_the_witness_of_segwit_inputs() {
+        let (signature, public_key) = test_signature();
+        let key = bitcoin::PublicKey::new(public_key.inner);
+        for address_type in [AddressType::P2wpkh, AddressType::P2shP2wpkh] {
+            let address = address_type
+                .deriver()
+                .derive(&public_key, DEFAULT_NETWORK)
+                .unwrap();
+            let mut psbt = psbt_spending(address.script_pubkey());
+            prepare_input(
+                &mut psbt,
+                0,
+                &public_key,
+                address_type,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+            assert!(input_sighash(&psbt, 0).is_ok());
+
+            let psbt = finalize_tx(psbt, 0, &public_key, address_type, signature).unwrap();
+            let input = &psbt.inputs[0];
+            assert_eq!(
+                input.final_script_witness,
+                Some(Witness::from_slice(&[signature.to_vec(), key.to_bytes()]))
+            );
+            assert_eq!(
+                input.final_script_sig.is_some(),
+                address_type == AddressType::P2shP2wpkh
+            );
+        }
+    }
+
+    #[test]
+    fn compact_signature_left_pads_both_scalars() {
+        // r and s of 31 and 30 bytes, as BigInt encodes scalars with leading
+        // zero bytes.
+        let r = BigInt::from_hex(&format!("ab{}", "11".repeat(30))).unwrap();
+        let s = BigInt::from_hex(&format!("cd{}", "22".repeat(29))).unwrap();
+        assert_eq!(r.to_bytes().len(), 31);
+
+        let compact = compact_signature(&r.to_bytes(), &s.to_bytes()).unwrap();
+        assert_eq!(
+            hex::encode(compact),
+            format!("00ab{}0000cd{}", "11".repeat(30), "22".repeat(29))
+        );
+        assert_eq!(compact_signature(&[], &[1]).unwrap()[..63], [0; 63][..]);
+        assert!(compact_signature(&[1; 33], &[1]).is_err());
+    }
+
+    #[test]
+    fn short_r_still_produces_a_valid_transaction() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp));
+        let group_key = public_key.inner.serialize_uncompressed();
+        let address = AddressType::P2wpkh
+            .deriver()
+            .derive(&public_key, DEFAULT_NETWORK)
+            .unwrap();
+
+        // Vary the transaction until its signature's r starts with a zero
+        // byte, which happens once every 256 signatures on average.
+        let (psbt, digest, recid, compact) = (0..4096)
+            .find_map(|value| {
+                let mut psbt = psbt_spending(address.script_pubkey());
+                psbt.unsigned_tx.output[0].value = value;
+                let all = EcdsaSighashType::All;
+                prepare_input(&mut psbt, 0, &public_key, AddressType::P2wpkh, all).unwrap();
+                let digest = input_sighash(&psbt, 0).unwrap();
+                let msg = bitcoin::secp256k1::Message::from_slice(&digest).unwrap();
+                let (recid, compact) = secp
+                    .sign_ecdsa_recoverable(&msg, &secret_key)
+                    .serialize_compact();
+                match compact[0] {
+                    0 => Some((psbt, digest, recid.to_i32() as u8, compact)),
+                    _ => None,
+                }
+            })
+            .unwrap();
+
+        let r = BigInt::from_bytes(&compact[..32]).to_bytes();
+        let s = BigInt::from_bytes(&compact[32..]).to_bytes();
+        assert!(r.len() < 32);
+        let (signature, key) = signature_from_scalars(
+            &r,
+            &s,
+            recid,
+            &digest,
+            Some(&group_key),
+            EcdsaSighashType::All,
+        )
+        .unwrap();
+        assert_eq!(&signature.sig.serialize_compact()[..], &compact[..]);
+
+        let psbt = finalize_tx(psbt, 0, &key, AddressType::P2wpkh, signature).unwrap();
+        assert!(verify_inputs(&psbt, &[0]).is_ok());
+    }
+
+    #[test]
+    fn mixed_inputs_are_each_signed_by_their_own_type() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp));
+        let script = |address_type: AddressType| locking_script(&public_key, address_type).unwrap();
+        let previous = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: script(AddressType::P2pkh),
+            }],
+        };
+        let spending = |previous_output| TxIn {
+            previous_output,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                spending(OutPoint::new(previous.txid(), 0)),
+                spending(OutPoint::null()),
+                spending(OutPoint::null()),
+            ],
+            output: vec![TxOut {
+                value: 1500,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        // A legacy input with its previous transaction, a segwit one with only
+        // its spent output, and one carrying neither.
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(previous);
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 1000,
+            script_pubkey: script(AddressType::P2wpkh),
+        });
+
+        assert_eq!(
+            input_address_type(&psbt, 0, &public_key).unwrap(),
+            Some(AddressType::P2pkh)
+        );
+        assert_eq!(
+            input_address_type(&psbt, 1, &public_key).unwrap(),
+            Some(AddressType::P2wpkh)
+        );
+        assert!(matches!(
+            input_address_type(&psbt, 2, &public_key),
+            Err(BoomerError::MissingUtxo { inputs }) if inputs == vec![2]
+        ));
+
+        for index in 0..2 {
+            let address_type = input_address_type(&psbt, index, &public_key)
+                .unwrap()
+                .unwrap();
+            prepare_input(
+                &mut psbt,
+                index,
+                &public_key,
+                address_type,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+            let digest = input_sighash(&psbt, index).unwrap();
+            let msg = bitcoin::secp256k1::Message::from_slice(&digest).unwrap();
+            let signature =
+                bitcoin::ecdsa::Signature::sighash_all(secp.sign_ecdsa(&msg, &secret_key));
+            psbt = finalize_tx(psbt, index, &public_key, address_type, signature).unwrap();
+        }
+        assert!(psbt.inputs[0].final_script_sig.is_some());
+        assert!(psbt.inputs[0].final_script_witness.is_none());
+        assert!(psbt.inputs[1].final_script_sig.is_none());
+        assert!(psbt.inputs[1].final_script_witness.is_some());
+        verify_inputs(&psbt, &[0, 1]).unwrap();
+        assert_eq!(unfinalized_inputs(&psbt), vec![2]);
+    }
+
+    #[test]
+    fn signatures_are_verified_against_the_digest() {
+        let (signature, public_key) = test_signature();
+        assert!(verify_signature(&signature, &[2; 32], &public_key).is_ok());
+        assert!(matches!(
+            verify_signature(&signature, &[3; 32], &public_key),
+            Err(BoomerError::InvalidSignature { .. })
+        ));
+    }
+
+    #[test]
+    fn verify_inputs_runs_the_script_interpreter() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp));
+        let sign = |digest: &[u8]| {
+            let msg = bitcoin::secp256k1::Message::from_slice(digest).unwrap();
+            bitcoin::ecdsa::Signature::sighash_all(secp.sign_ecdsa(&msg, &secret_key))
+        };
+        let address = AddressType::P2wpkh
+            .deriver()
+            .derive(&public_key, DEFAULT_NETWORK)
+            .unwrap();
+        let mut psbt = psbt_spending(address.script_pubkey());
+        let all = EcdsaSighashType::All;
+        prepare_input(&mut psbt, 0, &public_key, AddressType::P2wpkh, all).unwrap();
+
+        let signature = sign(&input_sighash(&psbt, 0).unwrap());
+        let signed = finalize_tx(psbt.clone(), 0, &public_key, AddressType::P2wpkh, signature);
+        assert!(verify_inputs(&signed.unwrap(), &[0]).is_ok());
+
+        // Valid for some other digest, but not for this transaction.
+        let signature = sign(&[3; 32]);
+        let forged = finalize_tx(psbt, 0, &public_key, AddressType::P2wpkh, signature).unwrap();
+        assert!(matches!(
+            verify_inputs(&forged, &[0]),
+            Err(BoomerError::ScriptVerificationFailed { input: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn prepare_input_records_the_sighash_type() {
+        let (_, public_key) = test_signature();
+        let address = bitcoin::Address::p2pkh(&public_key, DEFAULT_NETWORK);
+        let mut psbt = psbt_spending(address.script_pubkey());
+        let none = EcdsaSighashType::NonePlusAnyoneCanPay;
+        prepare_input(&mut psbt, 0, &public_key, AddressType::P2pkh, none).unwrap();
+        assert_eq!(psbt.inputs[0].sighash_type, Some(none.into()));
+        assert!(input_sighash(&psbt, 0).is_ok());
+
+        // The PSBT already asks for another type.
+        let all = EcdsaSighashType::All;
+        assert!(prepare_input(&mut psbt, 0, &public_key, AddressType::P2pkh, all).is_err());
+
+        // SIGHASH_SINGLE without a matching output.
+        psbt.unsigned_tx.output.clear();
+        psbt.inputs[0].sighash_type = None;
+        let single = EcdsaSighashType::Single;
+        assert!(prepare_input(&mut psbt, 0, &public_key, AddressType::P2pkh, single).is_err());
+    }
+
+    #[test]
+    fn sighash_types_parse_by_name_or_value() {
+        assert_eq!(
+            parse_sighash_type("single|anyonecanpay").unwrap(),
+            EcdsaSighashType::SinglePlusAnyoneCanPay
+        );
+        assert_eq!(
+            parse_sighash_type("SIGHASH_NONE").unwrap(),
+            EcdsaSighashType::None
+        );
+        assert_eq!(
+            parse_sighash_type("0x81").unwrap(),
+            EcdsaSighashType::AllPlusAnyoneCanPay
+        );
+        assert!(parse_sighash_type("0x04").is_err());
+        assert!(parse_sighash_type("anyonecanpay").is_err());
+    }
+
+    #[test]
+    fn signable_inputs_matches_own_scripts() {
+        let (_, public_key) = test_signature();
+        let address = AddressType::P2wpkh
+            .deriver()
+            .derive(&public_key, bitcoin::Network::Signet)
+            .unwrap();
+
+        assert_eq!(
+            signable_inputs(&psbt_spending(address.script_pubkey()), &public_key),
+            vec![0]
+        );
+        assert!(signable_inputs(&psbt_spending(ScriptBuf::new()), &public_key).is_empty());
+    }
+
+    #[test]
+    fn selected_inputs_narrows_signable_inputs() {
+        let (_, public_key) = test_signature();
+        let address = bitcoin::Address::p2pkh(&public_key, DEFAULT_NETWORK);
+        let mut psbt = psbt_spending(address.script_pubkey());
+        for _ in 0..2 {
+            psbt.unsigned_tx
+                .input
+                .push(psbt.unsigned_tx.input[0].clone());
+            psbt.inputs.push(psbt.inputs[0].clone());
+        }
+        psbt.inputs[1].witness_utxo = None;
+
+        assert_eq!(
+            selected_inputs(&psbt, &public_key, InputSelection::All),
+            vec![0, 2]
+        );
+        assert_eq!(
+            selected_inputs(&psbt, &public_key, InputSelection::Single(2)),
+            vec![2]
+        );
+        assert!(selected_inputs(&psbt, &public_key, InputSelection::Single(1)).is_empty());
+    }
+
+    #[test]
+    fn parties_must_include_our_index() {
+        let parties = parse_parties("1, 3,").unwrap();
+        assert_eq!(parties, vec![1, 3]);
+        assert!(check_parties(&parties, 3).is_ok());
+
+        let err = check_parties(&parties, 2).unwrap_err();
+        assert!(matches!(
+            &err,
+            BoomerError::SignerNotInParties { suggestion, .. } if suggestion == &vec![1, 2, 3]
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Your index (2) must be included in the parties list, e.g. [1, 2, 3]"
+        );
+        assert!(parse_parties("1, two").is_err());
+    }
+
+    #[test]
+    fn signing_room_depends_on_the_transaction_and_signers() {
+        let (signature, public_key) = test_signature();
+        let address = bitcoin::Address::p2pkh(&public_key, DEFAULT_NETWORK);
+        let psbt = psbt_spending(address.script_pubkey());
+        let mut signed = psbt.clone();
+        signed.inputs[0].partial_sigs.insert(public_key, signature);
+
+        let room = signing_room(&psbt.to_string(), &[1, 3], 0, false);
+        assert_eq!(room, signing_room(&signed.to_string(), &[3, 1], 0, false));
+        assert_ne!(room, signing_room(&psbt.to_string(), &[1, 2], 0, false));
+        assert_ne!(room, signing_room(&psbt.to_string(), &[1, 3], 1, false));
+        assert_ne!(
+            signing_room("hello", &[1, 3], 0, false),
+            signing_room("hello!", &[1, 3], 0, false)
+        );
+    }
+
+    #[test]
+    fn signers_differing_in_rbf_meet_in_different_rooms() {
+        let psbt = psbt_spending(ScriptBuf::new());
+        let room = signing_room(&psbt.to_string(), &[1, 2], 0, false);
+        let rbf_room = signing_room(&psbt.to_string(), &[1, 2], 0, true);
+        assert_ne!(room, rbf_room);
+
+        // A PSBT that already signals is signed as it is either way.
+        let mut signaling = psbt;
+        signal_rbf(&mut signaling).unwrap();
+        assert_eq!(
+            signing_room(&signaling.to_string(), &[1, 2], 0, false),
+            rbf_room
+        );
+        assert_eq!(
+            signing_room(&signaling.to_string(), &[1, 2], 0, true),
+            rbf_room
+        );
+    }
+
+    #[test]
+    fn signers_must_reach_the_threshold() {
+        assert!(check_signer_count(&[1, 3, 5], 2, 5).is_ok());
+        assert!(matches!(
+            check_signer_count(&[1, 3, 3], 2, 5),
+            Err(BoomerError::TooFewSigners { needed: 3, got: 2 })
+        ));
+        assert!(matches!(
+            check_signer_count(&[1, 6], 1, 5),
+            Err(BoomerError::SignerOutOfRange { party: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn finalize_tx_leaves_foreign_input_partially_signed() {
+        let (signature, public_key) = test_signature();
+        let psbt = finalize_tx(
+            psbt_spending(ScriptBuf::new()),
+            0,
+            &public_key,
+            AddressType::P2pkh,
+            signature,
+        )
+        .unwrap();
+
+        assert!(!is_fully_signed(&psbt));
+        assert_eq!(
+            psbt.inputs[0].partial_sigs.get(&public_key),
+            Some(&signature)
+        );
+    }
+
+    #[test]
+    fn extract_signed_tx_reports_missing_signatures() {
+        let (signature, public_key) = test_signature();
+        let address = bitcoin::Address::p2pkh(&public_key, bitcoin::Network::Signet);
+        let mut psbt = psbt_spending(address.script_pubkey());
+        psbt.unsigned_tx
+            .input
+            .push(psbt.unsigned_tx.input[0].clone());
+        psbt.inputs.push(psbt.inputs[0].clone());
+        let psbt = finalize_tx(psbt, 0, &public_key, AddressType::P2pkh, signature).unwrap();
+
+        let err = extract_signed_tx(psbt.clone()).unwrap_err();
+        match err.downcast_ref::<BoomerError>() {
+            Some(BoomerError::UnsignedInputs { inputs }) => assert_eq!(inputs, &vec![1]),
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let psbt = finalize_tx(psbt, 1, &public_key, AddressType::P2pkh, signature).unwrap();
+        let tx = extract_signed_tx(psbt).unwrap();
+        assert!(tx.input.iter().all(|input| !input.script_sig.is_empty()));
+    }
+
+    #[test]
+    fn recovery_falls_back_to_the_other_recid() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let group_key = secret_key.public_key(&secp).serialize_uncompressed();
+        let digest = [2; 32];
+        let (recid, compact) = secp
+            .sign_ecdsa_recoverable(
+                &bitcoin::secp256k1::Message::from_slice(&digest).unwrap(),
+                &secret_key,
+            )
+            .serialize_compact();
+        let wrong_recid = recid.to_i32() as u8 ^ 1;
+
+        let secp = Secp256k1::new();
+        let msg = Message::from_slice(&digest).unwrap();
+        let (_, public_key) =
+            recover_public_key(&secp, &compact, wrong_recid, &msg, Some(&group_key)).unwrap();
+        assert_eq!(&public_key.serialize_vec(&secp, false)[..], &group_key[..]);
+
+        let other_key = [4; 65];
+        match recover_public_key(&secp, &compact, wrong_recid, &msg, Some(&other_key)) {
+            Err(BoomerError::RecoveryFailed { recid, .. }) => assert_eq!(recid, wrong_recid),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn partial(sender: u16) -> Result<Msg<u16>> {
+        Ok(Msg {
+            sender,
+            receiver: None,
+            body: sender,
+        })
+    }
+
+    #[tokio::test]
+    async fn collect_partials_reports_signer_dropping_after_offline_stage() {
+        // Party 3 completed the offline stage but never sends its partial.
+        let incoming = futures::stream::iter(vec![partial(2)]).chain(futures::stream::pending());
+        let err = collect_partials(incoming, 2, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<BoomerError>() {
+            Some(BoomerError::OnlineStageIncomplete {
+                received, expected, ..
+            }) => assert_eq!((*received, *expected), (1, 2)),
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let incoming = futures::stream::iter(vec![partial(2), partial(3)]);
+        assert_eq!(
+            collect_partials(incoming, 2, Duration::from_millis(50))
+                .await
+                .unwrap(),
+            vec![2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn do_sign_tells_which_stage_failed() {
+        let args = SigningConfig {
+            address: surf::Url::parse("http://127.0.0.1:8000/").unwrap(),
+            relay_options: RelayOptions::default(),
+            room: "test".into(),
+            local_share: PathBuf::from("/nonexistent/local-share1.json"),
+            parties: vec![1, 2],
+            data_to_sign: "not a psbt".into(),
+            transaction: true,
+            idx: 1,
+            address_type: AddressType::P2pkh,
+            network: bitcoin::Network::Signet,
+            hash_scheme: HashScheme::default(),
+            sighash_type: EcdsaSighashType::All,
+            progress: ProgressHandle::default(),
+            save_offline_stage: None,
+            resume_offline_stage: None,
+            inputs: InputSelection::default(),
+            passphrase: None,
+            script_multisig: false,
+            timeouts: PhaseTimeouts::default(),
+            dry_run: false,
+            rbf: false,
+            bip322: false,
+        };
+        let err = do_sign(args.clone()).await.unwrap_err();
+        assert!(matches!(err, SigningError::PsbtParseError(_)), "{:?}", err);
+
+        let err = do_sign(SigningConfig {
+            data_to_sign: "abc".into(),
+            transaction: false,
+            ..args.clone()
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(err, SigningError::ShareReadError(_)), "{:?}", err);
+
+        let err = do_sign(SigningConfig {
+            parties: vec![2, 3],
+            ..args
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(err, SigningError::JoinError(_)), "{:?}", err);
+        assert_eq!(err.category(), crate::bs_error::ErrorCategory::Config);
+    }
+
+    #[tokio::test]
+    async fn two_parties_generate_a_key_and_sign_through_the_relay() {
+        use crate::bs_keygen::{do_keygen, KeygenConfig};
+        use crate::bs_mock_relay::MockRelay;
+
+        let relay = MockRelay::start();
+        let share = |index: u16| {
+            std::env::temp_dir().join(format!(
+                "boomersig-relay-{}-share{}.json",
+                std::process::id(),
+                index
+            ))
+        };
+        let keygen = |index: u16| KeygenConfig {
+            address: relay.url.clone(),
+            relay_options: RelayOptions::default(),
+            room: "keygen".into(),
+            output: share(index),
+            index,
+            threshold: 1,
+            number_of_parties: 2,
+            test_signature: false,
+            progress: ProgressHandle::default(),
+            network: bitcoin::Network::Signet,
+            passphrase: None,
+            script_multisig: false,
+            timeouts: PhaseTimeouts::default(),
+            events: None,
+        };
+        // Party 1's events are never drained, which must not stall its keygen.
+        let (events, _undrained) = tokio::sync::mpsc::channel(1);
+        let (first, second) = tokio::join!(
+            do_keygen(KeygenConfig {
+                events: Some(events),
+                ..keygen(1)
+            }),
+            do_keygen(keygen(2))
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert_eq!(first.pubkey, second.pubkey);
+        let group_key = crate::bs_address::parse_pubkey(&first.pubkey).unwrap();
+
+        let psbt = psbt_spending(ScriptBuf::new_p2pkh(&group_key.pubkey_hash())).to_string();
+        let sign = |idx: u16| SigningConfig {
+            address: relay.url.clone(),
+            relay_options: RelayOptions::default(),
+            room: signing_room(&psbt, &[1, 2], 0, false),
+            local_share: share(idx),
+            parties: vec![1, 2],
+            data_to_sign: psbt.clone(),
+            transaction: true,
+            idx,
+            address_type: AddressType::P2pkh,
+            network: bitcoin::Network::Signet,
+            hash_scheme: HashScheme::default(),
+            sighash_type: EcdsaSighashType::All,
+            progress: ProgressHandle::default(),
+            save_offline_stage: None,
+            resume_offline_stage: None,
+            inputs: InputSelection::default(),
+            passphrase: None,
+            script_multisig: false,
+            timeouts: PhaseTimeouts::default(),
+            dry_run: false,
+            rbf: false,
+            bip322: false,
+        };
+        let (first, second) = tokio::join!(do_sign(sign(1)), do_sign(sign(2)));
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        let signed = first.signined_tx.unwrap();
+        assert_eq!(second.signined_tx.as_ref(), Some(&signed));
+        let tx: Transaction = deserialize(&hex::decode(signed).unwrap()).unwrap();
+        let input = crate::bs_verify::verify_tx_input(&tx, 0, &group_key, None).unwrap();
+        assert!(input.valid);
+
+        // do_sign checks the proof's script before handing it out.
+        let prove = |idx: u16| SigningConfig {
+            room: signing_room("Hello World", &[1, 2], 0, false),
+            data_to_sign: "Hello World".into(),
+            transaction: false,
+            address_type: AddressType::P2wpkh,
+            bip322: true,
+            ..sign(idx)
+        };
+        let (first, second) = tokio::join!(do_sign(prove(1)), do_sign(prove(2)));
+        let (first, second) = (first.unwrap(), second.unwrap());
+        for index in 1..=2 {
+            let _ = std::fs::remove_file(share(index));
+        }
+        assert!(first.bip322_signature.is_some());
+        assert_eq!(first.bip322_signature, second.bip322_signature);
+    }
+}
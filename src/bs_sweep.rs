@@ -0,0 +1,181 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use bitcoin::absolute::LockTime;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+
+use crate::bs_explorer::Utxo;
+use crate::bs_fee::fee_at;
+
+/// Outputs below this are non-standard and won't be relayed.
+pub(crate) const DUST_LIMIT: u64 = 546;
+
+// Version, locktime and the input and output counts.
+pub(crate) const TX_OVERHEAD_VSIZE: u64 = 10;
+// Outpoint, sequence and a script_sig pushing a DER signature and the key.
+const P2PKH_INPUT_VSIZE: u64 = 148;
+const P2PKH_UNCOMPRESSED_INPUT_VSIZE: u64 = 180;
+
+/// The vsize of a signed input spending the p2pkh output of the group key.
+pub(crate) fn p2pkh_input_vsize(compressed: bool) -> u64 {
+    match compressed {
+        true => P2PKH_INPUT_VSIZE,
+        false => P2PKH_UNCOMPRESSED_INPUT_VSIZE,
+    }
+}
+
+/// The vsize of an output paying `script_pubkey`: its amount, the script's
+/// length and the script.
+pub(crate) fn output_vsize(script_pubkey: &ScriptBuf) -> u64 {
+    9 + script_pubkey.len() as u64
+}
+
+/// The unsigned input spending `utxo`, replaceable so a stuck transaction can
+/// be bumped.
+pub(crate) fn spending_input(utxo: &Utxo) -> TxIn {
+    TxIn {
+        previous_output: OutPoint {
+            txid: utxo.txid,
+            vout: utxo.vout,
+        },
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Witness::new(),
+    }
+}
+
+/// Parses the destination, refusing addresses for another network.
+pub fn parse_destination(destination: &str, network: Network) -> Result<Address> {
+    let destination = destination.trim();
+    Address::from_str(destination)
+        .context(format!("invalid destination address {:?}", destination))?
+        .require_network(network)
+        .context(format!(
+            "destination {} is not a {} address",
+            destination, network
+        ))
+}
+
+/// A transaction spending every UTXO of the multisig to a single output.
+#[derive(Debug)]
+pub struct Sweep {
+    pub psbt: PartiallySignedTransaction,
+    pub balance: u64,
+    pub fee: u64,
+}
+
+impl Sweep {
+    /// What the destination receives, the balance minus the fee.
+    pub fn amount(&self) -> u64 {
+        self.balance - self.fee
+    }
+}
+
+/// Builds the sweep of `utxos`, all locked to the p2pkh `script_pubkey` of
+/// the group key, paying `fee_rate` sat/vB out of the swept amount.
+pub fn build_sweep(
+    utxos: &[Utxo],
+    script_pubkey: &ScriptBuf,
+    compressed: bool,
+    destination: &Address,
+    fee_rate: u64,
+) -> Result<Sweep> {
+    if utxos.is_empty() {
+        bail!("the multisig address has no funds to sweep");
+    }
+
+    let input = utxos.iter().map(spending_input).collect();
+
+    let balance: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let output_script = destination.script_pubkey();
+    let vsize = TX_OVERHEAD_VSIZE
+        + p2pkh_input_vsize(compressed) * utxos.len() as u64
+        + output_vsize(&output_script);
+    let fee = fee_at(vsize, fee_rate)?;
+    if balance < fee.saturating_add(DUST_LIMIT) {
+        bail!(
+            "balance of {} sat does not cover a fee of {} sat plus the dust limit",
+            balance,
+            fee
+        );
+    }
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input,
+        output: vec![TxOut {
+            value: balance - fee,
+            script_pubkey: output_script,
+        }],
+    };
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)?;
+    for (psbt_input, utxo) in psbt.inputs.iter_mut().zip(utxos) {
+        psbt_input.witness_utxo = Some(TxOut {
+            value: utxo.value,
+            script_pubkey: script_pubkey.clone(),
+        });
+    }
+
+    Ok(Sweep { psbt, balance, fee })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_error::BoomerError;
+    use crate::bs_fee::compute_fee;
+    use bitcoin::Txid;
+    use std::collections::BTreeMap;
+
+    fn utxo(value: u64) -> Utxo {
+        Utxo {
+            txid: Txid::from_str(&"4d3c2b1a".repeat(8)).unwrap(),
+            vout: 0,
+            value,
+            status: Default::default(),
+        }
+    }
+
+    #[test]
+    fn sweep_subtracts_the_fee_from_the_output() {
+        let destination =
+            parse_destination("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", Network::Signet).unwrap();
+        let sweep = build_sweep(
+            &[utxo(40_000), utxo(60_000)],
+            &destination.script_pubkey(),
+            true,
+            &destination,
+            2,
+        )
+        .unwrap();
+
+        // 10 + 2 * 148 + 9 + 25 vbytes at 2 sat/vB.
+        assert_eq!(sweep.fee, 680);
+        assert_eq!(sweep.amount(), 99_320);
+        assert_eq!(sweep.psbt.unsigned_tx.output[0].value, sweep.amount());
+        assert_eq!(
+            compute_fee(&sweep.psbt, &BTreeMap::new()).unwrap().fee,
+            sweep.fee
+        );
+    }
+
+    #[test]
+    fn sweep_refuses_dust_and_foreign_networks() {
+        let destination =
+            parse_destination("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", Network::Signet).unwrap();
+        let script = destination.script_pubkey();
+        assert!(build_sweep(&[utxo(1_000)], &script, true, &destination, 5).is_err());
+        assert!(build_sweep(&[], &script, true, &destination, 1).is_err());
+        let err = build_sweep(&[utxo(1_000)], &script, true, &destination, u64::MAX).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BoomerError>(),
+            Some(BoomerError::FeeRateTooHigh { .. })
+        ));
+
+        let err =
+            parse_destination("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", Network::Signet).unwrap_err();
+        assert!(err.to_string().contains("is not a signet address"));
+    }
+}
@@ -1,35 +1,212 @@
-use bs_keygen::{do_keygen, KeygenConfig};
-use bs_signing::{do_sign, SigningConfig};
+use anyhow::Context;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::sighash::EcdsaSighashType;
+use bs_address::{is_legacy_only, AddressType, LEGACY_ONLY_WARNING};
+use bs_broadcast::broadcast_raw_transaction;
+use bs_client::{attempt_room, RelayOptions};
+use bs_clipboard::{copy, Copied};
+use bs_config::{config_path, load_config, save_config, BroadcastBackend, Config};
+use bs_encryption::env_passphrase;
+use bs_error::{describe_chain, BoomerError, SigningError};
+use bs_explorer::{fetch_utxos, wait_for_confirmation, Balance, TxProgress, Utxo};
+use bs_export::Export;
+use bs_fee::{compute_fee, parse_input_values, FeeInfo, HIGH_FEE_PERCENT};
+use bs_history::{
+    append_record, format_timestamp, history_path, load_history, raw_txid, SigningRecord,
+};
+use bs_hook::{run_post_sign, HOOK_TIMEOUT};
+use bs_keygen::{back_up_share, do_keygen, preflight, KeygenConfig, KeygenProgress, KeygenResult};
+use bs_network::check_psbt_network;
+use bs_progress::{lobby_mark, Activity, Progress, ProgressHandle};
+use bs_qr::{qr_lines, qr_parts, PsbtAssembler, FRAME_INTERVAL};
+use bs_rbf::signal_rbf;
+use bs_send::{build_spend, Spend};
+use bs_share::{check_share_index, data_dir, ensure_data_dir, inspect_share, ShareInfo};
+use bs_signing::{
+    check_parties, check_signer_count, compact_psbt, decode_outputs, do_sign, message_digest,
+    multisig_inputs, parse_parties, parse_psbt, signable_inputs, signing_room, DecodedOutput,
+    HashScheme, InputSelection, SigningConfig, SigningResult,
+};
+use bs_sweep::{build_sweep, parse_destination, Sweep};
 use crossterm::event::{self, Event};
+mod bs_address;
+mod bs_bip322;
+mod bs_broadcast;
+mod bs_cli;
 mod bs_client;
+mod bs_clipboard;
+mod bs_config;
+mod bs_encryption;
+mod bs_error;
+mod bs_explorer;
+mod bs_export;
+mod bs_fee;
+mod bs_history;
+mod bs_hook;
 mod bs_keygen;
+mod bs_log;
+#[cfg(test)]
+mod bs_mock_relay;
+mod bs_network;
+mod bs_progress;
+mod bs_qr;
+mod bs_rbf;
+mod bs_send;
+mod bs_share;
 mod bs_signing;
+mod bs_sweep;
+mod bs_verify;
+use futures::channel::oneshot;
 use futures::executor::block_on;
+use futures::{Future, TryFutureExt};
 use ratatui::{
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     prelude::Widget,
     style::{Color, Style, Stylize},
     symbols::border,
     text::{Line, Span, Text},
-    widgets::{Block, Borders, Paragraph},
+    widgets::{
+        Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, Wrap,
+    },
     Frame,
 };
-use sha2::Digest;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
 use std::sync::Arc;
 use std::thread;
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     fs, io,
-    time::{Duration, Instant},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::time::timeout;
-use tui_textarea::TextArea;
+use structopt::StructOpt;
+use tokio::time::{error::Elapsed, timeout};
+use tui_textarea::{CursorMove, TextArea};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum AppMode {
     Menu,
     Create,
     Sign,
     GetAddress,
+    Send,
+    Sweep,
+    History,
+    Settings,
+    Result,
+    SharePicker,
+    // Decoded PSBT, confirmed before the signing ceremony starts.
+    Review,
+    Qr,
+    // Scanned QR parts of a PSBT, pasted one by one.
+    QrImport,
+}
+
+/// What a completed ceremony produced, shown on the Result screen.
+#[derive(Debug, Default)]
+struct ResultView {
+    title: String,
+    fields: Vec<(&'static str, String)>,
+    // The field to show as a QR code, the one to move on from here.
+    qr: Option<(&'static str, String)>,
+    // What `S` saves to a file.
+    export: Option<Export>,
+}
+
+impl ResultView {
+    fn keygen(result: &KeygenResult) -> Self {
+        Self {
+            title: match result.tested {
+                true => "Keygen complete, test signature succeeded".into(),
+                false => "Keygen complete".into(),
+            },
+            fields: vec![
+                ("Address", result.address.clone()),
+                ("Public key", result.pubkey.clone()),
+                (
+                    "Descriptor",
+                    result
+                        .descriptor(AddressType::default())
+                        .unwrap_or_else(|e| format!("{:#}", e)),
+                ),
+                ("Share", result.out_dir.display().to_string()),
+                (
+                    "Threshold",
+                    format!(
+                        "{} of {} parties sign",
+                        result.threshold + 1,
+                        result.parties
+                    ),
+                ),
+                ("Party public keys", result.party_pubkeys.join(" ")),
+            ]
+            .into_iter()
+            .chain(
+                result
+                    .multisig_address
+                    .clone()
+                    .map(|address| ("Multisig address", address)),
+            )
+            .chain(
+                result
+                    .multisig_descriptor
+                    .clone()
+                    .map(|descriptor| ("Multisig descriptor", descriptor)),
+            )
+            .collect(),
+            qr: Some(("Address", result.address.clone())),
+            export: None,
+        }
+    }
+
+    /// `statuses` are the outcomes of what ran after the signing, such as the
+    /// broadcast, under their labels.
+    fn signing(
+        title: &str,
+        result: &SigningResult,
+        statuses: &[(&'static str, &Option<String>)],
+    ) -> Self {
+        let mut fields = vec![
+            ("Address", result.address.clone()),
+            ("Public key", result.pubkey.clone()),
+            ("Share", result.out_dir.display().to_string()),
+            ("Signers", format!("{:?}", result.parties)),
+        ];
+        if !result.signed_inputs.is_empty() {
+            fields.push(("Signed inputs", format!("{:?}", result.signed_inputs)));
+        }
+        if let Some(tx) = &result.signined_tx {
+            fields.push(("Signed transaction", tx.clone()));
+        }
+        if let Some(txid) = &result.broadcast_txid {
+            fields.push(("Broadcast txid", txid.clone()));
+        }
+        if let Some(psbt) = &result.partially_signed_psbt {
+            fields.push(("PSBT for the next signer", psbt.clone()));
+        }
+        for (label, status) in statuses {
+            if let Some(status) = status {
+                fields.push((label, status.clone()));
+            }
+        }
+        let qr = match (&result.signined_tx, &result.partially_signed_psbt) {
+            (Some(tx), _) => ("Signed transaction", tx.clone()),
+            (None, Some(psbt)) => ("PSBT for the next signer", psbt.clone()),
+            (None, None) => ("Address", result.address.clone()),
+        };
+        Self {
+            title: title.into(),
+            fields,
+            qr: Some(qr),
+            export: Export::of(result),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MenuState {
+    selected: usize,
 }
 
 #[derive(Debug, Default)]
@@ -37,53 +214,475 @@ struct CreateState {
     threshold: u8,
     number_of_parties: u8,
     participant_index: u8,
+    test_signature: bool,
     selected_field: usize,
     cursor_visible: bool,
+    status: Option<String>,
 }
 
 #[derive(Debug, Default)]
 struct SignState {
     participant_index: u8,
+    attempt: u8,
     psbt: TextArea<'static>,
+    // Why the PSBT field can't be signed, shown next to it.
+    psbt_error: Option<String>,
+    data_to_sign: String,
     selected_field: usize,
+    cursor_visible: bool,
+    broadcast_status: Option<String>,
+    hook_status: Option<String>,
+    // The broadcast and post-sign command running after a signing, followed
+    // by `poll_broadcast`.
+    broadcast_rx: Option<Receiver<BroadcastEvent>>,
+    broadcast_retries: u32,
+    // Where the broadcast transaction stands, updated by `poll_confirmation`.
+    confirmation_status: Option<String>,
+    confirmation_rx: Option<Receiver<Result<TxProgress, String>>>,
+    share_info: Option<ShareInfo>,
+    signable_inputs: Option<Vec<usize>>,
+    // None signs every signable input.
+    input_choice: Option<usize>,
+    dry_run: bool,
+    parties: TextArea<'static>,
+    input_values: TextArea<'static>,
+    fee: Option<Result<FeeInfo, String>>,
+    // Picked with F2, replaces the participant index's share.
+    share_file: Option<PathBuf>,
+    review: Option<Review>,
+}
+
+/// What the broadcast thread reports, in order: each retry, the outcome of
+/// the broadcast, then the post-sign command's if one is set.
+#[derive(Debug)]
+enum BroadcastEvent {
+    Retry(u32),
+    Broadcast(Result<bitcoin::Txid, String>),
+    Hook(String),
+}
+
+#[derive(Debug, Default)]
+struct QrImportState {
+    part: TextArea<'static>,
+    assembler: PsbtAssembler,
+    error: Option<String>,
+}
+
+/// Data shown as a QR code, cycling through its parts when it takes several.
+#[derive(Debug)]
+struct QrView {
+    title: String,
+    parts: Vec<String>,
+    shown: Instant,
+    back: AppMode,
+}
+
+/// What the PSBT about to be signed does.
+#[derive(Debug)]
+struct Review {
+    txid: String,
+    outputs: Vec<DecodedOutput>,
+    fee: Result<FeeInfo, String>,
+    // The inputs were made to signal replace-by-fee, which changed the txid.
+    rbf_signaled: bool,
 }
 
 #[derive(Debug, Default)]
 struct GetAddressState {
     participant_index: u8,
+    attempt: u8,
+    digest: TextArea<'static>,
+    parties: TextArea<'static>,
+    share_info: Option<ShareInfo>,
     selected_field: usize,
+    cursor_visible: bool,
     address: Option<String>,
+    utxos: Option<Result<Vec<Utxo>, String>>,
+    // Set while a balance lookup runs in the background.
+    utxos_rx: Option<Receiver<Result<Vec<Utxo>, String>>>,
+    share_file: Option<PathBuf>,
+}
+
+type Timed<T> = Result<anyhow::Result<T>, Elapsed>;
+
+/// The outcome a ceremony thread sends back.
+enum Finished {
+    Keygen(Timed<KeygenResult>),
+    Sign {
+        outcome: Timed<SigningResult>,
+        // Timed out attempts that already moved on to the next room.
+        retried: u8,
+        signer: u16,
+        parties: Vec<u16>,
+    },
+    Address(Timed<SigningResult>),
+}
+
+/// A ceremony running on a background thread.
+#[derive(Debug)]
+struct Ceremony {
+    title: &'static str,
+    started: Instant,
+    timeout: Duration,
+    progress: ProgressHandle,
+    rx: Receiver<Finished>,
+    cancel: oneshot::Sender<()>,
+    // Only keygen reports its messages, counted as they arrive.
+    events: Option<tokio::sync::mpsc::Receiver<KeygenProgress>>,
+    messages: usize,
+}
+
+/// Shares inspected so far, by path and passphrase, so an encrypted share's
+/// Argon2id runs once instead of on every keystroke. An entry holds while the
+/// file keeps its modification time.
+#[derive(Debug, Default)]
+struct ShareCache {
+    entries: RefCell<HashMap<(PathBuf, Option<String>), (SystemTime, Result<ShareInfo, String>)>>,
+}
+
+impl ShareCache {
+    fn inspect(&self, path: &Path, passphrase: Option<&str>) -> anyhow::Result<ShareInfo> {
+        let modified = match fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            // Nothing to unlock, `inspect_share` tells why.
+            Err(_) => return inspect_share(path, passphrase),
+        };
+        let key = (path.to_owned(), passphrase.map(str::to_owned));
+        if let Some((seen, inspected)) = self.entries.borrow().get(&key) {
+            if *seen == modified {
+                return inspected.clone().map_err(anyhow::Error::msg);
+            }
+        }
+        let inspected = inspect_share(path, passphrase);
+        let cached = match &inspected {
+            Ok(info) => Ok(info.clone()),
+            Err(e) => Err(format!("{:#}", e)),
+        };
+        self.entries.borrow_mut().insert(key, (modified, cached));
+        inspected
+    }
+}
+
+#[derive(Debug)]
+struct SharePickerState {
+    dir: PathBuf,
+    // Labels and paths, the parent directory and subdirectories first.
+    entries: Vec<(String, PathBuf)>,
+    list_state: ListState,
+    // The screen the picked share is for.
+    target: AppMode,
+    error: Option<String>,
+}
+
+impl Default for SharePickerState {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::new(),
+            entries: Vec::new(),
+            list_state: ListState::default(),
+            target: AppMode::Sign,
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct HistoryState {
+    records: Vec<SigningRecord>,
+    list_state: ListState,
+    error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct SweepState {
+    participant_index: u8,
+    destination: TextArea<'static>,
+    fee_rate: TextArea<'static>,
+    // 0 participant, 1 destination, 2 fee rate, 3 Build.
+    selected_field: usize,
+    sweep: Option<Sweep>,
+    // The sweep whose UTXOs are being looked up, `poll_sweep` asks to
+    // confirm it once built.
+    sweep_rx: Option<Receiver<Result<Prepared<Sweep>, String>>>,
+}
+
+#[derive(Debug, Default)]
+struct SendState {
+    participant_index: u8,
+    destination: TextArea<'static>,
+    amount: TextArea<'static>,
+    fee_rate: TextArea<'static>,
+    // 0 participant, 1 destination, 2 amount, 3 fee rate, 4 Build.
+    selected_field: usize,
+    spend: Option<Spend>,
+    // The spend whose UTXOs are being looked up, `poll_spend` asks to
+    // confirm it once built.
+    spend_rx: Option<Receiver<Result<Prepared<Spend>, String>>>,
+}
+
+// A spend or sweep ready to confirm, with the funding address, destination
+// and fee rate it was built from.
+type Prepared<T> = (bitcoin::Address, bitcoin::Address, u64, T);
+
+#[derive(Debug, Default)]
+struct SettingsState {
+    relay: TextArea<'static>,
+    recent_relays: ListState,
+    passphrase: TextArea<'static>,
+    // 0 is the relay field, 1 the recently used list, 2 the passphrase.
+    selected_field: usize,
+    status: Option<Result<String, String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PromptKind {
+    RetryAbort,
+    Error,
+    Confirm,
+    Info,
+}
+
+#[derive(Debug)]
+struct Prompt {
+    kind: PromptKind,
+    title: String,
+    message: String,
+}
+
+impl Prompt {
+    /// `progress` tells whether the ceremony stalled on the network or on
+    /// local computation.
+    fn timed_out(timeout: Duration, progress: &Progress) -> Self {
+        Self {
+            kind: PromptKind::RetryAbort,
+            title: "Ceremony timed out".into(),
+            message: format!(
+                "Not all parties answered within {}s.\nLast activity: {}\nRetry in a fresh room or abort?",
+                timeout.as_secs(),
+                progress
+            ),
+        }
+    }
+
+    fn failed(title: &str, error: &anyhow::Error, progress: &Progress) -> Self {
+        let hint = match error.downcast_ref::<SigningError>() {
+            Some(e) => format!("\n\n{}", e.hint()),
+            None => String::new(),
+        };
+        Self::error(
+            title,
+            format!(
+                "{}{}\n\nLast activity: {}",
+                describe_chain(error),
+                hint,
+                progress
+            ),
+        )
+    }
+
+    /// Shows exactly what is about to be signed, so users never sign an
+    /// opaque challenge blindly.
+    fn confirm_message(data: &str, hash_scheme: HashScheme, warning: Option<&str>) -> Self {
+        let digest = match message_digest(data, hash_scheme) {
+            Ok(digest) => hex::encode(digest),
+            Err(e) => format!("cannot hash message: {}", e),
+        };
+        let mut message = match hash_scheme {
+            HashScheme::RawDigest => {
+                format!("You are about to sign this digest as-is:\n\n{}", digest)
+            }
+            _ => format!(
+                "You are about to sign:\n\nText:    {}\nHex:     {}\nDigest:  {}",
+                data,
+                hex::encode(data.as_bytes()),
+                digest
+            ),
+        };
+        if let Some(warning) = warning {
+            message.push_str(&format!("\n\n{}", warning));
+        }
+        Self {
+            kind: PromptKind::Confirm,
+            title: "Confirm message".into(),
+            message,
+        }
+    }
+
+    fn share_exists(path: &Path) -> Self {
+        Self {
+            kind: PromptKind::Confirm,
+            title: "Share exists".into(),
+            message: format!(
+                "{} already holds a share, keygen never overwrites one.\n\nBack it up next to it as {}.bak-<time> and run keygen?",
+                path.display(),
+                path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default()
+            ),
+        }
+    }
+
+    fn info(title: &str, message: String) -> Self {
+        Self {
+            kind: PromptKind::Info,
+            title: title.into(),
+            message,
+        }
+    }
+
+    fn error(title: &str, message: String) -> Self {
+        Self {
+            kind: PromptKind::Error,
+            title: title.into(),
+            message,
+        }
+    }
 }
 
+const MENU_ITEMS: [&str; 7] = [
+    "Create Multisig",
+    "Sign Multisig",
+    "Get Address",
+    "Send Funds",
+    "Sweep Funds",
+    "Signing History",
+    "Settings",
+];
+
+// The digest Get Address signs unless the user enters another one, the group
+// key is recovered from the signature.
+const ADDRESS_CHALLENGE: &str = "fdd4d9893b23aa6cdb357e1606907c6909a1231595549e698f779a141d4534c7";
+
+// Signers used when the share can't be inspected.
+const FALLBACK_SIGNERS: [u16; 2] = [1, 2];
+
+// Shown instead of a broadcast txid, so a rehearsal is never mistaken for a
+// payment.
+const DRY_RUN_STATUS: &str = "NOT BROADCAST — dry run";
+
+// The Result screen field `poll_confirmation` keeps up to date.
+const CONFIRMATION_FIELD: &str = "Confirmation";
+
+// The Result screen fields `poll_broadcast` keeps up to date.
+const BROADCAST_FIELD: &str = "Broadcast";
+const HOOK_FIELD: &str = "Post-sign command";
+
+const NOTICE_DURATION: Duration = Duration::from_secs(2);
+
+// Makes the UI panic as soon as it is up, to check the terminal is restored.
+const TEST_PANIC_ENV: &str = "BOOMERSIG_TEST_PANIC";
+
+// Confirmations after which a broadcast transaction is no longer watched.
+const WATCHED_CONFIRMATIONS: u32 = 6;
+
+// Keygen events the UI hasn't drawn yet, more are dropped until it catches up.
+const KEYGEN_EVENTS: usize = 256;
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+// Below this the Sign screen can't fit its fields, show a notice instead.
+const MIN_WIDTH: u16 = 60;
+const MIN_HEIGHT: u16 = 20;
+
 #[derive(Debug)]
 pub struct App {
     mode: AppMode,
+    menu_state: MenuState,
+    menu_list_state: ListState,
     create_state: CreateState,
     sign_state: SignState,
     get_address_state: GetAddressState,
+    send_state: SendState,
+    sweep_state: SweepState,
+    history_state: HistoryState,
+    settings_state: SettingsState,
+    share_picker_state: SharePickerState,
+    config: Config,
+    prompt: Option<Prompt>,
+    /// The outcome of the latest ceremony, kept in memory for the results
+    /// pane instead of being written to the working directory.
+    last_result: Option<Result<SigningResult, String>>,
+    result_view: ResultView,
+    qr: Option<QrView>,
+    qr_import_state: QrImportState,
+    /// Keys are ignored while it runs, its progress is drawn over the screen.
+    ceremony: Option<Ceremony>,
+    /// Encrypts new shares and decrypts existing ones. Only ever kept in
+    /// memory, it is entered in Settings or taken from the environment.
+    share_passphrase: Option<String>,
+    shares: ShareCache,
     exit: bool,
     last_blink: Instant,
+    /// Confirms a copy for `NOTICE_DURATION`, in place of the key hints.
+    notice: Option<(String, Instant)>,
+    /// Where to save the Result screen's export, while it is being entered.
+    export_path: Option<TextArea<'static>>,
 }
 
 impl Default for App {
     fn default() -> Self {
         Self {
             mode: AppMode::Menu,
+            menu_state: MenuState::default(),
+            menu_list_state: ListState::default(),
             create_state: CreateState::default(),
             sign_state: SignState::default(),
-            get_address_state: GetAddressState::default(),
+            get_address_state: GetAddressState {
+                digest: TextArea::new(vec![ADDRESS_CHALLENGE.into()]),
+                ..GetAddressState::default()
+            },
+            send_state: SendState {
+                fee_rate: TextArea::new(vec!["2".into()]),
+                ..SendState::default()
+            },
+            sweep_state: SweepState {
+                fee_rate: TextArea::new(vec!["2".into()]),
+                ..SweepState::default()
+            },
+            history_state: HistoryState::default(),
+            settings_state: SettingsState::default(),
+            share_picker_state: SharePickerState::default(),
+            config: Config::default(),
+            prompt: None,
+            last_result: None,
+            result_view: ResultView::default(),
+            qr: None,
+            qr_import_state: QrImportState::default(),
+            ceremony: None,
+            share_passphrase: None,
+            shares: ShareCache::default(),
             exit: false,
             last_blink: Instant::now(),
+            notice: None,
+            export_path: None,
         }
     }
 }
 
 impl App {
+    /// Starts where the last session left off: its relay, network, share
+    /// directory and participant index.
+    fn from_config(config: Config) -> Self {
+        let mut app = Self::default();
+        app.create_state.participant_index = config.participant_index;
+        app.sign_state.participant_index = config.participant_index;
+        app.get_address_state.participant_index = config.participant_index;
+        app.send_state.participant_index = config.participant_index;
+        app.sweep_state.participant_index = config.participant_index;
+        app.config = config;
+        app.share_passphrase = env_passphrase();
+        app
+    }
+
     pub fn run(
         &mut self,
         terminal: &mut ratatui::Terminal<impl ratatui::backend::Backend>,
     ) -> io::Result<()> {
         while !self.exit {
+            self.poll_utxos();
+            self.poll_ceremony();
+            self.poll_spend();
+            self.poll_sweep();
+            self.poll_broadcast();
+            self.poll_confirmation();
             terminal.draw(|frame| self.draw(frame))?;
 
             if self.last_blink.elapsed() > Duration::from_millis(500) {
@@ -91,14 +690,28 @@ impl App {
                     AppMode::Create => {
                         self.create_state.cursor_visible = !self.create_state.cursor_visible
                     }
+                    AppMode::Sign => {
+                        self.sign_state.cursor_visible = !self.sign_state.cursor_visible
+                    }
+                    AppMode::GetAddress => {
+                        self.get_address_state.cursor_visible =
+                            !self.get_address_state.cursor_visible
+                    }
                     _ => {}
                 }
                 self.last_blink = Instant::now();
             }
 
             if event::poll(Duration::from_millis(50))? {
-                if let Event::Key(key_event) = event::read()? {
-                    self.handle_key_event(key_event);
+                match event::read()? {
+                    Event::Key(key_event) => self.handle_key_event(key_event),
+                    // Redraw from scratch right away, instead of on the next
+                    // tick, so stale cells don't linger while resizing.
+                    Event::Resize(_, _) => {
+                        terminal.autoresize()?;
+                        terminal.clear()?;
+                    }
+                    _ => {}
                 }
             }
         }
@@ -107,14 +720,157 @@ impl App {
 
     fn draw(&mut self, frame: &mut Frame) {
         frame.render_widget(Paragraph::new(""), frame.area());
+        let area = frame.area();
+        if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+            frame.render_widget(
+                Paragraph::new(format!(
+                    "Terminal too small ({}x{}), resize to at least {}x{}",
+                    area.width, area.height, MIN_WIDTH, MIN_HEIGHT
+                ))
+                .wrap(Wrap { trim: true })
+                .centered(),
+                area,
+            );
+            return;
+        }
         match self.mode {
             AppMode::Menu => self.render_menu(frame),
             AppMode::Create => self.render_create(frame),
             AppMode::Sign => self.render_sign(frame),
             AppMode::GetAddress => self.render_get_address(frame),
+            AppMode::Send => self.render_send(frame),
+            AppMode::Sweep => self.render_sweep(frame),
+            AppMode::History => self.render_history(frame),
+            AppMode::Settings => self.render_settings(frame),
+            AppMode::Result => self.render_result(frame),
+            AppMode::SharePicker => self.render_share_picker(frame),
+            AppMode::Review => self.render_review(frame),
+            AppMode::Qr => self.render_qr(frame),
+            AppMode::QrImport => self.render_qr_import(frame),
+        }
+        self.render_ceremony(frame);
+        if self.prompt.is_some() {
+            self.render_prompt(frame);
         }
     }
 
+    fn render_ceremony(&self, frame: &mut Frame) {
+        let ceremony = match &self.ceremony {
+            Some(ceremony) => ceremony,
+            None => return,
+        };
+        let area = frame.area();
+        let width = area.width.min(70);
+        let height = area.height.min(7);
+        let popup = Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        };
+
+        let elapsed = ceremony.started.elapsed();
+        let spinner = SPINNER[(elapsed.as_millis() / 100) as usize % SPINNER.len()];
+        let progress = ceremony.progress.get();
+        let mut keys = vec![" Cancel ".into(), "Esc".blue().bold()];
+        let status = match &progress.activity {
+            Activity::Lobby {
+                present,
+                expected,
+                parties,
+            } => {
+                keys.extend([" Start now ".into(), "Enter".blue().bold()]);
+                lobby_line(spinner, present, expected, *parties)
+            }
+            _ => Line::from(format!("{} {}", spinner, progress)),
+        };
+        let messages = match ceremony.events {
+            Some(_) => format!(", {} messages received", ceremony.messages),
+            None => String::new(),
+        };
+        frame.render_widget(Clear, popup);
+        frame.render_widget(
+            Paragraph::new(vec![
+                status,
+                Line::from(""),
+                Line::from(format!(
+                    "Elapsed {}s of {}s{}",
+                    elapsed.as_secs(),
+                    ceremony.timeout.as_secs(),
+                    messages
+                )),
+            ])
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::bordered()
+                    .title(format!(" {} running ", ceremony.title))
+                    .title_bottom(Line::from(keys).centered())
+                    .border_style(Style::default().bold()),
+            ),
+            popup,
+        );
+    }
+
+    fn render_prompt(&self, frame: &mut Frame) {
+        let prompt = match &self.prompt {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        let area = frame.area();
+        let width = area.width.min(70);
+        let height = area.height.min(match prompt.kind {
+            PromptKind::Confirm => 16,
+            PromptKind::Error | PromptKind::Info => 14,
+            PromptKind::RetryAbort => 11,
+        });
+        let popup = Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        };
+
+        let instructions = match prompt.kind {
+            PromptKind::RetryAbort => Line::from(vec![
+                " Retry ".into(),
+                "R".blue().bold(),
+                " Abort ".into(),
+                "A/Esc".blue().bold(),
+            ]),
+            PromptKind::Error | PromptKind::Info => {
+                Line::from(vec![" Dismiss ".into(), "Enter/Esc".blue().bold()])
+            }
+            PromptKind::Confirm => Line::from(vec![
+                match self.mode {
+                    AppMode::Create => " Back up and continue ",
+                    _ => " Sign ",
+                }
+                .into(),
+                "Y".blue().bold(),
+                " Cancel ".into(),
+                "N/Esc".blue().bold(),
+            ]),
+        };
+        let border_style = match prompt.kind {
+            PromptKind::RetryAbort | PromptKind::Confirm => Style::default().yellow().bold(),
+            PromptKind::Error => Style::default().red().bold(),
+            PromptKind::Info => Style::default().bold(),
+        };
+
+        frame.render_widget(Clear, popup);
+        frame.render_widget(
+            Paragraph::new(prompt.message.clone())
+                .wrap(Wrap { trim: false })
+                .block(
+                    Block::bordered()
+                        .title(format!(" {} ", prompt.title))
+                        .title_bottom(instructions.centered())
+                        .border_style(border_style),
+                ),
+            popup,
+        );
+    }
+
     fn render_menu(&mut self, frame: &mut Frame) {
         let main_block = Block::bordered()
             .title(" BoomerSig (Main Menu)".bold())
@@ -124,30 +880,21 @@ impl App {
             .direction(Direction::Vertical)
             .constraints([
                 Constraint::Length(3),
-                Constraint::Length(5), // Increased space for menu items
-                Constraint::Min(3),
+                Constraint::Min(1), // Scrollable menu items
+                Constraint::Length(3),
             ])
             .split(main_block.inner(frame.area()));
 
-        let menu_items = vec!["Create Multisig", "Sign Multisig", "Get Address"];
-        let mut text = Text::default();
-        for (i, item) in menu_items.iter().enumerate() {
-            let style = if i == self.create_state.selected_field {
-                Style::default().blue().bold()
-            } else {
-                Style::default()
-            };
-            text.lines.push(Line::from(vec![Span::styled(
-                format!("▶ {} ", item),
-                style,
-            )]));
-        }
+        let items: Vec<ListItem> = MENU_ITEMS
+            .iter()
+            .map(|item| ListItem::new(Line::from(format!("▶ {} ", item)).centered()))
+            .collect();
 
-        frame.render_widget(
-            Paragraph::new(text)
-                .block(Block::default().title(""))
-                .centered(),
+        self.menu_list_state.select(Some(self.menu_state.selected));
+        frame.render_stateful_widget(
+            List::new(items).highlight_style(Style::default().blue().bold()),
             chunks[1],
+            &mut self.menu_list_state,
         );
 
         let instructions = Line::from(vec![
@@ -168,9 +915,19 @@ impl App {
         frame.render_widget(main_block, frame.area());
     }
 
+    /// Which relay the screen's ceremony will use, it is changed in Settings.
+    fn relay_title(&self) -> Line<'static> {
+        let title = Line::from(format!(" relay {} ", self.config.relay)).right_aligned();
+        match self.config.relay_insecure {
+            true => title.red(),
+            false => title,
+        }
+    }
+
     fn render_create(&mut self, frame: &mut Frame) {
         let main_block = Block::bordered()
             .title(" BoomerSig (Create Multisig) ".bold())
+            .title(self.relay_title())
             .border_set(border::THICK);
 
         let chunks = Layout::default()
@@ -179,6 +936,8 @@ impl App {
                 Constraint::Length(3),
                 Constraint::Length(3),
                 Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(2),
                 Constraint::Min(3),
             ])
             .split(main_block.inner(frame.area()));
@@ -210,11 +969,42 @@ impl App {
             );
         }
 
+        let test_style = if self.create_state.selected_field == 3 {
+            Style::default().blue().bold()
+        } else {
+            Style::default()
+        };
+        frame.render_widget(
+            Paragraph::new(if self.create_state.test_signature {
+                "Yes, all parties must stay online"
+            } else {
+                "No"
+            })
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Test Signature"),
+            )
+            .style(test_style),
+            chunks[3],
+        );
+
+        if let Some(status) = &self.create_state.status {
+            frame.render_widget(
+                Paragraph::new(status.as_str()).wrap(Wrap { trim: true }),
+                chunks[4],
+            );
+        }
+
         let instructions = Line::from(vec![
             " Navigate ".into(),
             "▲/▼".blue().bold(),
             " Adjust ".into(),
-            "◄/►".blue().bold(),
+            "◄/► 0-9".blue().bold(),
+            " Create ".into(),
+            "Enter".blue().bold(),
+            " Check ".into(),
+            "C".blue().bold(),
             " Back ".into(),
             "Esc".blue().bold(),
             " Quit ".into(),
@@ -224,7 +1014,7 @@ impl App {
             Paragraph::new(Text::from(instructions))
                 .block(Block::default())
                 .centered(),
-            chunks[3],
+            chunks[5],
         );
 
         frame.render_widget(main_block, frame.area());
@@ -233,20 +1023,26 @@ impl App {
     fn render_sign(&mut self, frame: &mut Frame) {
         let main_block = Block::bordered()
             .title(" BoomerSig (Sign Multisig)".bold())
+            .title(self.relay_title())
             .border_set(border::THICK);
 
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
+                Constraint::Length(3),
                 Constraint::Length(3),
                 Constraint::Min(3),
                 Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(if self.last_result.is_some() { 6 } else { 0 }),
+                Constraint::Length(3),
             ])
             .split(main_block.inner(frame.area()));
 
         let is_participant_selected = self.sign_state.selected_field == 0;
         let mut participant_text = self.sign_state.participant_index.to_string();
-        if is_participant_selected && self.create_state.cursor_visible {
+        if is_participant_selected && self.sign_state.cursor_visible {
             participant_text.push('_');
         }
 
@@ -256,18 +1052,66 @@ impl App {
             Style::default()
         };
 
+        let participant_title = share_file_title(&self.sign_state.share_file);
+        let share_summary = match &self.sign_state.share_info {
+            Some(info) => Line::from(format!(
+                " threshold {}, parties 1..={} ",
+                info.threshold, info.parties
+            )),
+            None => Line::from(" share unreadable ").yellow(),
+        };
+
         frame.render_widget(
             Paragraph::new(participant_text)
                 .block(
                     Block::default()
                         .borders(Borders::ALL)
-                        .title("Participant Index"),
+                        .title(participant_title)
+                        .title(share_summary.right_aligned()),
                 )
                 .style(participant_style),
             chunks[0],
         );
 
-        let is_psbt_selected = self.sign_state.selected_field == 1;
+        let is_attempt_selected = self.sign_state.selected_field == 1;
+        let mut attempt_text = self.sign_state.attempt.to_string();
+        if is_attempt_selected && self.sign_state.cursor_visible {
+            attempt_text.push('_');
+        }
+
+        let row = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(12), Constraint::Min(10)])
+            .split(chunks[1]);
+        frame.render_widget(
+            Paragraph::new(attempt_text)
+                .block(Block::default().borders(Borders::ALL).title("Attempt"))
+                .style(if is_attempt_selected {
+                    Style::default().blue().bold()
+                } else {
+                    Style::default()
+                }),
+            row[0],
+        );
+
+        let parties_check = match self.sign_parties() {
+            Ok(_) => Line::from(""),
+            Err(e) => Line::from(format!(" {} ", e)).red(),
+        };
+        self.sign_state.parties.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Signers")
+                .title(parties_check.right_aligned())
+                .style(if self.sign_state.selected_field == 2 {
+                    Style::default().blue().bold()
+                } else {
+                    Style::default()
+                }),
+        );
+        frame.render_widget(&self.sign_state.parties, row[1]);
+
+        let is_psbt_selected = self.sign_state.selected_field == 3;
         let psbt_block = Block::default()
             .borders(Borders::ALL)
             .border_style(if is_psbt_selected {
@@ -280,19 +1124,162 @@ impl App {
             } else {
                 Style::default()
             })
-            .title("PSBT (Critical Field) ▶");
+            .title("PSBT (Critical Field) ▶")
+            .title(match &self.sign_state.psbt_error {
+                Some(e) => Line::from(format!(" {} ", e)).red().right_aligned(),
+                None => Line::from(""),
+            });
 
         self.sign_state.psbt.set_block(psbt_block);
         self.sign_state
             .psbt
             .set_cursor_style(Style::default().bg(ratatui::style::Color::Yellow));
-        frame.render_widget(&self.sign_state.psbt, chunks[1]);
+        frame.render_widget(&self.sign_state.psbt, chunks[2]);
+
+        let is_values_selected = self.sign_state.selected_field == 4;
+        self.sign_state.input_values.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Input Amounts (only if the PSBT lacks them)")
+                .style(if is_values_selected {
+                    Style::default().blue().bold()
+                } else {
+                    Style::default()
+                }),
+        );
+        frame.render_widget(&self.sign_state.input_values, chunks[3]);
+
+        let choice_text = match self.sign_state.input_choice {
+            None => "All inputs I can sign".to_string(),
+            Some(index) => format!("Input {} only", index),
+        };
+        let row = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(10), Constraint::Length(28)])
+            .split(chunks[4]);
+        frame.render_widget(
+            Paragraph::new(choice_text)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Inputs to Sign ◄/►"),
+                )
+                .style(if self.sign_state.selected_field == 5 {
+                    Style::default().blue().bold()
+                } else {
+                    Style::default()
+                }),
+            row[0],
+        );
+        let dry_run_style = match (self.sign_state.selected_field == 6, self.sign_state.dry_run) {
+            (true, _) => Style::default().blue().bold(),
+            (false, true) => Style::default().yellow().bold(),
+            (false, false) => Style::default(),
+        };
+        frame.render_widget(
+            Paragraph::new(if self.sign_state.dry_run {
+                "Yes, never broadcast"
+            } else {
+                "No"
+            })
+            .block(Block::default().borders(Borders::ALL).title("Dry Run ◄/►"))
+            .style(dry_run_style),
+            row[1],
+        );
+
+        let mut preview = Vec::new();
+        if let Some(inputs) = &self.sign_state.signable_inputs {
+            let preview_span = if inputs.is_empty() {
+                Span::styled(
+                    " This PSBT has no inputs for your key",
+                    Style::default().red(),
+                )
+            } else {
+                let signing = match self.sign_state.input_choice {
+                    None => inputs.clone(),
+                    Some(index) => vec![index],
+                };
+                Span::styled(
+                    format!(
+                        " Will sign inputs {:?} of {:?} you can sign",
+                        signing, inputs
+                    ),
+                    Style::default().green(),
+                )
+            };
+            preview.push(Line::from(preview_span));
+        }
+        match &self.sign_state.fee {
+            Some(Ok(fee)) => {
+                let mut text = format!(" Fee: {}", fee_text(fee));
+                if fee.user_supplied {
+                    text.push_str(" (based on user-supplied amounts)");
+                }
+                if fee.is_high() {
+                    text.push_str(" - unusually high, double check the outputs");
+                }
+                let style = match fee.is_high() {
+                    true => Style::default().red().bold(),
+                    false => Style::default().green(),
+                };
+                preview.push(Line::from(Span::styled(text, style)));
+            }
+            Some(Err(e)) => preview.push(Line::from(Span::styled(
+                format!(" Fee unknown: {}", e),
+                Style::default().yellow(),
+            ))),
+            None => {}
+        }
+        if let Some(info) = &self.sign_state.share_info {
+            if is_legacy_only(&info.public_key, AddressType::P2pkh) {
+                preview.push(Line::from(Span::styled(
+                    format!(" {}", LEGACY_ONLY_WARNING),
+                    Style::default().yellow(),
+                )));
+            }
+        }
+        frame.render_widget(Paragraph::new(preview), chunks[5]);
+
+        match &self.last_result {
+            Some(Ok(result)) => {
+                let (title, body) = match (&result.signined_tx, &result.partially_signed_psbt) {
+                    (Some(tx), _) => ("Signed Transaction", tx.clone()),
+                    (None, Some(psbt)) => {
+                        ("Partially Signed PSBT (pass to next signer)", psbt.clone())
+                    }
+                    (None, None) => ("Signing Result", result.address.clone()),
+                };
+                let body = [
+                    &self.sign_state.broadcast_status,
+                    &self.sign_state.confirmation_status,
+                    &self.sign_state.hook_status,
+                ]
+                .iter()
+                .filter_map(|status| status.as_ref())
+                .fold(body, |body, status| format!("{}\n\n{}", body, status));
+                frame.render_widget(
+                    Paragraph::new(body).wrap(Wrap { trim: false }).block(
+                        Block::default()
+                            .borders(Borders::TOP)
+                            .title(title)
+                            .style(Style::default().fg(Color::Green)),
+                    ),
+                    chunks[6],
+                );
+            }
+            Some(Err(e)) => render_last_error(frame, e, chunks[6]),
+            None => {}
+        }
 
         let instructions = Line::from(vec![
             " Navigate ".into(),
             "▲/▼".blue().bold(),
             " Edit ".into(),
             "Enter".blue().bold(),
+            " Share ".into(),
+            "F2".blue().bold(),
+            " Scan QR ".into(),
+            "F4".blue().bold(),
             " Back ".into(),
             "Esc".blue().bold(),
             " Quit ".into(),
@@ -302,345 +1289,2625 @@ impl App {
             Paragraph::new(Text::from(instructions))
                 .block(Block::default())
                 .centered(),
-            chunks[2],
+            chunks[7],
         );
 
         frame.render_widget(main_block, frame.area());
     }
 
-    fn render_get_address(&mut self, frame: &mut Frame) {
+    fn render_history(&mut self, frame: &mut Frame) {
         let main_block = Block::bordered()
-            .title(" BoomerSig (Get Address)".bold())
+            .title(" BoomerSig (Signing History)".bold())
             .border_set(border::THICK);
 
         let chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(3),
-                Constraint::Length(3),
-                Constraint::Min(1), // Address display area
-                Constraint::Length(3),
-            ])
+            .constraints([Constraint::Min(1), Constraint::Length(3)])
             .split(main_block.inner(frame.area()));
 
-        // Participant Index Field
-        let is_participant_selected = self.get_address_state.selected_field == 0;
-        let mut participant_text = self.get_address_state.participant_index.to_string();
-        if is_participant_selected && self.create_state.cursor_visible {
-            participant_text.push('_');
+        match &self.history_state.error {
+            Some(error) => frame.render_widget(
+                Paragraph::new(error.as_str())
+                    .red()
+                    .wrap(Wrap { trim: true }),
+                chunks[0],
+            ),
+            None if self.history_state.records.is_empty() => frame.render_widget(
+                Paragraph::new("No signings recorded yet.").centered(),
+                chunks[0],
+            ),
+            None => {
+                // Newest first.
+                let items: Vec<ListItem> = self
+                    .history_state
+                    .records
+                    .iter()
+                    .rev()
+                    .map(|record| {
+                        ListItem::new(Line::from(format!(
+                            " {}  party {} with {:?}  {}",
+                            format_timestamp(record.timestamp),
+                            record.signer,
+                            record.parties,
+                            match &record.txid {
+                                Some(txid) => format!("txid {}", txid),
+                                None => format!("partial, {}", record.address),
+                            }
+                        )))
+                    })
+                    .collect();
+                frame.render_stateful_widget(
+                    List::new(items).highlight_style(Style::default().blue().bold()),
+                    chunks[0],
+                    &mut self.history_state.list_state,
+                );
+            }
         }
 
-        let participant_style = if is_participant_selected {
-            Style::default().blue().bold()
-        } else {
-            Style::default()
-        };
-
+        let instructions = Line::from(vec![
+            " Navigate ".into(),
+            "▲/▼".blue().bold(),
+            " Back ".into(),
+            "Esc".blue().bold(),
+            " Quit ".into(),
+            "Q".blue().bold(),
+        ]);
         frame.render_widget(
-            Paragraph::new(participant_text)
-                .block(
-                    Block::default()
-                        .borders(Borders::ALL)
-                        .title("Participant Index"),
-                )
-                .style(participant_style),
-            chunks[0],
+            Paragraph::new(Text::from(instructions))
+                .block(Block::default())
+                .centered(),
+            chunks[1],
         );
 
-        // OK Button
-        let is_ok_button_selected = self.get_address_state.selected_field == 1;
-        let ok_button = Paragraph::new("OK")
-            .block(Block::default().borders(Borders::ALL))
-            .style(if is_ok_button_selected {
-                Style::default().bg(Color::Blue).fg(Color::White)
-            } else {
-                Style::default()
-            });
+        frame.render_widget(main_block, frame.area());
+    }
 
-        frame.render_widget(ok_button, chunks[1]);
+    fn render_share_picker(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (Pick Share)".bold())
+            .title(
+                Line::from(format!(" {} ", self.share_picker_state.dir.display())).right_aligned(),
+            )
+            .border_set(border::THICK);
 
-        // Address Display
-        if let Some(addr) = &self.get_address_state.address {
-            let address_block = Block::default()
-                .borders(Borders::ALL)
-                .title("Generated Address")
-                .style(Style::default().fg(Color::Green));
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(1),
+                Constraint::Length(1),
+                Constraint::Length(3),
+            ])
+            .split(main_block.inner(frame.area()));
 
+        if self.share_picker_state.entries.is_empty() {
             frame.render_widget(
-                Paragraph::new(addr.clone())
-                    .block(address_block)
-                    .style(Style::default().bold()),
-                chunks[2],
+                Paragraph::new("No shares or directories here.").centered(),
+                chunks[0],
+            );
+        } else {
+            let items: Vec<ListItem> = self
+                .share_picker_state
+                .entries
+                .iter()
+                .map(|(label, _)| ListItem::new(format!(" {}", label)))
+                .collect();
+            frame.render_stateful_widget(
+                List::new(items)
+                    .highlight_style(Style::default().blue().bold())
+                    .highlight_symbol("▶"),
+                chunks[0],
+                &mut self.share_picker_state.list_state,
             );
         }
 
-        // Instructions
+        if let Some(error) = &self.share_picker_state.error {
+            frame.render_widget(Paragraph::new(format!(" {}", error)).red(), chunks[1]);
+        }
+
         let instructions = Line::from(vec![
             " Navigate ".into(),
             "▲/▼".blue().bold(),
-            " Select ".into(),
+            " Open ".into(),
             "Enter".blue().bold(),
             " Back ".into(),
             "Esc".blue().bold(),
+        ]);
+        frame.render_widget(
+            Paragraph::new(Text::from(instructions))
+                .block(Block::default())
+                .centered(),
+            chunks[2],
+        );
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    fn render_result(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (Result)".bold())
+            .border_set(border::THICK);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(3)])
+            .split(main_block.inner(frame.area()));
+
+        let mut lines = Vec::new();
+        for (label, value) in &self.result_view.fields {
+            lines.push(Line::from(format!(" {}", label).bold()));
+            lines.push(Line::from(format!(" {}", value)));
+            lines.push(Line::from(""));
+        }
+        frame.render_widget(
+            Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(self.result_view.title.as_str())
+                    .style(Style::default().fg(Color::Green)),
+            ),
+            chunks[0],
+        );
+
+        let mut instructions = vec![" Menu ".into(), "Enter/Esc".blue().bold()];
+        if let Some((label, _)) = &self.result_view.qr {
+            instructions.push(format!(" {} as QR ", label).into());
+            instructions.push("R".blue().bold());
+            instructions.push(" Copy ".into());
+            instructions.push("C".blue().bold());
+        }
+        if let Some(export) = &self.result_view.export {
+            instructions.push(format!(" Save {} ", export.label()).into());
+            instructions.push("S".blue().bold());
+        }
+        instructions.extend([" Quit ".into(), "Q".blue().bold()]);
+        if let Some(notice) = self.fresh_notice() {
+            instructions = vec![notice.to_string().green().bold()];
+        }
+        match (&mut self.export_path, &self.result_view.export) {
+            (Some(path), Some(export)) => {
+                path.set_block(Block::default().borders(Borders::ALL).title(format!(
+                    "Save {} to (Enter saves, Esc cancels)",
+                    export.label()
+                )));
+                frame.render_widget(&*path, chunks[1]);
+            }
+            _ => frame.render_widget(
+                Paragraph::new(Text::from(Line::from(instructions)))
+                    .block(Block::default())
+                    .centered(),
+                chunks[1],
+            ),
+        }
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    fn show_qr(&mut self, title: &str, data: &str) {
+        self.qr = Some(QrView {
+            title: title.into(),
+            parts: qr_parts(data),
+            shown: Instant::now(),
+            back: self.mode,
+        });
+        self.mode = AppMode::Qr;
+    }
+
+    fn render_qr(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (QR code)".bold())
+            .border_set(border::THICK);
+        let qr = match &self.qr {
+            Some(qr) => qr,
+            None => return,
+        };
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(1),
+                Constraint::Min(1),
+                Constraint::Length(1),
+            ])
+            .split(main_block.inner(frame.area()));
+
+        // Large data cycles through its parts on a timer, like the spinner.
+        let part =
+            (qr.shown.elapsed().as_millis() / FRAME_INTERVAL.as_millis()) as usize % qr.parts.len();
+        let title = match qr.parts.len() {
+            1 => qr.title.clone(),
+            parts => format!("{}, part {} of {}", qr.title, part + 1, parts),
+        };
+        frame.render_widget(Paragraph::new(title).bold().centered(), chunks[0]);
+
+        let area = chunks[1];
+        match qr_lines(&qr.parts[part]) {
+            Ok(lines) => {
+                let width = lines.first().map_or(0, |line| line.chars().count()) as u16;
+                let height = lines.len() as u16;
+                if width > area.width || height > area.height {
+                    frame.render_widget(
+                        Paragraph::new(format!(
+                            "Enlarge the terminal to show the QR code, it needs {}x{} more",
+                            width.saturating_sub(area.width),
+                            height.saturating_sub(area.height)
+                        ))
+                        .wrap(Wrap { trim: true })
+                        .centered(),
+                        area,
+                    );
+                } else {
+                    let code = Rect {
+                        x: area.x + (area.width - width) / 2,
+                        y: area.y + (area.height - height) / 2,
+                        width,
+                        height,
+                    };
+                    frame.render_widget(
+                        Paragraph::new(lines.into_iter().map(Line::from).collect::<Vec<_>>())
+                            .style(Style::default().fg(Color::White).bg(Color::Black)),
+                        code,
+                    );
+                }
+            }
+            Err(e) => {
+                frame.render_widget(Paragraph::new(format!("{:#}", e)).red().centered(), area)
+            }
+        }
+
+        let instructions = Line::from(vec![
+            " Back ".into(),
+            "Enter/Esc".blue().bold(),
             " Quit ".into(),
             "Q".blue().bold(),
         ]);
+        frame.render_widget(Paragraph::new(instructions).centered(), chunks[2]);
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    fn render_qr_import(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (Import PSBT from QR)".bold())
+            .border_set(border::THICK);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(1),
+                Constraint::Length(3),
+            ])
+            .split(main_block.inner(frame.area()));
+
+        let state = &mut self.qr_import_state;
+        state.part.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Scanned part, or a file with one part per line")
+                .style(Style::default().blue().bold()),
+        );
+        frame.render_widget(&state.part, chunks[0]);
+
+        let mut status = vec![Line::from(match state.assembler.progress() {
+            Some((received, total)) => format!(" Received {} of {} parts", received, total),
+            None => " Paste the ur:crypto-psbt or pMofN parts in any order".into(),
+        })];
+        if let Some(e) = &state.error {
+            status.push(Line::from(Span::styled(
+                format!(" {}", e),
+                Style::default().red(),
+            )));
+        }
+        frame.render_widget(
+            Paragraph::new(status)
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title("Progress")),
+            chunks[1],
+        );
+
+        let instructions = Line::from(vec![
+            " Add part ".into(),
+            "Enter".blue().bold(),
+            " Back ".into(),
+            "Esc".blue().bold(),
+        ]);
         frame.render_widget(
             Paragraph::new(Text::from(instructions))
                 .block(Block::default())
                 .centered(),
-            chunks[3],
+            chunks[2],
         );
 
         frame.render_widget(main_block, frame.area());
     }
 
-    fn handle_key_event(&mut self, key_event: crossterm::event::KeyEvent) {
-        if key_event.code == crossterm::event::KeyCode::Char('q') && self.mode != AppMode::Sign {
-            self.exit();
-            return;
+    fn handle_qr_import_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match key_event.code {
+            crossterm::event::KeyCode::Esc => {
+                self.qr_import_state = QrImportState::default();
+                self.mode = AppMode::Sign;
+            }
+            crossterm::event::KeyCode::Enter => {
+                let state = &mut self.qr_import_state;
+                let input = std::mem::take(&mut state.part).lines().join("\n");
+                let assembler = &mut state.assembler;
+                let error = scanned_parts(input.trim())
+                    .and_then(|parts| {
+                        parts
+                            .iter()
+                            .try_for_each(|part| assembler.receive(part).map(drop))
+                    })
+                    .err();
+                state.error = error.map(|e| format!("{:#}", e));
+                if let Some(psbt) = state.assembler.psbt() {
+                    self.sign_state.psbt = TextArea::new(vec![psbt.to_string()]);
+                    self.sign_state.psbt_error = None;
+                    self.sign_state.selected_field = 3;
+                    self.qr_import_state = QrImportState::default();
+                    self.mode = AppMode::Sign;
+                    self.refresh_signable_inputs();
+                }
+            }
+            _ => {
+                self.qr_import_state.part.input(key_event);
+            }
         }
+    }
 
-        match self.mode {
-            AppMode::Menu => self.handle_menu_input(key_event),
-            AppMode::Create => self.handle_create_input(key_event),
-            AppMode::Sign => self.handle_sign_input(key_event),
-            AppMode::GetAddress => self.handle_get_address_input(key_event),
+    /// What the Review screen shows of `psbt`, with rbf signalled first when
+    /// `config.rbf` is set so the txid is the one that gets signed.
+    fn review(&self, mut psbt: PartiallySignedTransaction) -> anyhow::Result<Review> {
+        let rbf_signaled = self.config.rbf && signal_rbf(&mut psbt)?;
+        // Computed afresh, the preview may lag behind the fields.
+        let fee = parse_input_values(&self.sign_state.input_values.lines().join(","))
+            .and_then(|values| compute_fee(&psbt, &values))
+            .map_err(|e| format!("{:#}", e));
+        let share_info = self.sign_state.share_info.as_ref();
+        let mut outputs = decode_outputs(
+            &psbt,
+            share_info.map(|info| &info.public_key),
+            self.config.network,
+        );
+        if let (true, Some(info)) = (self.config.script_multisig, share_info) {
+            let script_pubkey = info.multisig.script_pubkey();
+            for output in &mut outputs {
+                output.change |= output.script_pubkey == script_pubkey;
+            }
         }
+        Ok(Review {
+            txid: psbt.unsigned_tx.txid().to_string(),
+            outputs,
+            fee,
+            rbf_signaled,
+        })
     }
 
-    fn handle_menu_input(&mut self, key_event: crossterm::event::KeyEvent) {
+    fn render_review(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (Review PSBT)".bold())
+            .border_set(border::THICK);
+        let review = match &self.sign_state.review {
+            Some(review) => review,
+            None => return,
+        };
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(3),
+                Constraint::Length(7),
+                Constraint::Length(3),
+            ])
+            .split(main_block.inner(frame.area()));
+
+        let mut transaction = vec![Span::raw(format!(" {}", review.txid))];
+        if review.rbf_signaled {
+            transaction.push(Span::styled(
+                "  inputs switched to RBF signaling",
+                Style::default().yellow(),
+            ));
+        }
+        frame.render_widget(
+            Paragraph::new(Line::from(transaction))
+                .block(Block::default().borders(Borders::ALL).title("Transaction")),
+            chunks[0],
+        );
+
+        let rows = review.outputs.iter().enumerate().map(|(index, output)| {
+            let destination = match &output.address {
+                Some(address) => address.to_string(),
+                None => format!("script {}", hex::encode(output.script_pubkey.as_bytes())),
+            };
+            let row = Row::new(vec![
+                Cell::from(index.to_string()),
+                Cell::from(destination),
+                Cell::from(format!("{} sats", output.value)),
+                Cell::from(if output.change { "change" } else { "" }),
+            ]);
+            match output.change {
+                true => row.style(Style::default().dark_gray()),
+                false => row.style(Style::default().bold()),
+            }
+        });
+        frame.render_widget(
+            Table::new(
+                rows,
+                [
+                    Constraint::Length(3),
+                    Constraint::Min(20),
+                    Constraint::Length(20),
+                    Constraint::Length(6),
+                ],
+            )
+            .header(Row::new(vec!["#", "Destination", "Amount", ""]).underlined())
+            .block(Block::default().borders(Borders::ALL).title("Outputs")),
+            chunks[1],
+        );
+
+        let sum = |change: bool| -> u64 {
+            review
+                .outputs
+                .iter()
+                .filter(|output| output.change == change)
+                .map(|output| output.value)
+                .sum()
+        };
+        let mut summary = vec![
+            Line::from(format!(" Sent:         {} sats", sum(false))),
+            Line::from(format!(" Change:       {} sats", sum(true))),
+        ];
+        match &review.fee {
+            Ok(fee) => {
+                summary.insert(
+                    0,
+                    Line::from(format!(" Total input:  {} sats", fee.input_total)),
+                );
+                summary.push(Line::from(format!(" Fee:          {}", fee_text(fee))));
+                if fee.user_supplied {
+                    summary.push(Line::from(Span::styled(
+                        " Input amounts entered by you, not carried by the PSBT",
+                        Style::default().yellow(),
+                    )));
+                }
+                if fee.is_high() {
+                    summary.push(Line::from(Span::styled(
+                        format!(
+                            " Fee over {}% of the spent amount, double check the outputs",
+                            HIGH_FEE_PERCENT
+                        ),
+                        Style::default().red().bold(),
+                    )));
+                }
+            }
+            Err(e) => {
+                summary.insert(0, Line::from(" Total input:  unknown"));
+                summary.push(Line::from(Span::styled(
+                    format!(" Fee unknown: {}", e),
+                    Style::default().yellow(),
+                )));
+            }
+        }
+        frame.render_widget(
+            Paragraph::new(summary)
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title("Summary")),
+            chunks[2],
+        );
+
+        let instructions = Line::from(vec![
+            " Confirm and sign ".into(),
+            "Y".blue().bold(),
+            " Back ".into(),
+            "N/Esc".blue().bold(),
+        ]);
+        frame.render_widget(
+            Paragraph::new(Text::from(instructions))
+                .block(Block::default())
+                .centered(),
+            chunks[3],
+        );
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    /// Copies `text`, or says where it went when there is no clipboard.
+    fn copy_text(&mut self, label: &str, text: &str) {
+        match copy(label, text, &data_dir()) {
+            Ok(Copied::Clipboard) => {
+                self.notice = Some((format!("{} copied!", label), Instant::now()))
+            }
+            Ok(Copied::File(path)) => {
+                self.prompt = Some(Prompt::info(
+                    "No clipboard",
+                    format!(
+                        "There is no clipboard here, the {} was written to\n{}",
+                        label.to_lowercase(),
+                        path.display()
+                    ),
+                ))
+            }
+            Err(e) => self.prompt = Some(Prompt::error("Copy failed", describe_chain(&e))),
+        }
+    }
+
+    fn handle_export_input(&mut self, key_event: crossterm::event::KeyEvent) {
         match key_event.code {
-            crossterm::event::KeyCode::Up => {
-                self.create_state.selected_field =
-                    self.create_state.selected_field.saturating_sub(1);
+            crossterm::event::KeyCode::Esc => self.export_path = None,
+            crossterm::event::KeyCode::Enter => {
+                let path = match self.export_path.take() {
+                    Some(path) => PathBuf::from(path.lines().join("").trim()),
+                    None => return,
+                };
+                let export = match &self.result_view.export {
+                    Some(export) => export,
+                    None => return,
+                };
+                match export.save(&path) {
+                    Ok(()) => {
+                        self.notice = Some((
+                            format!("{} saved to {}", export.label(), path.display()),
+                            Instant::now(),
+                        ))
+                    }
+                    Err(e) => self.prompt = Some(Prompt::error("Save failed", describe_chain(&e))),
+                }
             }
-            crossterm::event::KeyCode::Down => {
-                if self.create_state.selected_field < 2 {
-                    self.create_state.selected_field += 1;
+            _ => {
+                if let Some(path) = &mut self.export_path {
+                    path.input(key_event);
                 }
             }
-            crossterm::event::KeyCode::Enter => match self.create_state.selected_field {
-                0 => self.mode = AppMode::Create,
-                1 => self.mode = AppMode::Sign,
-                2 => self.mode = AppMode::GetAddress,
-                _ => {}
-            },
+        }
+    }
+
+    fn fresh_notice(&self) -> Option<&str> {
+        self.notice
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < NOTICE_DURATION)
+            .map(|(notice, _)| notice.as_str())
+    }
+
+    fn show_result(&mut self, view: ResultView) {
+        self.result_view = view;
+        self.mode = AppMode::Result;
+    }
+
+    fn render_settings(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(format!(" BoomerSig (Settings, {})", self.config.network).bold())
+            .border_set(border::THICK);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(3),
+                Constraint::Length(3),
+                Constraint::Length(1),
+                Constraint::Length(3),
+            ])
+            .split(main_block.inner(frame.area()));
+
+        let field_style = |field: usize| {
+            if self.settings_state.selected_field == field {
+                Style::default().blue().bold()
+            } else {
+                Style::default()
+            }
+        };
+        let relay_style = field_style(0);
+        let recent_style = field_style(1);
+        let passphrase_style = field_style(2);
+
+        self.settings_state.relay.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("SM-manager Address")
+                .style(relay_style),
+        );
+        frame.render_widget(&self.settings_state.relay, chunks[0]);
+
+        let items: Vec<ListItem> = self
+            .config
+            .recent_relays
+            .iter()
+            .map(|relay| ListItem::new(format!(" {}", relay)))
+            .collect();
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Recently Used")
+                        .style(recent_style),
+                )
+                .highlight_style(Style::default().blue().bold())
+                .highlight_symbol("▶"),
+            chunks[1],
+            &mut self.settings_state.recent_relays,
+        );
+
+        let passphrase_title = match self.share_passphrase {
+            Some(_) => "Share Passphrase (set)",
+            None => "Share Passphrase (none, shares are written in plaintext)",
+        };
+        self.settings_state.passphrase.set_mask_char('*');
+        self.settings_state.passphrase.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(passphrase_title)
+                .style(passphrase_style),
+        );
+        frame.render_widget(&self.settings_state.passphrase, chunks[2]);
+
+        match &self.settings_state.status {
+            Some(Ok(status)) => {
+                frame.render_widget(Paragraph::new(format!(" {}", status)).green(), chunks[3])
+            }
+            Some(Err(e)) => frame.render_widget(Paragraph::new(format!(" {}", e)).red(), chunks[3]),
+            None => {}
+        }
+
+        let instructions = Line::from(vec![
+            " Switch ".into(),
+            "Tab".blue().bold(),
+            " Navigate ".into(),
+            "▲/▼".blue().bold(),
+            " Use ".into(),
+            "Enter".blue().bold(),
+            " Back ".into(),
+            "Esc".blue().bold(),
+        ]);
+        frame.render_widget(
+            Paragraph::new(Text::from(instructions))
+                .block(Block::default())
+                .centered(),
+            chunks[4],
+        );
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    fn render_send(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (Send Funds)".bold())
+            .border_set(border::THICK);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Min(1),
+                Constraint::Length(3),
+            ])
+            .split(main_block.inner(frame.area()));
+
+        let field_style = |field: usize| {
+            if self.send_state.selected_field == field {
+                Style::default().blue().bold()
+            } else {
+                Style::default()
+            }
+        };
+        let (destination_style, amount_style, fee_rate_style) =
+            (field_style(1), field_style(2), field_style(3));
+
+        frame.render_widget(
+            Paragraph::new(self.send_state.participant_index.to_string())
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Participant Index"),
+                )
+                .style(field_style(0)),
+            chunks[0],
+        );
+
+        self.send_state.destination.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Destination Address")
+                .style(destination_style),
+        );
+        frame.render_widget(&self.send_state.destination, chunks[1]);
+
+        self.send_state.amount.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Amount (sat)")
+                .style(amount_style),
+        );
+        frame.render_widget(&self.send_state.amount, chunks[2]);
+
+        self.send_state.fee_rate.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Fee Rate (sat/vB)")
+                .style(fee_rate_style),
+        );
+        frame.render_widget(&self.send_state.fee_rate, chunks[3]);
+
+        frame.render_widget(
+            Paragraph::new(match self.send_state.spend_rx {
+                Some(_) => "Looking up UTXOs…",
+                None => "Build Transaction",
+            })
+            .block(Block::default().borders(Borders::ALL))
+            .style(if self.send_state.selected_field == 4 {
+                Style::default().bg(Color::Blue).fg(Color::White)
+            } else {
+                Style::default()
+            }),
+            chunks[4],
+        );
+
+        let instructions = Line::from(vec![
+            " Navigate ".into(),
+            "▲/▼".blue().bold(),
+            " Participant ".into(),
+            "◄/►".blue().bold(),
+            " Build ".into(),
+            "Enter".blue().bold(),
+            " Back ".into(),
+            "Esc".blue().bold(),
+        ]);
+        frame.render_widget(
+            Paragraph::new(Text::from(instructions))
+                .block(Block::default())
+                .centered(),
+            chunks[6],
+        );
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    fn render_sweep(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (Sweep Funds)".bold())
+            .border_set(border::THICK);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Min(1),
+                Constraint::Length(3),
+            ])
+            .split(main_block.inner(frame.area()));
+
+        let field_style = |field: usize| {
+            if self.sweep_state.selected_field == field {
+                Style::default().blue().bold()
+            } else {
+                Style::default()
+            }
+        };
+        let destination_style = field_style(1);
+        let fee_rate_style = field_style(2);
+
+        frame.render_widget(
+            Paragraph::new(self.sweep_state.participant_index.to_string())
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Participant Index"),
+                )
+                .style(field_style(0)),
+            chunks[0],
+        );
+
+        self.sweep_state.destination.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Destination Address")
+                .style(destination_style),
+        );
+        frame.render_widget(&self.sweep_state.destination, chunks[1]);
+
+        self.sweep_state.fee_rate.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Fee Rate (sat/vB)")
+                .style(fee_rate_style),
+        );
+        frame.render_widget(&self.sweep_state.fee_rate, chunks[2]);
+
+        frame.render_widget(
+            Paragraph::new(match self.sweep_state.sweep_rx {
+                Some(_) => "Looking up UTXOs…",
+                None => "Build Sweep",
+            })
+            .block(Block::default().borders(Borders::ALL))
+            .style(if self.sweep_state.selected_field == 3 {
+                Style::default().bg(Color::Blue).fg(Color::White)
+            } else {
+                Style::default()
+            }),
+            chunks[3],
+        );
+
+        let instructions = Line::from(vec![
+            " Navigate ".into(),
+            "▲/▼".blue().bold(),
+            " Participant ".into(),
+            "◄/►".blue().bold(),
+            " Build ".into(),
+            "Enter".blue().bold(),
+            " Back ".into(),
+            "Esc".blue().bold(),
+        ]);
+        frame.render_widget(
+            Paragraph::new(Text::from(instructions))
+                .block(Block::default())
+                .centered(),
+            chunks[5],
+        );
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    fn render_get_address(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(" BoomerSig (Get Address)".bold())
+            .title(self.relay_title())
+            .border_set(border::THICK);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Min(1), // Address display area
+                Constraint::Length(3),
+            ])
+            .split(main_block.inner(frame.area()));
+
+        // Participant Index Field
+        let is_participant_selected = self.get_address_state.selected_field == 0;
+        let mut participant_text = self.get_address_state.participant_index.to_string();
+        if is_participant_selected && self.get_address_state.cursor_visible {
+            participant_text.push('_');
+        }
+
+        let participant_style = if is_participant_selected {
+            Style::default().blue().bold()
+        } else {
+            Style::default()
+        };
+
+        frame.render_widget(
+            Paragraph::new(participant_text)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(share_file_title(&self.get_address_state.share_file)),
+                )
+                .style(participant_style),
+            chunks[0],
+        );
+
+        // Attempt Field
+        let is_attempt_selected = self.get_address_state.selected_field == 1;
+        let mut attempt_text = self.get_address_state.attempt.to_string();
+        if is_attempt_selected && self.get_address_state.cursor_visible {
+            attempt_text.push('_');
+        }
+
+        let row = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(12), Constraint::Min(10)])
+            .split(chunks[1]);
+        frame.render_widget(
+            Paragraph::new(attempt_text)
+                .block(Block::default().borders(Borders::ALL).title("Attempt"))
+                .style(if is_attempt_selected {
+                    Style::default().blue().bold()
+                } else {
+                    Style::default()
+                }),
+            row[0],
+        );
+
+        // Signers Field
+        let parties_check = match self.address_parties() {
+            Ok(_) => Line::from(""),
+            Err(e) => Line::from(format!(" {} ", e)).red(),
+        };
+        self.get_address_state.parties.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Signers")
+                .title(parties_check.right_aligned())
+                .style(if self.get_address_state.selected_field == 2 {
+                    Style::default().blue().bold()
+                } else {
+                    Style::default()
+                }),
+        );
+        frame.render_widget(&self.get_address_state.parties, row[1]);
+
+        // Digest Field
+        let is_digest_selected = self.get_address_state.selected_field == 3;
+        self.get_address_state.digest.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Digest to Sign (32 bytes hex)")
+                .title(Line::from(" the address is recovered from this signature ").right_aligned())
+                .style(if is_digest_selected {
+                    Style::default().blue().bold()
+                } else {
+                    Style::default()
+                }),
+        );
+        frame.render_widget(&self.get_address_state.digest, chunks[2]);
+
+        // OK Button
+        let is_ok_button_selected = self.get_address_state.selected_field == 4;
+        let ok_button = Paragraph::new("OK")
+            .block(Block::default().borders(Borders::ALL))
+            .style(if is_ok_button_selected {
+                Style::default().bg(Color::Blue).fg(Color::White)
+            } else {
+                Style::default()
+            });
+
+        frame.render_widget(ok_button, chunks[3]);
+
+        let address_area = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(0)])
+            .split(chunks[4]);
+        match (&self.last_result, &self.get_address_state.utxos) {
+            (Some(Err(e)), _) => render_last_error(frame, e, address_area[1]),
+            (_, Some(Ok(utxos))) if self.get_address_state.address.is_some() => {
+                frame.render_widget(utxo_list(utxos), address_area[1])
+            }
+            _ => {}
+        }
+
+        // Address Display
+        if let Some(addr) = &self.get_address_state.address {
+            let balance = match (
+                &self.get_address_state.utxos_rx,
+                &self.get_address_state.utxos,
+            ) {
+                (Some(_), _) => Line::from(" Refreshing... ").yellow(),
+                (None, Some(Ok(utxos))) => {
+                    let balance = Balance::of(utxos);
+                    Line::from(format!(
+                        " Balance: {} sat confirmed, {} sat unconfirmed ",
+                        balance.confirmed, balance.unconfirmed
+                    ))
+                }
+                (None, Some(Err(e))) => Line::from(format!(" Balance unknown: {} ", e)).red(),
+                (None, None) => Line::from(""),
+            };
+            let address_block = Block::default()
+                .borders(Borders::ALL)
+                .title("Generated Address")
+                .title(balance.right_aligned())
+                .style(Style::default().fg(Color::Green));
+
+            frame.render_widget(
+                Paragraph::new(addr.clone())
+                    .block(address_block)
+                    .style(Style::default().bold()),
+                address_area[0],
+            );
+        }
+
+        // Instructions
+        let instructions = Line::from(vec![
+            " Navigate ".into(),
+            "▲/▼".blue().bold(),
+            " Select ".into(),
+            "Enter".blue().bold(),
+            " Refresh ".into(),
+            "F5".blue().bold(),
+            " Share ".into(),
+            "F2".blue().bold(),
+            " QR ".into(),
+            "F3".blue().bold(),
+            " Copy ".into(),
+            "F4".blue().bold(),
+            " Back ".into(),
+            "Esc".blue().bold(),
+            " Quit ".into(),
+            "Q".blue().bold(),
+        ]);
+        let instructions = match self.fresh_notice() {
+            Some(notice) => Line::from(notice.to_string().green().bold()),
+            None => instructions,
+        };
+        frame.render_widget(
+            Paragraph::new(Text::from(instructions))
+                .block(Block::default())
+                .centered(),
+            chunks[5],
+        );
+
+        frame.render_widget(main_block, frame.area());
+    }
+
+    fn handle_key_event(&mut self, key_event: crossterm::event::KeyEvent) {
+        if let Some(ceremony) = &self.ceremony {
+            match key_event.code {
+                crossterm::event::KeyCode::Esc => self.cancel_ceremony(),
+                crossterm::event::KeyCode::Enter => ceremony.progress.start_now(),
+                _ => {}
+            }
+            return;
+        }
+        if self.prompt.is_some() {
+            self.handle_prompt_input(key_event);
+            return;
+        }
+
+        // 'q' is a valid character in the text fields of these screens.
+        let typing = match self.mode {
+            AppMode::Sign
+            | AppMode::Send
+            | AppMode::Sweep
+            | AppMode::Settings
+            | AppMode::QrImport => true,
+            AppMode::Result => self.export_path.is_some(),
+            _ => false,
+        };
+        if key_event.code == crossterm::event::KeyCode::Char('q') && !typing {
+            self.exit();
+            return;
+        }
+
+        match self.mode {
+            AppMode::Menu => self.handle_menu_input(key_event),
+            AppMode::Create => self.handle_create_input(key_event),
+            AppMode::Sign => self.handle_sign_input(key_event),
+            AppMode::GetAddress => self.handle_get_address_input(key_event),
+            AppMode::Send => self.handle_send_input(key_event),
+            AppMode::Sweep => self.handle_sweep_input(key_event),
+            AppMode::History => self.handle_history_input(key_event),
+            AppMode::Settings => self.handle_settings_input(key_event),
+            AppMode::Result if self.export_path.is_some() => self.handle_export_input(key_event),
+            AppMode::Result => match key_event.code {
+                crossterm::event::KeyCode::Enter | crossterm::event::KeyCode::Esc => {
+                    self.mode = AppMode::Menu
+                }
+                crossterm::event::KeyCode::Char('s') => {
+                    if let Some(export) = &self.result_view.export {
+                        let mut path = TextArea::new(vec![export
+                            .default_path(&data_dir())
+                            .display()
+                            .to_string()]);
+                        // Ready to edit the file name.
+                        path.move_cursor(CursorMove::End);
+                        self.export_path = Some(path);
+                    }
+                }
+                crossterm::event::KeyCode::Char('r') => {
+                    if let Some((label, data)) = self.result_view.qr.clone() {
+                        self.show_qr(label, &data);
+                    }
+                }
+                crossterm::event::KeyCode::Char('c') => {
+                    if let Some((label, data)) = self.result_view.qr.clone() {
+                        self.copy_text(label, &data);
+                    }
+                }
+                _ => {}
+            },
+            AppMode::Qr => match key_event.code {
+                crossterm::event::KeyCode::Enter | crossterm::event::KeyCode::Esc => {
+                    if let Some(qr) = self.qr.take() {
+                        self.mode = qr.back;
+                    }
+                }
+                _ => {}
+            },
+            AppMode::SharePicker => self.handle_share_picker_input(key_event),
+            AppMode::Review => self.handle_review_input(key_event),
+            AppMode::QrImport => self.handle_qr_import_input(key_event),
+        }
+    }
+
+    fn handle_review_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match key_event.code {
+            crossterm::event::KeyCode::Char('y') => {
+                self.sign_state.review = None;
+                self.mode = AppMode::Sign;
+                self.sign_psbt();
+            }
+            crossterm::event::KeyCode::Char('n') | crossterm::event::KeyCode::Esc => {
+                self.sign_state.review = None;
+                self.mode = AppMode::Sign;
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_menu_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match key_event.code {
+            // Both ways wrap around the ends of the menu.
+            crossterm::event::KeyCode::Up => {
+                self.menu_state.selected =
+                    (self.menu_state.selected + MENU_ITEMS.len() - 1) % MENU_ITEMS.len();
+            }
+            crossterm::event::KeyCode::Down => {
+                self.menu_state.selected = (self.menu_state.selected + 1) % MENU_ITEMS.len();
+            }
+            crossterm::event::KeyCode::Enter => match self.menu_state.selected {
+                0 => self.mode = AppMode::Create,
+                1 => {
+                    self.mode = AppMode::Sign;
+                    self.refresh_share_info();
+                }
+                2 => {
+                    self.mode = AppMode::GetAddress;
+                    self.refresh_address_share_info();
+                }
+                3 => self.mode = AppMode::Send,
+                4 => self.mode = AppMode::Sweep,
+                5 => {
+                    self.mode = AppMode::History;
+                    self.refresh_history();
+                }
+                6 => {
+                    self.mode = AppMode::Settings;
+                    self.open_settings();
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn handle_create_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match key_event.code {
+            crossterm::event::KeyCode::Esc => self.mode = AppMode::Menu,
+            crossterm::event::KeyCode::Up => {
+                if self.create_state.selected_field > 0 {
+                    self.create_state.selected_field -= 1;
+                }
+            }
+            crossterm::event::KeyCode::Down => {
+                if self.create_state.selected_field < 3 {
+                    self.create_state.selected_field += 1;
+                }
+            }
+            code @ (crossterm::event::KeyCode::Left
+            | crossterm::event::KeyCode::Right
+            | crossterm::event::KeyCode::Backspace
+            | crossterm::event::KeyCode::Char('0'..='9')) => match self.create_state.selected_field
+            {
+                0 => edit_number(&mut self.create_state.threshold, code),
+                1 => edit_number(&mut self.create_state.number_of_parties, code),
+                2 => edit_number(&mut self.create_state.participant_index, code),
+                3 if code == crossterm::event::KeyCode::Left
+                    || code == crossterm::event::KeyCode::Right =>
+                {
+                    self.create_state.test_signature = !self.create_state.test_signature
+                }
+                _ => {}
+            },
+            crossterm::event::KeyCode::Char('c') => {
+                let config = match self.keygen_config() {
+                    Some(config) => config,
+                    None => return,
+                };
+                let checks = preflight(&config);
+                let report: Vec<String> = checks
+                    .iter()
+                    .map(|check| match &check.result {
+                        Ok(()) => format!("PASS  {}", check.name),
+                        Err(e) => format!("FAIL  {}: {}", check.name, e),
+                    })
+                    .collect();
+                let title = match checks.iter().all(|check| check.result.is_ok()) {
+                    true => "Ready for keygen",
+                    false => "Not ready for keygen",
+                };
+                self.prompt = Some(Prompt::info(title, report.join("\n")));
+            }
+            crossterm::event::KeyCode::Enter => {
+                let output = self.config.share_path(self.create_state.participant_index);
+                if output.exists() {
+                    self.prompt = Some(Prompt::share_exists(&output));
+                    return;
+                }
+                self.start_keygen();
+            }
+            _ => {}
+        }
+    }
+
+    fn start_keygen(&mut self) {
+        let mut config = match self.keygen_config() {
+            Some(config) => config,
+            None => return,
+        };
+        self.config.participant_index = self.create_state.participant_index;
+        let progress = config.progress.clone();
+        // The test signature is a second ceremony of its own.
+        let ceremony_timeout = match config.test_signature {
+            true => self.config.ceremony_timeout() + self.config.signing_timeout(),
+            false => self.config.ceremony_timeout(),
+        };
+        self.create_state.status = None;
+        let (events, received) = tokio::sync::mpsc::channel(KEYGEN_EVENTS);
+        config.events = Some(events);
+
+        self.start_ceremony("Keygen", ceremony_timeout, progress, move || async move {
+            Finished::Keygen(timeout(ceremony_timeout, do_keygen(config)).await)
+        });
+        if let Some(ceremony) = &mut self.ceremony {
+            ceremony.events = Some(received);
+        }
+    }
+
+    /// Keygen again over a share that already exists, once it is backed up.
+    fn back_up_and_keygen(&mut self) {
+        let output = self.config.share_path(self.create_state.participant_index);
+        match back_up_share(&output) {
+            Ok(backup) => {
+                self.start_keygen();
+                self.create_state.status =
+                    Some(format!("Old share backed up to {}", backup.display()));
+            }
+            Err(e) => self.prompt = Some(Prompt::error("Backup failed", describe_chain(&e))),
+        }
+    }
+
+    fn keygen_config(&mut self) -> Option<KeygenConfig> {
+        let (address, relay_options) = self.relay()?;
+        Some(KeygenConfig {
+            output: self.config.share_path(self.create_state.participant_index),
+            address,
+            relay_options,
+            room: "default-keygen".into(),
+            index: self.create_state.participant_index as u16,
+            threshold: self.create_state.threshold as u16,
+            number_of_parties: self.create_state.number_of_parties as u16,
+            test_signature: self.create_state.test_signature,
+            progress: ProgressHandle::default(),
+            network: self.config.network,
+            passphrase: self.share_passphrase.clone(),
+            script_multisig: self.config.script_multisig,
+            timeouts: self.config.phase_timeouts(),
+            events: None,
+        })
+    }
+
+    fn handle_sign_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match key_event.code {
+            crossterm::event::KeyCode::Esc => self.mode = AppMode::Menu,
+            crossterm::event::KeyCode::F(2) => self.open_share_picker(AppMode::Sign),
+            crossterm::event::KeyCode::F(4) => self.mode = AppMode::QrImport,
+            crossterm::event::KeyCode::Up => {
+                self.sign_state.selected_field = (self.sign_state.selected_field + 6) % 7;
+            }
+            crossterm::event::KeyCode::Down => {
+                self.sign_state.selected_field = (self.sign_state.selected_field + 1) % 7;
+            }
+            crossterm::event::KeyCode::Enter => {
+                if self.sign_state.selected_field == 2 {
+                    // Accept the suggested list, with our own index added.
+                    if let Err(BoomerError::SignerNotInParties { suggestion, .. }) =
+                        self.sign_parties()
+                    {
+                        self.set_sign_parties(&suggestion);
+                    }
+                } else if self.sign_state.selected_field == 3 {
+                    // Checked before anything touches the network.
+                    if let Err(e) = self.sign_parties() {
+                        self.prompt = Some(Prompt::error("Invalid signers", e.to_string()));
+                        return;
+                    }
+                    if self.sign_state.signable_inputs == Some(vec![]) {
+                        self.prompt = Some(Prompt::error(
+                            "Nothing to sign",
+                            "This PSBT has no inputs for your key.".into(),
+                        ));
+                        return;
+                    }
+                    // Pasted PSBTs come with line breaks and stray spaces.
+                    self.sign_state.data_to_sign =
+                        compact_psbt(&self.sign_state.psbt.lines().join("\n"));
+                    let psbt = match parse_psbt(&self.sign_state.data_to_sign) {
+                        Ok(psbt) => psbt,
+                        Err(e) => {
+                            self.sign_state.psbt_error = Some(format!("{:#}", e));
+                            return;
+                        }
+                    };
+                    if let Err(e) = check_psbt_network(&psbt, self.config.network) {
+                        self.prompt = Some(Prompt::error("Wrong network", e.to_string()));
+                        return;
+                    }
+                    match self.review(psbt) {
+                        Ok(review) => {
+                            self.sign_state.review = Some(review);
+                            self.mode = AppMode::Review;
+                        }
+                        Err(e) => {
+                            self.prompt = Some(Prompt::error(
+                                "Cannot signal replace-by-fee",
+                                describe_chain(&e),
+                            ))
+                        }
+                    }
+                }
+            }
+            _ => {
+                if self.sign_state.selected_field == 0 {
+                    edit_number(&mut self.sign_state.participant_index, key_event.code);
+                    self.sign_state.share_file = None;
+                    self.refresh_share_info();
+                } else if self.sign_state.selected_field == 1 {
+                    edit_number(&mut self.sign_state.attempt, key_event.code);
+                } else if self.sign_state.selected_field == 2 {
+                    self.sign_state.parties.input(key_event);
+                } else if self.sign_state.selected_field == 3 {
+                    self.sign_state.psbt.input(key_event);
+                    self.sign_state.psbt_error = None;
+                    self.refresh_signable_inputs();
+                } else if self.sign_state.selected_field == 5 {
+                    match key_event.code {
+                        crossterm::event::KeyCode::Left => self.cycle_input_choice(false),
+                        crossterm::event::KeyCode::Right => self.cycle_input_choice(true),
+                        _ => {}
+                    }
+                } else if self.sign_state.selected_field == 6 {
+                    if let crossterm::event::KeyCode::Left | crossterm::event::KeyCode::Right =
+                        key_event.code
+                    {
+                        self.sign_state.dry_run = !self.sign_state.dry_run;
+                    }
+                } else {
+                    self.sign_state.input_values.input(key_event);
+                    self.refresh_signable_inputs();
+                }
+            }
+        }
+    }
+
+    fn handle_history_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match key_event.code {
+            crossterm::event::KeyCode::Esc => self.mode = AppMode::Menu,
+            crossterm::event::KeyCode::Up => self.history_state.list_state.select_previous(),
+            crossterm::event::KeyCode::Down => self.history_state.list_state.select_next(),
+            _ => {}
+        }
+    }
+
+    fn handle_settings_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match (self.settings_state.selected_field, key_event.code) {
+            (_, crossterm::event::KeyCode::Esc) => self.mode = AppMode::Menu,
+            (_, crossterm::event::KeyCode::Tab) => {
+                self.settings_state.selected_field = (self.settings_state.selected_field + 1) % 3;
+            }
+            (2, crossterm::event::KeyCode::Enter) => {
+                let passphrase = self.settings_state.passphrase.lines().join("");
+                self.settings_state.passphrase = TextArea::default();
+                self.share_passphrase = Some(passphrase).filter(|p| !p.is_empty());
+                self.settings_state.status = Some(Ok(match self.share_passphrase {
+                    Some(_) => "Passphrase set for this session".into(),
+                    None => "Passphrase cleared".into(),
+                }));
+                // The share can be inspected again now.
+                self.refresh_share_info();
+                self.refresh_address_share_info();
+            }
+            (2, _) => {
+                self.settings_state.passphrase.input(key_event);
+            }
+            (0, crossterm::event::KeyCode::Enter) => {
+                let relay = self.settings_state.relay.lines().join("");
+                self.use_relay(&relay);
+            }
+            (0, _) => {
+                self.settings_state.relay.input(key_event);
+            }
+            (_, crossterm::event::KeyCode::Up) => {
+                self.settings_state.recent_relays.select_previous()
+            }
+            (_, crossterm::event::KeyCode::Down) => self.settings_state.recent_relays.select_next(),
+            (_, crossterm::event::KeyCode::Enter) => {
+                let selected = self
+                    .settings_state
+                    .recent_relays
+                    .selected()
+                    .and_then(|index| self.config.recent_relays.get(index).cloned());
+                if let Some(relay) = selected {
+                    self.use_relay(&relay);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_send_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match (self.send_state.selected_field, key_event.code) {
+            (_, crossterm::event::KeyCode::Esc) => {
+                self.send_state.spend_rx = None;
+                self.mode = AppMode::Menu;
+            }
+            (_, crossterm::event::KeyCode::Up) => {
+                self.send_state.selected_field = (self.send_state.selected_field + 4) % 5;
+            }
+            (_, crossterm::event::KeyCode::Down) => {
+                self.send_state.selected_field = (self.send_state.selected_field + 1) % 5;
+            }
+            (0, code) => edit_number(&mut self.send_state.participant_index, code),
+            (1, _) => {
+                self.send_state.destination.input(key_event);
+            }
+            (2, _) => {
+                self.send_state.amount.input(key_event);
+            }
+            (3, _) => {
+                self.send_state.fee_rate.input(key_event);
+            }
+            (4, crossterm::event::KeyCode::Enter) => self.build_spend(),
+            _ => {}
+        }
+    }
+
+    /// Builds the payment out of the multisig's UTXOs on a background thread,
+    /// `poll_spend` then asks for confirmation with the exact amounts before
+    /// handing it to the Sign screen.
+    fn build_spend(&mut self) {
+        if self.send_state.spend_rx.is_some() {
+            return;
+        }
+        match self.prepare_spend() {
+            Ok(prepare) => self.send_state.spend_rx = Some(in_background(prepare)),
+            Err(e) => {
+                self.prompt = Some(Prompt::error(
+                    "Cannot build transaction",
+                    describe_chain(&e),
+                ))
+            }
+        }
+    }
+
+    fn poll_spend(&mut self) {
+        let received = match &self.send_state.spend_rx {
+            Some(rx) => rx.try_recv(),
+            None => return,
+        };
+        match received {
+            Ok(Ok(prepared)) => {
+                self.send_state.spend_rx = None;
+                self.confirm_spend(prepared);
+            }
+            Ok(Err(e)) => {
+                self.send_state.spend_rx = None;
+                self.prompt = Some(Prompt::error("Cannot build transaction", e));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.send_state.spend_rx = None,
+        }
+    }
+
+    fn confirm_spend(&mut self, (source, destination, fee_rate, spend): Prepared<Spend>) {
+        let change = match spend.change {
+            0 => "none, the rest goes to the fee".to_string(),
+            change => format!("{} sat back to the multisig", change),
+        };
+        let message = format!(
+            "Sending {} sat from {} UTXOs of\n{}\n\nTo:       {}\nFee:      {} sat ({} sat/vB)\nChange:   {}",
+            spend.amount,
+            spend.psbt.inputs.len(),
+            source,
+            destination,
+            spend.fee,
+            fee_rate,
+            change
+        );
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Confirm,
+            title: "Confirm transaction".into(),
+            message,
+        });
+        self.send_state.spend = Some(spend);
+    }
+
+    /// Checks the form, returning what looks up the UTXOs and builds the spend
+    /// off the UI thread.
+    fn prepare_spend(
+        &self,
+    ) -> anyhow::Result<impl FnOnce() -> anyhow::Result<Prepared<Spend>> + Send + 'static> {
+        let (share, source) = self.funding_address(self.send_state.participant_index)?;
+        let destination = parse_destination(
+            &self.send_state.destination.lines().join(""),
+            self.config.network,
+        )?;
+        let amount: u64 = self
+            .send_state
+            .amount
+            .lines()
+            .join("")
+            .trim()
+            .parse()
+            .context("amount must be a whole number of sats")?;
+        let fee_rate = parse_fee_rate(&self.send_state.fee_rate)?;
+        let esplora = self.config.esplora_url()?;
+        let compressed = share.public_key.compressed;
+        Ok(move || {
+            let utxos = block_on(fetch_utxos(&esplora, &source))?;
+            let spend = build_spend(
+                &utxos,
+                &source.script_pubkey(),
+                compressed,
+                &destination,
+                amount,
+                fee_rate,
+            )?;
+            Ok((source, destination, fee_rate, spend))
+        })
+    }
+
+    fn sign_spend(&mut self) {
+        if let Some(spend) = self.send_state.spend.take() {
+            self.open_in_sign(&spend.psbt, self.send_state.participant_index);
+        }
+    }
+
+    /// The share of `participant_index` and the group's p2pkh address its
+    /// funds are looked up at.
+    fn funding_address(
+        &self,
+        participant_index: u8,
+    ) -> anyhow::Result<(ShareInfo, bitcoin::Address)> {
+        let network = self.config.network;
+        let path = self.config.share_path(participant_index);
+        let share = self
+            .shares
+            .inspect(&path, self.share_passphrase.as_deref())?;
+        share.require_network(&path, network)?;
+        let source = AddressType::P2pkh
+            .deriver()
+            .derive(&share.public_key, network)?;
+        Ok((share, source))
+    }
+
+    fn handle_sweep_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match (self.sweep_state.selected_field, key_event.code) {
+            (_, crossterm::event::KeyCode::Esc) => {
+                self.sweep_state.sweep_rx = None;
+                self.mode = AppMode::Menu;
+            }
+            (_, crossterm::event::KeyCode::Up) => {
+                self.sweep_state.selected_field = (self.sweep_state.selected_field + 3) % 4;
+            }
+            (_, crossterm::event::KeyCode::Down) => {
+                self.sweep_state.selected_field = (self.sweep_state.selected_field + 1) % 4;
+            }
+            (0, code) => edit_number(&mut self.sweep_state.participant_index, code),
+            (1, _) => {
+                self.sweep_state.destination.input(key_event);
+            }
+            (2, _) => {
+                self.sweep_state.fee_rate.input(key_event);
+            }
+            (3, crossterm::event::KeyCode::Enter) => self.build_sweep(),
+            _ => {}
+        }
+    }
+
+    /// Builds the sweep on a background thread, `poll_sweep` then asks for
+    /// confirmation with the exact amounts before handing it to the Sign
+    /// screen.
+    fn build_sweep(&mut self) {
+        if self.sweep_state.sweep_rx.is_some() {
+            return;
+        }
+        match self.prepare_sweep() {
+            Ok(prepare) => self.sweep_state.sweep_rx = Some(in_background(prepare)),
+            Err(e) => self.prompt = Some(Prompt::error("Cannot build sweep", describe_chain(&e))),
+        }
+    }
+
+    fn poll_sweep(&mut self) {
+        let received = match &self.sweep_state.sweep_rx {
+            Some(rx) => rx.try_recv(),
+            None => return,
+        };
+        match received {
+            Ok(Ok(prepared)) => {
+                self.sweep_state.sweep_rx = None;
+                self.confirm_sweep(prepared);
+            }
+            Ok(Err(e)) => {
+                self.sweep_state.sweep_rx = None;
+                self.prompt = Some(Prompt::error("Cannot build sweep", e));
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.sweep_state.sweep_rx = None,
+        }
+    }
+
+    fn confirm_sweep(&mut self, (source, destination, fee_rate, sweep): Prepared<Sweep>) {
+        let message = format!(
+            "Sweeping {} sat from {} UTXOs of\n{}\n\nFee:      {} sat ({} sat/vB)\nReceives: {} sat\nTo:       {}",
+            sweep.balance,
+            sweep.psbt.inputs.len(),
+            source,
+            sweep.fee,
+            fee_rate,
+            sweep.amount(),
+            destination
+        );
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Confirm,
+            title: "Confirm sweep".into(),
+            message,
+        });
+        self.sweep_state.sweep = Some(sweep);
+    }
+
+    /// Checks the form, returning what looks up the UTXOs and builds the sweep
+    /// off the UI thread.
+    fn prepare_sweep(
+        &self,
+    ) -> anyhow::Result<impl FnOnce() -> anyhow::Result<Prepared<Sweep>> + Send + 'static> {
+        let (share, source) = self.funding_address(self.sweep_state.participant_index)?;
+        let destination = parse_destination(
+            &self.sweep_state.destination.lines().join(""),
+            self.config.network,
+        )?;
+        let fee_rate = parse_fee_rate(&self.sweep_state.fee_rate)?;
+        let esplora = self.config.esplora_url()?;
+        let compressed = share.public_key.compressed;
+        Ok(move || {
+            let utxos = block_on(fetch_utxos(&esplora, &source))?;
+            let sweep = build_sweep(
+                &utxos,
+                &source.script_pubkey(),
+                compressed,
+                &destination,
+                fee_rate,
+            )?;
+            Ok((source, destination, fee_rate, sweep))
+        })
+    }
+
+    fn sign_sweep(&mut self) {
+        if let Some(sweep) = self.sweep_state.sweep.take() {
+            self.open_in_sign(&sweep.psbt, self.sweep_state.participant_index);
+        }
+    }
+
+    /// Hands a PSBT built here to the Sign screen, ready to review and sign.
+    fn open_in_sign(&mut self, psbt: &PartiallySignedTransaction, participant_index: u8) {
+        self.sign_state.psbt = TextArea::new(vec![psbt.to_string()]);
+        self.sign_state.psbt_error = None;
+        self.sign_state.participant_index = participant_index;
+        self.sign_state.share_file = None;
+        self.sign_state.selected_field = 3;
+        self.mode = AppMode::Sign;
+        self.refresh_share_info();
+    }
+
+    fn sign_share(&self) -> PathBuf {
+        match &self.sign_state.share_file {
+            Some(path) => path.clone(),
+            None => self.config.share_path(self.sign_state.participant_index),
+        }
+    }
+
+    fn address_share(&self) -> PathBuf {
+        match &self.get_address_state.share_file {
+            Some(path) => path.clone(),
+            None => self
+                .config
+                .share_path(self.get_address_state.participant_index),
+        }
+    }
+
+    /// Browses for a share for `target`, starting next to the one it uses.
+    fn open_share_picker(&mut self, target: AppMode) {
+        let current = match target {
+            AppMode::GetAddress => self.address_share(),
+            _ => self.sign_share(),
+        };
+        let dir = current
+            .parent()
+            .filter(|dir| dir.is_dir())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| self.config.share_dir());
+        self.share_picker_state.target = target;
+        self.open_share_dir(dir);
+        self.mode = AppMode::SharePicker;
+    }
+
+    fn open_share_dir(&mut self, dir: PathBuf) {
+        self.share_picker_state.error = None;
+        self.share_picker_state.entries = match share_entries(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.share_picker_state.error =
+                    Some(format!("cannot list {}: {}", dir.display(), e));
+                Vec::new()
+            }
+        };
+        self.share_picker_state.list_state = ListState::default().with_selected(Some(0));
+        self.share_picker_state.dir = dir;
+    }
+
+    fn handle_share_picker_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        match key_event.code {
+            crossterm::event::KeyCode::Esc => self.mode = self.share_picker_state.target,
+            crossterm::event::KeyCode::Up => self.share_picker_state.list_state.select_previous(),
+            crossterm::event::KeyCode::Down => self.share_picker_state.list_state.select_next(),
+            crossterm::event::KeyCode::Enter => {
+                let selected = self
+                    .share_picker_state
+                    .list_state
+                    .selected()
+                    .and_then(|index| self.share_picker_state.entries.get(index))
+                    .map(|(_, path)| path.clone());
+                match selected {
+                    Some(path) if path.is_dir() => self.open_share_dir(path),
+                    Some(path) => self.pick_share(path),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Uses `path` if it holds a share, taking the participant index from it.
+    fn pick_share(&mut self, path: PathBuf) {
+        let info = match self.shares.inspect(&path, self.share_passphrase.as_deref()) {
+            Ok(info) => info,
+            Err(e) => {
+                self.share_picker_state.error = Some(format!("not a usable share: {:#}", e));
+                return;
+            }
+        };
+        let index = info.index as u8;
+        match self.share_picker_state.target {
+            AppMode::GetAddress => {
+                self.get_address_state.participant_index = index;
+                self.get_address_state.share_file = Some(path);
+                self.get_address_state.address = None;
+                self.refresh_address_share_info();
+            }
+            _ => {
+                self.sign_state.participant_index = index;
+                self.sign_state.share_file = Some(path);
+                self.refresh_share_info();
+            }
+        }
+        self.mode = self.share_picker_state.target;
+    }
+
+    fn open_settings(&mut self) {
+        self.settings_state.relay = TextArea::new(vec![self.config.relay.clone()]);
+        self.settings_state.recent_relays = ListState::default().with_selected(Some(0));
+        self.settings_state.passphrase = TextArea::default();
+        self.settings_state.selected_field = 0;
+        self.settings_state.status = None;
+    }
+
+    fn use_relay(&mut self, relay: &str) {
+        let saved = self
+            .config
+            .use_relay(relay)
+            .and_then(|()| save_config(&config_path(), &self.config));
+        self.settings_state.status = Some(match saved {
+            Ok(()) => Ok(format!("Using {}", self.config.relay)),
+            Err(e) => Err(format!("{:#}", e)),
+        });
+        self.settings_state.relay = TextArea::new(vec![self.config.relay.clone()]);
+        self.settings_state.recent_relays.select(Some(0));
+    }
+
+    /// The relay URL and connection options of the current config, or an
+    /// error prompt if they don't parse.
+    fn relay(&mut self) -> Option<(surf::Url, RelayOptions)> {
+        match self
+            .config
+            .relay_url()
+            .and_then(|url| Ok((url, self.config.relay_options()?)))
+        {
+            Ok(relay) => Some(relay),
+            Err(e) => {
+                self.prompt = Some(Prompt::error("Invalid relay", describe_chain(&e)));
+                None
+            }
+        }
+    }
+
+    fn refresh_history(&mut self) {
+        self.history_state.list_state = ListState::default().with_selected(Some(0));
+        match load_history(&history_path()) {
+            Ok(records) => {
+                self.history_state.records = records;
+                self.history_state.error = None;
+            }
+            Err(e) => self.history_state.error = Some(describe_chain(&e)),
+        }
+    }
+
+    fn refresh_share_info(&mut self) {
+        let share = self.sign_share();
+        self.sign_state.share_info = self
+            .shares
+            .inspect(&share, self.share_passphrase.as_deref())
+            .ok();
+        let signers = match &self.sign_state.share_info {
+            Some(info) => info.default_signers(),
+            None => FALLBACK_SIGNERS.to_vec(),
+        };
+        self.set_sign_parties(&signers);
+        self.refresh_signable_inputs();
+    }
+
+    fn set_sign_parties(&mut self, parties: &[u16]) {
+        self.sign_state.parties = parties_text(parties);
+    }
+
+    /// The signers entered on the Sign screen.
+    fn sign_parties(&self) -> Result<Vec<u16>, BoomerError> {
+        signer_set(
+            &self.sign_state.parties,
+            self.sign_state.participant_index,
+            &self.sign_share(),
+            self.sign_state.share_info.as_ref(),
+        )
+    }
+
+    fn refresh_address_share_info(&mut self) {
+        let share = self.address_share();
+        let info = self
+            .shares
+            .inspect(&share, self.share_passphrase.as_deref())
+            .ok();
+        let signers = match &info {
+            Some(info) => info.default_signers(),
+            None => FALLBACK_SIGNERS.to_vec(),
+        };
+        self.get_address_state.parties = parties_text(&signers);
+        self.get_address_state.share_info = info;
+    }
+
+    /// The signers entered on the Get Address screen.
+    fn address_parties(&self) -> Result<Vec<u16>, BoomerError> {
+        signer_set(
+            &self.get_address_state.parties,
+            self.get_address_state.participant_index,
+            &self.address_share(),
+            self.get_address_state.share_info.as_ref(),
+        )
+    }
+
+    fn refresh_signable_inputs(&mut self) {
+        let psbt = self.sign_state.psbt.lines().join("\n");
+        self.sign_state.signable_inputs = match (&self.sign_state.share_info, parse_psbt(&psbt)) {
+            (Some(share_info), Ok(psbt)) => Some(match self.config.script_multisig {
+                true => multisig_inputs(&psbt, &share_info.multisig),
+                false => signable_inputs(&psbt, &share_info.public_key),
+            }),
+            _ => None,
+        };
+        // A choice of input only makes sense for the PSBT it was made for.
+        let still_signable = match (
+            self.sign_state.input_choice,
+            &self.sign_state.signable_inputs,
+        ) {
+            (Some(index), Some(inputs)) => inputs.contains(&index),
+            _ => false,
+        };
+        if !still_signable {
+            self.sign_state.input_choice = None;
+        }
+        self.refresh_fee();
+    }
+
+    /// Steps through all inputs, then each signable input on its own.
+    fn cycle_input_choice(&mut self, forward: bool) {
+        let mut choices = vec![None];
+        if let Some(inputs) = &self.sign_state.signable_inputs {
+            choices.extend(inputs.iter().copied().map(Some));
+        }
+        let current = choices
+            .iter()
+            .position(|choice| *choice == self.sign_state.input_choice)
+            .unwrap_or(0);
+        let next = match forward {
+            true => (current + 1) % choices.len(),
+            false => (current + choices.len() - 1) % choices.len(),
+        };
+        self.sign_state.input_choice = choices[next];
+    }
+
+    fn refresh_fee(&mut self) {
+        let psbt = self.sign_state.psbt.lines().join("\n");
+        self.sign_state.fee = match parse_psbt(&psbt) {
+            Ok(psbt) => Some(
+                parse_input_values(&self.sign_state.input_values.lines().join(","))
+                    .and_then(|values| compute_fee(&psbt, &values))
+                    .map_err(|e| format!("{:#}", e)),
+            ),
+            Err(_) => None,
+        };
+    }
+
+    fn sign_psbt(&mut self) {
+        let (address, relay_options) = match self.relay() {
+            Some(relay) => relay,
+            None => return,
+        };
+        let parties = match self.sign_parties() {
+            Ok(parties) => parties,
+            Err(e) => {
+                self.prompt = Some(Prompt::error("Invalid signers", e.to_string()));
+                return;
+            }
+        };
+        self.config.participant_index = self.sign_state.participant_index;
+        let inputs = self
+            .sign_state
+            .input_choice
+            .map_or(InputSelection::All, InputSelection::Single);
+        // Every input is a ceremony of its own.
+        let ceremonies = match inputs {
+            InputSelection::All => self
+                .sign_state
+                .signable_inputs
+                .as_ref()
+                .map_or(1, |inputs| inputs.len().max(1)),
+            InputSelection::Single(_) => 1,
+        };
+        let ceremony_timeout = self.config.signing_timeout() * ceremonies as u32;
+        // Captured once, every attempt signs the same PSBT.
+        let config = SigningConfig {
+            room: signing_room(
+                &self.sign_state.data_to_sign,
+                &parties,
+                self.sign_state.attempt,
+                self.config.rbf,
+            ),
+            address,
+            relay_options,
+            parties,
+            transaction: true,
+            local_share: self.sign_share(),
+            data_to_sign: self.sign_state.data_to_sign.clone(),
+            idx: self.sign_state.participant_index as u16,
+            address_type: AddressType::P2pkh,
+            network: self.config.network,
+            hash_scheme: HashScheme::default(),
+            sighash_type: EcdsaSighashType::All,
+            progress: ProgressHandle::default(),
+            save_offline_stage: None,
+            resume_offline_stage: None,
+            inputs,
+            passphrase: self.share_passphrase.clone(),
+            script_multisig: self.config.script_multisig,
+            timeouts: self.config.phase_timeouts(),
+            dry_run: self.sign_state.dry_run,
+            rbf: self.config.rbf,
+            bip322: false,
+        };
+
+        self.sign_state.broadcast_status = None;
+        self.sign_state.broadcast_rx = None;
+        self.sign_state.broadcast_retries = 0;
+        self.sign_state.confirmation_status = None;
+        self.sign_state.confirmation_rx = None;
+        self.sign_state.hook_status = None;
+
+        let attempt = self.sign_state.attempt;
+        let retries = self.config.ceremony_retries;
+        let progress = config.progress.clone();
+        self.start_ceremony("Signing", ceremony_timeout, progress, move || async move {
+            let mut retried = 0;
+            loop {
+                let mut config = config.clone();
+                config.room = signing_room(
+                    &config.data_to_sign,
+                    &config.parties,
+                    attempt.wrapping_add(retried),
+                    config.rbf,
+                );
+                let (signer, parties) = (config.idx, config.parties.clone());
+                match timeout(ceremony_timeout, do_sign(config).err_into()).await {
+                    Err(_) if retried < retries => retried += 1,
+                    outcome => {
+                        return Finished::Sign {
+                            outcome,
+                            retried,
+                            signer,
+                            parties,
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn finish_sign(
+        &mut self,
+        ceremony: &Ceremony,
+        outcome: Timed<SigningResult>,
+        signer: u16,
+        parties: Vec<u16>,
+    ) {
+        let progress = &ceremony.progress;
+        match outcome {
+            Ok(Ok(ret)) => {
+                self.sign_state.psbt = TextArea::new(Vec::new());
+                self.sign_state.psbt_error = None;
+                // Logged before broadcasting, the signing happened whether or
+                // not the broadcast succeeds.
+                let record = SigningRecord::now(
+                    signer,
+                    parties,
+                    ret.address.clone(),
+                    ret.signined_tx.as_deref().and_then(raw_txid),
+                );
+                if let Err(e) = append_record(&history_path(), &record) {
+                    self.prompt = Some(Prompt::error(
+                        "Signing history not saved",
+                        describe_chain(&e),
+                    ));
+                }
+
+                if ret.signined_tx.is_some() && ret.dry_run {
+                    self.sign_state.broadcast_status = Some(DRY_RUN_STATUS.into());
+                } else if let Some(tx) = ret.signined_tx.clone() {
+                    let txid = record.txid.clone().unwrap_or_default();
+                    self.start_broadcast(tx, txid);
+                }
+                let title = match ret.dry_run {
+                    true => "Signing complete (dry run)",
+                    false => "Signing complete",
+                };
+                self.show_result(ResultView::signing(
+                    title,
+                    &ret,
+                    &[
+                        (BROADCAST_FIELD, &self.sign_state.broadcast_status),
+                        (HOOK_FIELD, &self.sign_state.hook_status),
+                    ],
+                ));
+                self.last_result = Some(Ok(ret));
+            }
+            Ok(Err(e)) => {
+                self.last_result = Some(Err(describe_chain(&e)));
+                self.sign_state.attempt = self.sign_state.attempt.wrapping_add(1);
+                self.prompt = Some(Prompt::failed("Signing failed", &e, &progress.get()));
+            }
+            Err(_) => {
+                self.last_result = Some(Err(format!("timed out, {}", progress.get())));
+                self.sign_state.attempt = self.sign_state.attempt.wrapping_add(1);
+                self.prompt = Some(Prompt::timed_out(ceremony.timeout, &progress.get()));
+            }
+        }
+    }
+
+    fn derive_address(&mut self) {
+        let data_to_sign = self.get_address_state.digest.lines().join("");
+
+        let (address, relay_options) = match self.relay() {
+            Some(relay) => relay,
+            None => return,
+        };
+        let parties = match self.address_parties() {
+            Ok(parties) => parties,
+            Err(e) => {
+                self.prompt = Some(Prompt::error("Invalid signers", e.to_string()));
+                return;
+            }
+        };
+        let config = SigningConfig {
+            room: attempt_room("default-get_key", self.get_address_state.attempt),
+            address,
+            relay_options,
+            parties,
+            transaction: false,
+            local_share: self.address_share(),
+            data_to_sign,
+            idx: self.get_address_state.participant_index as u16,
+            address_type: AddressType::P2pkh,
+            network: self.config.network,
+            hash_scheme: HashScheme::RawDigest,
+            sighash_type: EcdsaSighashType::All,
+            progress: ProgressHandle::default(),
+            save_offline_stage: None,
+            resume_offline_stage: None,
+            inputs: InputSelection::default(),
+            passphrase: self.share_passphrase.clone(),
+            script_multisig: false,
+            timeouts: self.config.phase_timeouts(),
+            dry_run: false,
+            rbf: false,
+            bip322: false,
+        };
+        let progress = config.progress.clone();
+        let ceremony_timeout = self.config.signing_timeout();
+        self.config.participant_index = self.get_address_state.participant_index;
+
+        self.start_ceremony(
+            "Get Address",
+            ceremony_timeout,
+            progress,
+            move || async move {
+                Finished::Address(timeout(ceremony_timeout, do_sign(config).err_into()).await)
+            },
+        );
+    }
+
+    fn finish_address(&mut self, ceremony: &Ceremony, outcome: Timed<SigningResult>) {
+        let progress = &ceremony.progress;
+        match outcome {
+            Ok(Ok(ret)) => {
+                self.get_address_state.address = Some(ret.address.clone());
+                self.show_result(ResultView::signing("Address derived", &ret, &[]));
+                self.last_result = Some(Ok(ret));
+                self.refresh_utxos();
+            }
+            Ok(Err(e)) => {
+                self.last_result = Some(Err(describe_chain(&e)));
+                self.get_address_state.attempt = self.get_address_state.attempt.wrapping_add(1);
+                self.prompt = Some(Prompt::failed("Get Address failed", &e, &progress.get()));
+            }
+            Err(_) => {
+                self.last_result = Some(Err(format!("timed out, {}", progress.get())));
+                self.get_address_state.attempt = self.get_address_state.attempt.wrapping_add(1);
+                self.prompt = Some(Prompt::timed_out(ceremony.timeout, &progress.get()));
+            }
+        }
+    }
+
+    fn finish_keygen(&mut self, ceremony: &Ceremony, outcome: Timed<KeygenResult>) {
+        self.create_state.status = match outcome {
+            Ok(Ok(result)) => {
+                self.show_result(ResultView::keygen(&result));
+                Some(format!("Share saved: {}", result.address))
+            }
+            Ok(Err(e)) => {
+                self.prompt = Some(Prompt::failed(
+                    "Keygen failed",
+                    &e,
+                    &ceremony.progress.get(),
+                ));
+                None
+            }
+            Err(_) => {
+                self.prompt = Some(Prompt::error(
+                    "Keygen timed out",
+                    format!(
+                        "The ceremony did not complete in time.\nLast activity: {}",
+                        ceremony.progress.get()
+                    ),
+                ));
+                None
+            }
+        };
+    }
+
+    /// Runs the ceremony `run` makes on a background thread, the UI keeps
+    /// redrawing its progress until `poll_ceremony` receives the outcome.
+    fn start_ceremony<F>(
+        &mut self,
+        title: &'static str,
+        timeout: Duration,
+        progress: ProgressHandle,
+        run: impl FnOnce() -> F + Send + 'static,
+    ) where
+        F: Future<Output = Finished>,
+    {
+        let (tx, rx) = mpsc::channel();
+        let (cancel, cancelled) = oneshot::channel();
+        thread::spawn(move || {
+            let _rt = tokio::runtime::Runtime::new().unwrap();
+            // Dropping a cancelled ceremony closes its relay connections.
+            let finished = _rt.block_on(async {
+                tokio::select! {
+                    finished = run() => Some(finished),
+                    _ = cancelled => None,
+                }
+            });
+            if let Some(finished) = finished {
+                let _ = tx.send(finished);
+            }
+        });
+        self.ceremony = Some(Ceremony {
+            title,
+            started: Instant::now(),
+            timeout,
+            progress,
+            rx,
+            cancel,
+            events: None,
+            messages: 0,
+        });
+    }
+
+    /// Aborts the running ceremony and goes back to the menu. The room may
+    /// hold its messages, so the next attempt uses a fresh one.
+    fn cancel_ceremony(&mut self) {
+        let ceremony = match self.ceremony.take() {
+            Some(ceremony) => ceremony,
+            None => return,
+        };
+        let _ = ceremony.cancel.send(());
+        match self.mode {
+            AppMode::Sign => self.sign_state.attempt = self.sign_state.attempt.wrapping_add(1),
+            AppMode::GetAddress => {
+                self.get_address_state.attempt = self.get_address_state.attempt.wrapping_add(1)
+            }
             _ => {}
         }
+        self.last_result = Some(Err(format!("cancelled, {}", ceremony.progress.get())));
+        self.mode = AppMode::Menu;
     }
 
-    fn handle_create_input(&mut self, key_event: crossterm::event::KeyEvent) {
-        match key_event.code {
-            crossterm::event::KeyCode::Esc => self.mode = AppMode::Menu,
-            crossterm::event::KeyCode::Up => {
-                if self.create_state.selected_field > 0 {
-                    self.create_state.selected_field -= 1;
+    fn poll_ceremony(&mut self) {
+        let mut ceremony = match self.ceremony.take() {
+            Some(ceremony) => ceremony,
+            None => return,
+        };
+        if let Some(events) = &mut ceremony.events {
+            while let Ok(event) = events.try_recv() {
+                if let KeygenProgress::MessageReceived { .. } = event {
+                    ceremony.messages += 1;
                 }
             }
-            crossterm::event::KeyCode::Down => {
-                if self.create_state.selected_field < 2 {
-                    self.create_state.selected_field += 1;
-                }
+        }
+        match ceremony.rx.try_recv() {
+            Ok(Finished::Keygen(outcome)) => self.finish_keygen(&ceremony, outcome),
+            Ok(Finished::Sign {
+                outcome,
+                retried,
+                signer,
+                parties,
+            }) => {
+                // Retried timeouts moved on to the next room on their own.
+                self.sign_state.attempt = self.sign_state.attempt.wrapping_add(retried);
+                self.finish_sign(&ceremony, outcome, signer, parties);
             }
-            crossterm::event::KeyCode::Left => match self.create_state.selected_field {
-                0 => self.create_state.threshold = self.create_state.threshold.saturating_sub(1),
-                1 => {
-                    self.create_state.number_of_parties =
-                        self.create_state.number_of_parties.saturating_sub(1)
-                }
-                2 => {
-                    self.create_state.participant_index =
-                        self.create_state.participant_index.saturating_sub(1)
-                }
-                _ => {}
-            },
-            crossterm::event::KeyCode::Right => match self.create_state.selected_field {
-                0 => self.create_state.threshold = self.create_state.threshold.saturating_add(1),
-                1 => {
-                    self.create_state.number_of_parties =
-                        self.create_state.number_of_parties.saturating_add(1)
-                }
-                2 => {
-                    self.create_state.participant_index =
-                        self.create_state.participant_index.saturating_add(1)
-                }
-                _ => {}
-            },
-            crossterm::event::KeyCode::Enter => {
-                let config = KeygenConfig {
-                    output: format!("local-share{}.json", self.create_state.participant_index)
-                        .into(),
-                    address: "http://127.0.0.1:8000".parse().unwrap(),
-                    room: "default-keygen".into(),
-                    index: self.create_state.participant_index as u16,
-                    threshold: self.create_state.threshold as u16,
-                    number_of_parties: self.create_state.number_of_parties as u16,
-                };
+            Ok(Finished::Address(outcome)) => self.finish_address(&ceremony, outcome),
+            Err(TryRecvError::Empty) => self.ceremony = Some(ceremony),
+            // The thread panicked.
+            Err(TryRecvError::Disconnected) => {
+                self.prompt = Some(Prompt::error(
+                    &format!("{} failed", ceremony.title),
+                    "The ceremony stopped without a result.".into(),
+                ))
+            }
+        }
+    }
+
+    /// Derives the address again from the share on disk, no ceremony needed,
+    /// in case the share was replaced, and looks up its balance.
+    fn refresh_address(&mut self) {
+        let network = self.config.network;
+        let share = self.address_share();
+        let address = self
+            .shares
+            .inspect(&share, self.share_passphrase.as_deref())
+            .and_then(|info| {
+                info.require_network(&share, network)?;
+                AddressType::P2pkh
+                    .deriver()
+                    .derive(&info.public_key, network)
+            });
+        match address {
+            Ok(address) => {
+                self.get_address_state.address = Some(address.to_string());
+                self.refresh_utxos();
+            }
+            Err(e) => {
+                self.prompt = Some(Prompt::error("Cannot refresh address", describe_chain(&e)))
+            }
+        }
+    }
 
-                let _rt = tokio::runtime::Runtime::new().unwrap();
-                let ret = _rt
-                    .block_on(async { timeout(Duration::from_secs(30), do_keygen(config)).await });
+    /// Starts looking up the UTXOs of the displayed address on a background
+    /// thread, `poll_utxos` picks up the result.
+    fn refresh_utxos(&mut self) {
+        let network = self.config.network;
+        let address = match self
+            .get_address_state
+            .address
+            .as_deref()
+            .map(|address| parse_destination(address, network))
+        {
+            Some(Ok(address)) => address,
+            _ => return,
+        };
+        let (tx, rx) = mpsc::channel();
+        let esplora = self.config.esplora_url();
+        thread::spawn(move || {
+            let utxos = esplora
+                .and_then(|esplora| block_on(fetch_utxos(&esplora, &address)))
+                .map_err(|e| format!("{:#}", e));
+            let _ = tx.send(utxos);
+        });
+        self.get_address_state.utxos = None;
+        self.get_address_state.utxos_rx = Some(rx);
+    }
 
-                std::fs::write("ms.json", format!("{ret:?}")).unwrap();
+    /// Broadcasts the signed `tx`, then runs the post-sign command with it, on
+    /// a background thread so the UI keeps drawing through the retries and
+    /// the command's timeout.
+    fn start_broadcast(&mut self, tx: String, txid: String) {
+        let (events, rx) = mpsc::channel();
+        let broadcaster = self.config.broadcaster();
+        let broadcast_config = self.config.broadcast_config();
+        let command = self.config.post_sign_command.clone();
+        thread::spawn(move || {
+            let _rt = tokio::runtime::Runtime::new().unwrap();
+            let broadcast = broadcaster.and_then(|broadcaster| {
+                _rt.block_on(broadcast_raw_transaction(
+                    &tx,
+                    &broadcaster,
+                    &broadcast_config,
+                    |attempt| {
+                        let _ = events.send(BroadcastEvent::Retry(attempt));
+                    },
+                ))
+            });
+            let _ = events.send(BroadcastEvent::Broadcast(
+                broadcast.map_err(|e| e.to_string()),
+            ));
+            if let Some(command) = command {
+                let summary = match run_post_sign(&command, &txid, &tx, HOOK_TIMEOUT) {
+                    Ok(output) => output.summary(),
+                    Err(e) => describe_chain(&e),
+                };
+                let _ = events.send(BroadcastEvent::Hook(summary));
             }
-            _ => {}
+        });
+        self.sign_state.broadcast_status = Some("broadcasting".into());
+        if self.config.post_sign_command.is_some() {
+            self.sign_state.hook_status = Some("waiting for the broadcast".into());
         }
+        self.sign_state.broadcast_rx = Some(rx);
     }
 
-    fn handle_sign_input(&mut self, key_event: crossterm::event::KeyEvent) {
-        match key_event.code {
-            crossterm::event::KeyCode::Esc => self.mode = AppMode::Menu,
-            crossterm::event::KeyCode::Up | crossterm::event::KeyCode::Down => {
-                self.sign_state.selected_field = (self.sign_state.selected_field + 1) % 2;
+    fn poll_broadcast(&mut self) {
+        let received = match &self.sign_state.broadcast_rx {
+            Some(rx) => rx.try_recv(),
+            None => return,
+        };
+        let (label, status) = match received {
+            Ok(BroadcastEvent::Retry(attempt)) => {
+                self.sign_state.broadcast_retries = attempt;
+                let retries = self.config.broadcast_config().retries;
+                let status = format!("no answer, retry {} of {}", attempt, retries);
+                (BROADCAST_FIELD, status)
             }
-            crossterm::event::KeyCode::Enter => {
-                let sha256 = |data: &str| -> String {
-                    let mut hasher = sha2::Sha256::new();
-                    hasher.update(data.as_bytes());
-                    let result = hasher.finalize();
-                    hex::encode(result)
+            Ok(BroadcastEvent::Broadcast(broadcast)) => {
+                let retried = match self.sign_state.broadcast_retries {
+                    0 => String::new(),
+                    attempts => format!(" (retried {} times)", attempts),
                 };
-
-                let _rt = tokio::runtime::Runtime::new().unwrap();
-
-                if self.sign_state.selected_field == 1 {
-                    let data_to_sign = self.sign_state.psbt.lines().join("\n");
-                    for i in 0..10 {
-                        let room = format!("default-signing{}{}", i, sha256(&data_to_sign.clone()));
-
-                        let config = SigningConfig {
-                            room,
-                            address: "http://127.0.0.1:8000".parse().unwrap(),
-                            parties: vec![1, 2],
-                            transaction: true,
-                            local_share: format!(
-                                "local-share{}.json",
-                                self.sign_state.participant_index
-                            )
-                            .into(),
-                            data_to_sign: data_to_sign.clone(),
-                            idx: self.sign_state.participant_index as u16,
-                        };
-
-                        self.sign_state.psbt = TextArea::new(Vec::new());
-
-                        match _rt.block_on(async {
-                            timeout(Duration::from_secs(30), do_sign(config)).await
-                        }) {
-                            Ok(Ok(ret)) => {
-                                ret.signined_tx.clone().map(Self::broadcast_raw_transaction);
-                                std::fs::write("output.raw", format!("{:?}", ret));
-                                break;
+                let status = match broadcast {
+                    Ok(txid) => {
+                        if let Some(Ok(ret)) = &mut self.last_result {
+                            ret.broadcast_txid = Some(txid.to_string());
+                        }
+                        // Asking esplora would give away what our own node
+                        // was there to keep private.
+                        if self.config.broadcast_backend == BroadcastBackend::Esplora {
+                            self.watch_confirmation(txid);
+                            let shown = self
+                                .result_view
+                                .fields
+                                .iter()
+                                .any(|(label, _)| *label == BROADCAST_FIELD);
+                            if let (true, Some(status)) =
+                                (shown, &self.sign_state.confirmation_status)
+                            {
+                                self.result_view
+                                    .fields
+                                    .push((CONFIRMATION_FIELD, status.clone()));
                             }
-                            Ok(Err(e)) => std::fs::write("error.raw", format!("{:?}", e)).unwrap(),
-                            Err(e) => std::fs::write("error.raw", format!("{:?}", e)).unwrap(),
                         }
+                        format!("Broadcast{}: {}", retried, txid)
                     }
-                }
+                    Err(e) => format!("Broadcast failed{}: {}", retried, e),
+                };
+                (BROADCAST_FIELD, status)
             }
-            _ => {
-                if self.sign_state.selected_field == 0 {
-                    match key_event.code {
-                        crossterm::event::KeyCode::Left => {
-                            self.sign_state.participant_index =
-                                self.sign_state.participant_index.saturating_sub(1)
-                        }
-                        crossterm::event::KeyCode::Right => {
-                            self.sign_state.participant_index =
-                                self.sign_state.participant_index.saturating_add(1)
-                        }
-                        _ => {}
-                    }
-                } else {
-                    self.sign_state.psbt.input(key_event);
-                }
+            Ok(BroadcastEvent::Hook(summary)) => (HOOK_FIELD, summary),
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                self.sign_state.broadcast_rx = None;
+                return;
+            }
+        };
+        for (field, value) in &mut self.result_view.fields {
+            if *field == label {
+                *value = status.clone();
+            }
+        }
+        match label {
+            HOOK_FIELD => self.sign_state.hook_status = Some(status),
+            _ => self.sign_state.broadcast_status = Some(status),
+        }
+    }
+
+    /// Starts following `txid` on esplora on a background thread,
+    /// `poll_confirmation` picks up each step.
+    fn watch_confirmation(&mut self, txid: bitcoin::Txid) {
+        let (tx, rx) = mpsc::channel();
+        let esplora = self.config.esplora_url();
+        thread::spawn(move || {
+            let _rt = tokio::runtime::Runtime::new().unwrap();
+            let watched = esplora.and_then(|esplora| {
+                _rt.block_on(wait_for_confirmation(
+                    &esplora,
+                    &txid,
+                    WATCHED_CONFIRMATIONS,
+                    |progress| {
+                        let _ = tx.send(Ok(progress));
+                    },
+                ))
+            });
+            if let Err(e) = watched {
+                let _ = tx.send(Err(format!("{:#}", e)));
+            }
+        });
+        self.sign_state.confirmation_status = Some("waiting for esplora to see it".into());
+        self.sign_state.confirmation_rx = Some(rx);
+    }
+
+    fn poll_confirmation(&mut self) {
+        let received = match &self.sign_state.confirmation_rx {
+            Some(rx) => rx.try_recv(),
+            None => return,
+        };
+        let status = match received {
+            Ok(Ok(progress)) => progress.to_string(),
+            Ok(Err(e)) => e,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                self.sign_state.confirmation_rx = None;
+                return;
+            }
+        };
+        for (label, value) in &mut self.result_view.fields {
+            if *label == CONFIRMATION_FIELD {
+                *value = status.clone();
             }
         }
+        self.sign_state.confirmation_status = Some(status);
     }
 
-    async fn broadcast_raw_transaction(tx: String) -> anyhow::Result<String> {
-        let client = reqwest::Client::new();
-        let mut res = client
-            .post("https://mempool.space/api/tx")
-            .body(tx.to_string())
-            .send()?;
+    fn poll_utxos(&mut self) {
+        let received = match &self.get_address_state.utxos_rx {
+            Some(rx) => rx.try_recv(),
+            None => return,
+        };
+        match received {
+            Ok(utxos) => {
+                self.get_address_state.utxos = Some(utxos);
+                self.get_address_state.utxos_rx = None;
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.get_address_state.utxos_rx = None,
+        }
+    }
 
-        Ok(res.text()?)
+    fn handle_prompt_input(&mut self, key_event: crossterm::event::KeyEvent) {
+        let kind = match &self.prompt {
+            Some(prompt) => prompt.kind,
+            None => return,
+        };
+        match (kind, key_event.code) {
+            (PromptKind::RetryAbort, crossterm::event::KeyCode::Char('r'))
+            | (PromptKind::Confirm, crossterm::event::KeyCode::Char('y')) => {
+                self.prompt = None;
+                match self.mode {
+                    AppMode::Sign => self.sign_psbt(),
+                    AppMode::GetAddress => self.derive_address(),
+                    AppMode::Send => self.sign_spend(),
+                    AppMode::Sweep => self.sign_sweep(),
+                    AppMode::Create => self.back_up_and_keygen(),
+                    _ => {}
+                }
+            }
+            (PromptKind::RetryAbort, crossterm::event::KeyCode::Char('a'))
+            | (PromptKind::Confirm, crossterm::event::KeyCode::Char('n'))
+            | (_, crossterm::event::KeyCode::Esc)
+            | (PromptKind::Error, crossterm::event::KeyCode::Enter)
+            | (PromptKind::Info, crossterm::event::KeyCode::Enter) => self.prompt = None,
+            _ => {}
+        }
     }
 
     fn handle_get_address_input(&mut self, key_event: crossterm::event::KeyEvent) {
         match key_event.code {
             crossterm::event::KeyCode::Esc => self.mode = AppMode::Menu,
+            crossterm::event::KeyCode::F(5) => self.refresh_address(),
+            crossterm::event::KeyCode::F(2) => self.open_share_picker(AppMode::GetAddress),
+            crossterm::event::KeyCode::F(3) => {
+                if let Some(address) = self.get_address_state.address.clone() {
+                    self.show_qr("Address", &address);
+                }
+            }
+            crossterm::event::KeyCode::F(4) => {
+                if let Some(address) = self.get_address_state.address.clone() {
+                    self.copy_text("Address", &address);
+                }
+            }
             crossterm::event::KeyCode::Up => {
                 if self.get_address_state.selected_field > 0 {
                     self.get_address_state.selected_field -= 1;
                 }
             }
             crossterm::event::KeyCode::Down => {
-                if self.get_address_state.selected_field < 1 {
+                if self.get_address_state.selected_field < 4 {
                     self.get_address_state.selected_field += 1;
                 }
             }
             crossterm::event::KeyCode::Enter => {
                 match self.get_address_state.selected_field {
-                    0 => {
-                        // Handle Participant Index input (if needed)
+                    2 => {
+                        // Accept the suggested list, with our own index added.
+                        if let Err(BoomerError::SignerNotInParties { suggestion, .. }) =
+                            self.address_parties()
+                        {
+                            self.get_address_state.parties = parties_text(&suggestion);
+                        }
                     }
-                    1 => {
-                        // Handle OK button press
-                        let data_to_sign =
-                            "fdd4d9893b23aa6cdb357e1606907c6909a1231595549e698f779a141d4534c7"
-                                .to_string();
-
-                        let _rt = tokio::runtime::Runtime::new().unwrap();
-                        for i in 0..10 {
-                            let room = format!("default-get_key{}", i);
-                            let config = SigningConfig {
-                                room,
-                                address: "http://127.0.0.1:8000".parse().unwrap(),
-                                parties: vec![1, 2],
-                                transaction: false,
-                                local_share: format!(
-                                    "local-share{}.json",
-                                    self.get_address_state.participant_index
-                                )
-                                .into(),
-                                data_to_sign: data_to_sign.clone(),
-                                idx: self.get_address_state.participant_index as u16,
-                            };
-
-                            match _rt.block_on(async {
-                                timeout(Duration::from_secs(30), do_sign(config.clone())).await
-                            }) {
-                                Ok(Ok(ret)) => {
-                                    std::fs::write("address.raw", format!("{:?}", ret)).unwrap();
-                                    self.get_address_state.address = Some(ret.address);
-                                    break;
-                                }
-                                Ok(Err(e)) => {
-                                    std::fs::write("error.raw", format!("{:?}", e)).unwrap()
-                                }
-                                Err(e) => std::fs::write("error.raw", format!("{:?}", e)).unwrap(),
-                            }
+                    4 => {
+                        // Checked before anything touches the network.
+                        if let Err(e) = self.address_parties() {
+                            self.prompt = Some(Prompt::error("Invalid signers", e.to_string()));
+                            return;
+                        }
+                        let digest = self.get_address_state.digest.lines().join("");
+                        if let Err(e) = message_digest(&digest, HashScheme::RawDigest) {
+                            self.prompt = Some(Prompt::error("Invalid digest", describe_chain(&e)));
+                            return;
                         }
+                        let share = self.address_share();
+                        let warning = self
+                            .shares
+                            .inspect(&share, self.share_passphrase.as_deref())
+                            .ok()
+                            .filter(|info| is_legacy_only(&info.public_key, AddressType::P2pkh))
+                            .map(|_| LEGACY_ONLY_WARNING);
+                        self.prompt = Some(Prompt::confirm_message(
+                            &digest,
+                            HashScheme::RawDigest,
+                            warning,
+                        ));
                     }
                     _ => {}
                 }
             }
             _ => {
                 if self.get_address_state.selected_field == 0 {
-                    match key_event.code {
-                        crossterm::event::KeyCode::Left => {
-                            self.get_address_state.participant_index =
-                                self.get_address_state.participant_index.saturating_sub(1)
-                        }
-                        crossterm::event::KeyCode::Right => {
-                            self.get_address_state.participant_index =
-                                self.get_address_state.participant_index.saturating_add(1)
-                        }
-                        _ => {}
-                    }
+                    edit_number(
+                        &mut self.get_address_state.participant_index,
+                        key_event.code,
+                    );
+                    self.get_address_state.share_file = None;
+                    self.refresh_address_share_info();
+                } else if self.get_address_state.selected_field == 1 {
+                    edit_number(&mut self.get_address_state.attempt, key_event.code);
+                } else if self.get_address_state.selected_field == 2 {
+                    self.get_address_state.parties.input(key_event);
+                } else if self.get_address_state.selected_field == 3 {
+                    self.get_address_state.digest.input(key_event);
                 }
             }
         }
@@ -651,31 +3918,267 @@ impl App {
     }
 }
 
+/// Who is in the lobby, with a mark per party of the group.
+fn lobby_line(spinner: char, present: &[u16], expected: &[u16], parties: u16) -> Line<'static> {
+    let mut spans = vec![Span::from(format!("{} Waiting in the lobby:", spinner))];
+    for party in 1..=parties {
+        let mark = lobby_mark(party, present, expected);
+        let style = match mark {
+            '✓' => Style::default().green(),
+            '…' => Style::default().yellow(),
+            _ => Style::default().dim(),
+        };
+        spans.push(Span::from(format!(" party {} ", party)));
+        spans.push(Span::styled(mark.to_string(), style));
+    }
+    Line::from(spans)
+}
+
+/// The UTXOs of the Get Address screen's address, unconfirmed ones dimmed.
+fn utxo_list(utxos: &[Utxo]) -> List<'static> {
+    let items: Vec<ListItem> = utxos
+        .iter()
+        .map(|utxo| {
+            let status = match utxo.status.block_height {
+                Some(height) => format!("block {}", height),
+                None => "unconfirmed".into(),
+            };
+            let item = ListItem::new(format!(
+                " {}:{}  {} sat  {}",
+                utxo.txid, utxo.vout, utxo.value, status
+            ));
+            match utxo.status.confirmed {
+                true => item,
+                false => item.dark_gray(),
+            }
+        })
+        .collect();
+    List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("UTXOs ({})", utxos.len())),
+    )
+}
+
+/// Runs `work` on a thread of its own, the receiver gets its outcome with the
+/// error chain spelled out for a prompt.
+fn in_background<T: Send + 'static>(
+    work: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+) -> Receiver<Result<T, String>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(work().map_err(|e| describe_chain(&e)));
+    });
+    rx
+}
+
+/// The parts pasted on the QR import screen, or read from the file it names,
+/// one per line.
+fn scanned_parts(input: &str) -> anyhow::Result<Vec<String>> {
+    let text = match Path::new(input).is_file() {
+        true => std::fs::read_to_string(input).context(format!("cannot read {}", input))?,
+        false => input.to_string(),
+    };
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn parse_fee_rate(text: &TextArea) -> anyhow::Result<u64> {
+    bs_fee::parse_fee_rate(&text.lines().join(""))
+}
+
+/// The fee in sats and, when the signed size is known, in sat/vB.
+fn fee_text(fee: &FeeInfo) -> String {
+    match (fee.fee_rate(), fee.vsize) {
+        (Some(rate), Some(vsize)) => format!(
+            "{} sats, {:.1} sat/vB for about {} vB signed",
+            fee.fee, rate, vsize
+        ),
+        _ => format!("{} sats", fee.fee),
+    }
+}
+
+fn parties_text(parties: &[u16]) -> TextArea<'static> {
+    let text = parties
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    TextArea::new(vec![text])
+}
+
+/// The comma separated signers in `parties`. They must include our own index
+/// and, once the share could be read, be enough of its parties to sign.
+fn signer_set(
+    parties: &TextArea,
+    index: u8,
+    share: &Path,
+    share_info: Option<&ShareInfo>,
+) -> Result<Vec<u16>, BoomerError> {
+    if let Some(info) = share_info {
+        check_share_index(share, info.index, u16::from(index))?;
+    }
+    let parties = parse_parties(&parties.lines().join(","))?;
+    check_parties(&parties, u16::from(index))?;
+    if let Some(info) = share_info {
+        check_signer_count(&parties, info.threshold, info.parties)?;
+    }
+    Ok(parties)
+}
+
+/// Edits a numeric field: the arrows nudge it, digits are appended and
+/// Backspace drops the last one. Typed values are clamped to the `u8` range,
+/// other keys are ignored.
+fn edit_number(value: &mut u8, key: crossterm::event::KeyCode) {
+    *value = match key {
+        crossterm::event::KeyCode::Left => value.saturating_sub(1),
+        crossterm::event::KeyCode::Right => value.saturating_add(1),
+        crossterm::event::KeyCode::Backspace => *value / 10,
+        crossterm::event::KeyCode::Char(digit @ '0'..='9') => {
+            let typed = u32::from(*value) * 10 + digit.to_digit(10).unwrap_or(0);
+            typed.min(u32::from(u8::MAX)) as u8
+        }
+        _ => *value,
+    };
+}
+
+fn share_file_title(share_file: &Option<PathBuf>) -> String {
+    match share_file {
+        Some(path) => format!("Participant Index (from {})", path.display()),
+        None => "Participant Index".into(),
+    }
+}
+
+/// What the share picker lists in `dir`: its parent, subdirectories and
+/// `.json` files, each group sorted by name.
+fn share_entries(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut dirs = Vec::new();
+    let mut shares = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if path.is_dir() {
+            dirs.push((format!("{}/", name), path));
+        } else if path.extension().map_or(false, |ext| ext == "json") {
+            shares.push((name, path));
+        }
+    }
+    dirs.sort();
+    shares.sort();
+
+    let mut entries = Vec::new();
+    if let Some(parent) = dir.canonicalize()?.parent() {
+        entries.push(("../".into(), parent.to_path_buf()));
+    }
+    entries.extend(dirs);
+    entries.extend(shares);
+    Ok(entries)
+}
+
+fn render_last_error(frame: &mut Frame, error: &str, area: Rect) {
+    frame.render_widget(
+        Paragraph::new(error).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::TOP)
+                .title("Last Error")
+                .style(Style::default().fg(Color::Red)),
+        ),
+        area,
+    );
+}
+
 fn main() -> io::Result<()> {
+    let cli = bs_cli::Cli::from_args();
+    if let Err(e) = bs_log::init_logging(&data_dir()) {
+        bs_cli::exit_with_error(e);
+    }
+    if let Some(command) = cli.command {
+        if let Err(e) = bs_cli::run(command, cli.insecure) {
+            bs_cli::exit_with_error(e);
+        }
+        return Ok(());
+    }
+
     let _rt = tokio::runtime::Runtime::new().unwrap();
 
+    // Bootstrap before entering the alternate screen so the error stays visible.
+    if let Err(e) = ensure_data_dir(&data_dir()) {
+        bs_cli::exit_with_error(e);
+    }
+    // A broken config shouldn't lock users out, they can fix it in Settings.
+    let mut config = load_config(&config_path()).unwrap_or_default();
+    config.relay_insecure = cli.insecure;
+    if let Some(dir) = &config.share_dir {
+        if let Err(e) = ensure_data_dir(dir) {
+            bs_cli::exit_with_error(e);
+        }
+    }
+
+    let mut app = App::from_config(config);
+    app.sign_state
+        .psbt
+        .set_placeholder_text("Enter PSBT here...");
+    app.sign_state
+        .input_values
+        .set_placeholder_text("e.g. 0=50000, 1=1200");
+    install_panic_hook();
+    let res = enter_terminal().and_then(|mut terminal| {
+        if std::env::var_os(TEST_PANIC_ENV).is_some() {
+            panic!("{} is set", TEST_PANIC_ENV);
+        }
+        app.run(&mut terminal)
+    });
+    // Also when setting up or running the UI failed, or the shell is left raw.
+    let restored = restore_terminal();
+
+    // Outside the alternate screen, so a failure is still visible.
+    if let Err(e) = save_config(&config_path(), &app.config) {
+        eprintln!("{:#}", e);
+    }
+    res.and(restored)
+}
+
+fn enter_terminal() -> io::Result<ratatui::Terminal<ratatui::backend::CrosstermBackend<io::Stdout>>>
+{
     crossterm::execute!(
         std::io::stdout(),
         crossterm::terminal::EnterAlternateScreen,
         crossterm::event::EnableMouseCapture
     )?;
-    let mut terminal =
-        ratatui::Terminal::new(ratatui::backend::CrosstermBackend::new(std::io::stdout()))?;
     crossterm::terminal::enable_raw_mode()?;
+    ratatui::Terminal::new(ratatui::backend::CrosstermBackend::new(std::io::stdout()))
+}
 
-    let mut app = App::default();
-    app.sign_state
-        .psbt
-        .set_placeholder_text("Enter PSBT here...");
-    let res = app.run(&mut terminal);
+/// Restores the terminal before the panic message is printed, or it would
+/// land in the alternate screen and leave the shell raw. Only for the UI
+/// thread: a ceremony thread panicking is reported by `poll_ceremony` while
+/// the UI carries on.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") {
+            let _ = restore_terminal();
+        }
+        default_hook(info);
+    }));
+}
 
-    crossterm::terminal::disable_raw_mode()?;
-    crossterm::execute!(
+/// Undoes `enter_terminal`, however far it got.
+fn restore_terminal() -> io::Result<()> {
+    let raw_mode = crossterm::terminal::disable_raw_mode();
+    let screen = crossterm::execute!(
         std::io::stdout(),
         crossterm::terminal::LeaveAlternateScreen,
         crossterm::event::DisableMouseCapture
-    )?;
-    Ok(())
+    );
+    raw_mode.and(screen)
 }
 
 // assure that our app, in different ways, runs in a safe way. Warn user when some are
@@ -713,4 +4216,380 @@ mod tests {
         terminal.draw(|frame| app.draw(frame))?;
         Ok(())
     }
+
+    #[test]
+    fn numeric_fields_accept_typed_digits() {
+        use crossterm::event::KeyCode;
+
+        let mut value = 1;
+        edit_number(&mut value, KeyCode::Char('5'));
+        assert_eq!(value, 15);
+        edit_number(&mut value, KeyCode::Char('x'));
+        edit_number(&mut value, KeyCode::Right);
+        assert_eq!(value, 16);
+        edit_number(&mut value, KeyCode::Char('9'));
+        assert_eq!(value, u8::MAX);
+        edit_number(&mut value, KeyCode::Backspace);
+        edit_number(&mut value, KeyCode::Backspace);
+        assert_eq!(value, 2);
+    }
+
+    #[test]
+    fn menu_selection_wraps_around() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let mut app = App::default();
+        app.handle_key_event(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+        assert_eq!(app.menu_state.selected, MENU_ITEMS.len() - 1);
+        app.handle_key_event(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE));
+        assert_eq!(app.menu_state.selected, 0);
+        app.handle_key_event(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE));
+        assert_eq!(app.menu_state.selected, 1);
+
+        // Moving through the Create form leaves the menu where it was.
+        app.handle_key_event(KeyEvent::new(KeyCode::Up, KeyModifiers::NONE));
+        app.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(app.mode, AppMode::Create);
+        app.handle_key_event(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE));
+        app.handle_key_event(KeyEvent::new(KeyCode::Down, KeyModifiers::NONE));
+        app.handle_key_event(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(app.create_state.selected_field, 2);
+        assert_eq!(app.menu_state.selected, 0);
+    }
+
+    #[test]
+    fn result_screen_saves_the_export_where_asked() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let mut app = App::default();
+        app.mode = AppMode::Result;
+        let export = Export::SignedTx("0200".into());
+        app.result_view.export = Some(export.clone());
+
+        app.handle_key_event(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::NONE));
+        let default = export.default_path(&data_dir()).display().to_string();
+        assert_eq!(app.export_path.as_ref().unwrap().lines(), [default]);
+
+        let path = std::env::temp_dir().join(format!("boomersig-save-{}.hex", std::process::id()));
+        let mut typed = TextArea::new(vec![path.display().to_string()]);
+        typed.move_cursor(CursorMove::End);
+        app.export_path = Some(typed);
+        app.handle_key_event(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(!app.exit);
+        app.handle_key_event(KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
+        app.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert!(app.export_path.is_none() && app.prompt.is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "0200");
+        assert_eq!(app.mode, AppMode::Result);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn spend_is_confirmed_once_its_utxos_arrive() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let mut app = App::default();
+        app.mode = AppMode::Send;
+        app.send_state.selected_field = 4;
+        let (tx, rx) = mpsc::channel();
+        app.send_state.spend_rx = Some(rx);
+
+        // A second Enter while the lookup runs starts nothing new.
+        app.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        app.poll_spend();
+        assert!(app.prompt.is_none() && app.send_state.spend_rx.is_some());
+
+        tx.send(Err("no UTXOs at the address".into())).unwrap();
+        app.poll_spend();
+        assert!(app.send_state.spend_rx.is_none());
+        let prompt = app.prompt.unwrap();
+        assert_eq!(prompt.title, "Cannot build transaction");
+        assert_eq!(prompt.message, "no UTXOs at the address");
+    }
+
+    #[test]
+    fn sweep_lookup_is_abandoned_on_esc() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let mut app = App::default();
+        app.mode = AppMode::Sweep;
+        let (tx, rx) = mpsc::channel();
+        app.sweep_state.sweep_rx = Some(rx);
+
+        app.handle_key_event(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(tx.send(Err("too late".into())).is_err());
+        app.poll_sweep();
+        assert!(app.prompt.is_none());
+        assert_eq!(app.mode, AppMode::Menu);
+    }
+
+    #[test]
+    fn broadcast_status_follows_each_retry() {
+        use bitcoin::hashes::Hash;
+
+        let mut app = App::default();
+        app.config.broadcast_backend = BroadcastBackend::Bitcoind;
+        app.result_view.fields = vec![
+            (BROADCAST_FIELD, "broadcasting".into()),
+            (HOOK_FIELD, "waiting for the broadcast".into()),
+        ];
+        let (tx, rx) = mpsc::channel();
+        app.sign_state.broadcast_rx = Some(rx);
+
+        tx.send(BroadcastEvent::Retry(1)).unwrap();
+        app.poll_broadcast();
+        assert_eq!(app.result_view.fields[0].1, "no answer, retry 1 of 3");
+        app.poll_broadcast();
+        assert_eq!(app.result_view.fields[0].1, "no answer, retry 1 of 3");
+
+        let txid = bitcoin::Txid::all_zeros();
+        tx.send(BroadcastEvent::Broadcast(Ok(txid))).unwrap();
+        tx.send(BroadcastEvent::Hook("exit 0".into())).unwrap();
+        app.poll_broadcast();
+        assert_eq!(
+            app.sign_state.broadcast_status,
+            Some(format!("Broadcast (retried 1 times): {}", txid))
+        );
+        app.poll_broadcast();
+        assert_eq!(app.result_view.fields[1].1, "exit 0");
+
+        drop(tx);
+        app.poll_broadcast();
+        assert!(app.sign_state.broadcast_rx.is_none());
+        assert_eq!(app.result_view.fields.len(), 2);
+    }
+
+    #[test]
+    fn confirmation_status_follows_the_watcher() {
+        let mut app = App::default();
+        app.result_view.fields = vec![(CONFIRMATION_FIELD, "waiting".into())];
+        let (tx, rx) = mpsc::channel();
+        app.sign_state.confirmation_rx = Some(rx);
+
+        tx.send(Ok(TxProgress::InMempool)).unwrap();
+        tx.send(Ok(TxProgress::Confirmed { confirmations: 2 }))
+            .unwrap();
+        app.poll_confirmation();
+        assert_eq!(app.result_view.fields[0].1, "in mempool");
+        app.poll_confirmation();
+        assert_eq!(app.result_view.fields[0].1, "confirmed (2 blocks)");
+
+        drop(tx);
+        app.poll_confirmation();
+        assert!(app.sign_state.confirmation_rx.is_none());
+        assert_eq!(
+            app.sign_state.confirmation_status.as_deref(),
+            Some("confirmed (2 blocks)")
+        );
+    }
+
+    #[test]
+    fn unparsable_psbt_is_reported_on_the_sign_screen() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let mut app = App::default();
+        app.mode = AppMode::Sign;
+        app.sign_state.participant_index = 1;
+        app.set_sign_parties(&[1, 2]);
+        app.sign_state.selected_field = 3;
+        app.sign_state.psbt = TextArea::new(vec!["cHNidP8BAE ".into(), " not quite".into()]);
+
+        app.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(app.sign_state.data_to_sign, "cHNidP8BAEnotquite");
+        assert!(app.sign_state.psbt_error.is_some());
+        assert_eq!(app.mode, AppMode::Sign);
+        assert!(app.ceremony.is_none() && app.prompt.is_none());
+
+        app.handle_key_event(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE));
+        assert!(app.sign_state.psbt_error.is_none());
+    }
+
+    #[test]
+    fn share_entries_list_directories_before_shares() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("boomersig-picker-{}", std::process::id()));
+        fs::create_dir_all(dir.join("backup"))?;
+        fs::write(dir.join("local-share2.json"), "{}")?;
+        fs::write(dir.join("notes.txt"), "")?;
+
+        let labels: Vec<String> = share_entries(&dir)?
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect();
+        assert_eq!(labels, ["../", "backup/", "local-share2.json"]);
+
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn ceremony_progress_is_drawn_until_it_finishes() -> io::Result<()> {
+        let mut terminal = Terminal::new(TestBackend::new(100, 30))?;
+        let mut app = App::default();
+        let (tx, rx) = mpsc::channel();
+        let (cancel, mut cancelled) = oneshot::channel();
+        let (events, received) = tokio::sync::mpsc::channel(KEYGEN_EVENTS);
+        app.ceremony = Some(Ceremony {
+            title: "Keygen",
+            started: Instant::now(),
+            timeout: Duration::from_secs(30),
+            progress: ProgressHandle::default(),
+            rx,
+            cancel,
+            events: Some(received),
+            messages: 0,
+        });
+
+        for event in [
+            KeygenProgress::RoundStarted(1),
+            KeygenProgress::MessageReceived { from: 2 },
+            KeygenProgress::MessageReceived { from: 3 },
+        ] {
+            events.try_send(event).unwrap();
+        }
+        app.poll_ceremony();
+        terminal.draw(|frame| app.draw(frame))?;
+        assert_eq!(app.ceremony.as_ref().map(|c| c.messages), Some(2));
+
+        // A ceremony thread that died without an outcome is reported.
+        drop(tx);
+        app.poll_ceremony();
+        assert!(app.ceremony.is_none());
+        assert_eq!(
+            app.prompt.take().map(|prompt| prompt.title).as_deref(),
+            Some("Keygen failed")
+        );
+        assert_eq!(cancelled.try_recv(), Err(oneshot::Canceled));
+        Ok(())
+    }
+
+    #[test]
+    fn esc_cancels_the_running_ceremony() {
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+        let mut app = App::default();
+        app.mode = AppMode::Sign;
+        let (_tx, rx) = mpsc::channel();
+        let (cancel, mut cancelled) = oneshot::channel();
+        app.ceremony = Some(Ceremony {
+            title: "Signing",
+            started: Instant::now(),
+            timeout: Duration::from_secs(30),
+            progress: ProgressHandle::default(),
+            rx,
+            cancel,
+            events: None,
+            messages: 0,
+        });
+
+        app.handle_key_event(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(app.ceremony.is_some() && !app.exit);
+
+        // Enter asks a ceremony waiting in the lobby to start without everyone.
+        let progress = app.ceremony.as_ref().unwrap().progress.clone();
+        app.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert!(progress.take_start_now());
+
+        app.handle_key_event(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(app.ceremony.is_none());
+        assert_eq!(app.mode, AppMode::Menu);
+        assert_eq!(app.sign_state.attempt, 1);
+        assert_eq!(cancelled.try_recv(), Ok(Some(())));
+    }
+
+    #[test]
+    fn inspected_shares_are_cached_until_the_file_changes() {
+        let path =
+            std::env::temp_dir().join(format!("boomersig-cache-{}.json", std::process::id()));
+        fs::write(&path, "not a share").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let shares = ShareCache::default();
+        let first = format!("{:#}", shares.inspect(&path, None).unwrap_err());
+
+        let touch = |time: SystemTime| {
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        fs::write(&path, "").unwrap();
+        touch(modified);
+        assert_eq!(
+            format!("{:#}", shares.inspect(&path, None).unwrap_err()),
+            first
+        );
+
+        touch(modified + Duration::from_secs(1));
+        assert_ne!(
+            format!("{:#}", shares.inspect(&path, None).unwrap_err()),
+            first
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn review_shows_the_txid_as_signed_with_rbf() {
+        let psbt = crate::bs_signing::tests::psbt_spending(bitcoin::ScriptBuf::new());
+        let mut app = App::default();
+        let review = app.review(psbt.clone()).unwrap();
+        assert_eq!(review.txid, psbt.unsigned_tx.txid().to_string());
+        assert!(!review.rbf_signaled);
+
+        app.config.rbf = true;
+        let mut signaling = psbt.clone();
+        signal_rbf(&mut signaling).unwrap();
+        let review = app.review(psbt).unwrap();
+        assert_eq!(review.txid, signaling.unsigned_tx.txid().to_string());
+        assert!(review.rbf_signaled);
+    }
+
+    #[test]
+    fn draw_survives_any_terminal_size() -> io::Result<()> {
+        let mut terminal = Terminal::new(TestBackend::new(1, 1))?;
+        let mut app = App::default();
+        app.prompt = Some(Prompt::timed_out(
+            Duration::from_secs(30),
+            &Progress::default(),
+        ));
+        app.sign_state.review = Some(Review {
+            txid: String::new(),
+            outputs: decode_outputs(
+                &crate::bs_signing::tests::psbt_spending(bitcoin::ScriptBuf::new()),
+                None,
+                bitcoin::Network::Signet,
+            ),
+            fee: Err("missing amounts".into()),
+            rbf_signaled: true,
+        });
+        app.qr = Some(QrView {
+            title: "PSBT".into(),
+            parts: qr_parts(&"cHNidP8B".repeat(40)),
+            shown: Instant::now(),
+            back: AppMode::Result,
+        });
+
+        for mode in [
+            AppMode::Menu,
+            AppMode::Create,
+            AppMode::Sign,
+            AppMode::GetAddress,
+            AppMode::Send,
+            AppMode::Sweep,
+            AppMode::History,
+            AppMode::Settings,
+            AppMode::Result,
+            AppMode::SharePicker,
+            AppMode::Review,
+            AppMode::Qr,
+            AppMode::QrImport,
+        ] {
+            app.mode = mode;
+            for (width, height) in [(0, 0), (1, 1), (MIN_WIDTH - 1, 5), (MIN_WIDTH, MIN_HEIGHT)] {
+                terminal.backend_mut().resize(width, height);
+                terminal.autoresize()?;
+                terminal.draw(|frame| app.draw(frame))?;
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,167 @@
+use anyhow::{bail, Context, Result};
+use bitcoin::hashes::Hash;
+use bitcoin::script::Instruction;
+use bitcoin::sighash::SighashCache;
+use bitcoin::{ScriptBuf, Transaction};
+
+use crate::bs_address::AddressType;
+use crate::bs_signing::{recover_signer, verify_signature};
+
+/// What a signed input carries, checked against the key it should be signed
+/// by.
+#[derive(Debug)]
+pub struct InputSignature {
+    pub address_type: AddressType,
+    pub signature: bitcoin::ecdsa::Signature,
+    /// The key pushed next to the signature, which the spent output commits
+    /// to.
+    pub signing_key: bitcoin::PublicKey,
+    /// The key the signature recovers to over the recomputed sighash.
+    pub recovered_key: bitcoin::PublicKey,
+    /// The signature verifies against the expected key, which is also the one
+    /// the input carries.
+    pub valid: bool,
+}
+
+/// Checks the signature of a finalized p2pkh, p2wpkh or p2sh-p2wpkh input of
+/// `tx` against `expected`, without a PSBT or a ceremony. The spent script
+/// follows from the key the input carries, but a segwit sighash also commits
+/// to the `amount` the input spends.
+pub fn verify_tx_input(
+    tx: &Transaction,
+    index: usize,
+    expected: &bitcoin::PublicKey,
+    amount: Option<u64>,
+) -> Result<InputSignature> {
+    let (address_type, signature, key) = signature_and_key(tx, index)?;
+    let signature = bitcoin::ecdsa::Signature::from_slice(&signature).context(format!(
+        "input {} carries an invalid signature encoding",
+        index
+    ))?;
+    let signing_key = bitcoin::PublicKey::from_slice(&key)
+        .context(format!("input {} carries an invalid public key", index))?;
+
+    // p2wpkh signs the p2pkh script of its key, as p2pkh itself does.
+    let script_code = ScriptBuf::new_p2pkh(&signing_key.pubkey_hash());
+    let mut cache = SighashCache::new(tx);
+    let digest = match address_type {
+        AddressType::P2pkh => cache
+            .legacy_signature_hash(index, &script_code, signature.hash_ty.to_u32())?
+            .to_byte_array(),
+        _ => {
+            let amount = amount.context(format!(
+                "input {} is segwit, its sighash commits to the amount it spends: pass --amount",
+                index
+            ))?;
+            cache
+                .segwit_signature_hash(index, &script_code, amount, signature.hash_ty)?
+                .to_byte_array()
+        }
+    };
+
+    Ok(InputSignature {
+        address_type,
+        recovered_key: recover_signer(&signature, &digest, expected)?,
+        valid: signing_key.inner == expected.inner
+            && verify_signature(&signature, &digest, expected).is_ok(),
+        signature,
+        signing_key,
+    })
+}
+
+/// The signature and key of the input, told apart by where they are.
+fn signature_and_key(tx: &Transaction, index: usize) -> Result<(AddressType, Vec<u8>, Vec<u8>)> {
+    let input = tx
+        .input
+        .get(index)
+        .context(format!("transaction has no input {}", index))?;
+    let pushes = input
+        .script_sig
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Ok(bytes.as_bytes().to_vec()),
+            _ => bail!("input {} scriptSig is not only pushes", index),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let witness: Vec<Vec<u8>> = input.witness.iter().map(<[u8]>::to_vec).collect();
+
+    Ok(match (pushes.as_slice(), witness.as_slice()) {
+        ([signature, key], []) => (AddressType::P2pkh, signature.clone(), key.clone()),
+        ([], [signature, key]) => (AddressType::P2wpkh, signature.clone(), key.clone()),
+        // The pushed redeem script is the 22 byte p2wpkh program.
+        ([redeem_script], [signature, key]) if redeem_script.len() == 22 => {
+            (AddressType::P2shP2wpkh, signature.clone(), key.clone())
+        }
+        _ => bail!(
+            "input {} is not a signed p2pkh, p2wpkh or p2sh-p2wpkh input",
+            index
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bs_signing::tests::psbt_spending;
+    use crate::bs_signing::{extract_signed_tx, finalize_tx, input_sighash, prepare_input};
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::sighash::EcdsaSighashType;
+
+    fn signed_tx(secret_key: &SecretKey, address_type: AddressType) -> Transaction {
+        let secp = Secp256k1::new();
+        let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp));
+        let address = address_type
+            .deriver()
+            .derive(&public_key, bitcoin::Network::Signet)
+            .unwrap();
+        let mut psbt = psbt_spending(address.script_pubkey());
+        prepare_input(
+            &mut psbt,
+            0,
+            &public_key,
+            address_type,
+            EcdsaSighashType::All,
+        )
+        .unwrap();
+        let digest = input_sighash(&psbt, 0).unwrap();
+        let sig = secp.sign_ecdsa(&Message::from_slice(&digest).unwrap(), secret_key);
+        let signature = bitcoin::ecdsa::Signature::sighash_all(sig);
+        let psbt = finalize_tx(psbt, 0, &public_key, address_type, signature).unwrap();
+        extract_signed_tx(psbt).unwrap()
+    }
+
+    #[test]
+    fn signed_inputs_verify_against_their_key() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp));
+        let stranger =
+            bitcoin::PublicKey::new(SecretKey::from_slice(&[2; 32]).unwrap().public_key(&secp));
+
+        for address_type in [
+            AddressType::P2pkh,
+            AddressType::P2wpkh,
+            AddressType::P2shP2wpkh,
+        ] {
+            let tx = signed_tx(&secret_key, address_type);
+            let checked = verify_tx_input(&tx, 0, &public_key, Some(1000)).unwrap();
+            assert_eq!(checked.address_type, address_type);
+            assert!(checked.valid);
+            assert_eq!(checked.recovered_key, public_key);
+
+            assert!(
+                !verify_tx_input(&tx, 0, &stranger, Some(1000))
+                    .unwrap()
+                    .valid
+            );
+            assert!(verify_tx_input(&tx, 1, &public_key, Some(1000)).is_err());
+            if address_type != AddressType::P2pkh {
+                // The amount is signed too, another one breaks the signature.
+                let checked = verify_tx_input(&tx, 0, &public_key, Some(999)).unwrap();
+                assert!(!checked.valid);
+                assert_ne!(checked.recovered_key, public_key);
+                assert!(verify_tx_input(&tx, 0, &public_key, None).is_err());
+            }
+        }
+    }
+}